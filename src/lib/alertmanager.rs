@@ -0,0 +1,475 @@
+use super::containers::Severity;
+use super::notifier::{Notification, Notifier};
+use super::swarm::UNDER_REPLICATED;
+use super::webhook::Webhook;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+const STOPPED: &str = "stopped";
+/// How long the alerts last when they are not pushed again, without an interval to base it on.
+pub const DEFAULT_ENDS_AFTER: Duration = Duration::from_secs(60 * 60);
+
+#[derive(clap::Args, Debug, PartialEq, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AlertmanagerSettings {
+    #[clap(short, long, help = "Alertmanager url, e.g. http://alertmanager:9093")]
+    #[serde(deserialize_with = "super::config::deserialize_url")]
+    pub url: String,
+    #[clap(
+        long,
+        help = "How long the alerts last when they are not pushed again (e.g. 15m), 3 times the interval by default, or 1h without one",
+        value_parser = humantime::parse_duration
+    )]
+    #[serde(default, with = "humantime_serde")]
+    pub ends_after: Option<Duration>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Alert {
+    pub labels: BTreeMap<String, String>,
+    pub annotations: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ends_at: Option<String>,
+}
+
+/// Pushes every container with problems on each check, with an `endsAt` after the next checks, so the alerts last
+/// while they are pushed again and Alertmanager resolves the ones that are not. It gets the full status instead of only
+/// what changed.
+pub struct AlertmanagerNotifier {
+    name: String,
+    url: String,
+    ends_after: Duration,
+    webhook: Webhook,
+}
+
+impl AlertmanagerNotifier {
+    pub fn new(name: String, url: &str, ends_after: Duration) -> Self {
+        AlertmanagerNotifier {
+            name,
+            url: format!("{}/api/v2/alerts", url.trim_end_matches('/')),
+            ends_after,
+            webhook: Webhook::default(),
+        }
+    }
+}
+
+impl Notifier for AlertmanagerNotifier {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    fn notify(&self, notification: &Notification) -> Result<(), Box<dyn std::error::Error>> {
+        if notification.is_empty() {
+            return Ok(());
+        }
+        let alerts = alerts(notification, SystemTime::now(), self.ends_after);
+        info!("Alerts to be sent: {:?}", alerts);
+        self.webhook.post(&self.url, serde_json::to_vec(&alerts)?)
+    }
+    fn only_changes(&self) -> bool {
+        false
+    }
+}
+
+/// The alerts of the containers with problems end after `ends_after`, unless they are pushed again, and the ones of
+/// the containers that recovered end now.
+fn alerts(notification: &Notification, now: SystemTime, ends_after: Duration) -> Vec<Alert> {
+    let Notification {
+        running_containers,
+        stopped_containers,
+        resolved_containers,
+        unreachable_hosts,
+        resolved_hosts,
+        services,
+        ..
+    } = notification;
+    let hostname = notification.hostname.as_deref();
+    let firing_ends_at = humantime::format_rfc3339_seconds(now + ends_after).to_string();
+    let mut alerts = vec![];
+    for container in running_containers {
+        let health = container
+            .health
+            .map(|health| health.to_string())
+            .unwrap_or_else(|| "none".to_owned());
+        alerts.push(Alert {
+            labels: labels(
+                &container.name,
+                container.host.as_deref().or(hostname),
+                &health,
+                container.severity,
+            ),
+            annotations: annotations(
+                format!("Container {} is {}", container.name, health_description(&health)),
+                format!("The container {} is running, with health: {health}.", container.name),
+            ),
+            ends_at: Some(firing_ends_at.clone()),
+        });
+    }
+    for container in stopped_containers {
+        let status = container.status.clone().unwrap_or_else(|| "no status".to_owned());
+        alerts.push(Alert {
+            labels: labels(
+                &container.name,
+                container.host.as_deref().or(hostname),
+                STOPPED,
+                container.severity,
+            ),
+            annotations: annotations(
+                format!("Container {} is not running", container.name),
+                format!(
+                    "The container {} is not running, with status: {status}.",
+                    container.name
+                ),
+            ),
+            ends_at: Some(firing_ends_at.clone()),
+        });
+    }
+    for service in services {
+        alerts.push(Alert {
+            labels: labels(
+                &service.name,
+                service.host.as_deref().or(hostname),
+                UNDER_REPLICATED,
+                None,
+            ),
+            annotations: annotations(
+                format!("Service {} is missing replicas", service.name),
+                format!("The service {} has {}.", service.name, service.summary()),
+            ),
+            ends_at: Some(firing_ends_at.clone()),
+        });
+    }
+    for container in resolved_containers {
+        let down_for = humantime::format_duration(Duration::from_secs(container.down_for_seconds));
+        alerts.push(Alert {
+            labels: labels(
+                &container.name,
+                container.host.as_deref().or(hostname),
+                &container.previous_status,
+                container.severity,
+            ),
+            annotations: if container.previous_status == UNDER_REPLICATED {
+                annotations(
+                    format!("Service {} has recovered", container.name),
+                    format!("The service {} was missing replicas for {down_for}.", container.name),
+                )
+            } else {
+                annotations(
+                    format!("Container {} has recovered", container.name),
+                    format!(
+                        "The container {} was {} for {down_for}.",
+                        container.name,
+                        health_description(&container.previous_status)
+                    ),
+                )
+            },
+            ends_at: Some(humantime::format_rfc3339_seconds(now).to_string()),
+        });
+    }
+    for host in unreachable_hosts {
+        alerts.push(Alert {
+            labels: host_labels(&host.host),
+            annotations: annotations(
+                format!("Docker host {} could not be checked", host.host),
+                format!("The containers of {} could not be checked: {}.", host.host, host.error),
+            ),
+            ends_at: Some(firing_ends_at.clone()),
+        });
+    }
+    for host in resolved_hosts {
+        let down_for = humantime::format_duration(Duration::from_secs(host.down_for_seconds));
+        alerts.push(Alert {
+            labels: host_labels(&host.host),
+            annotations: annotations(
+                format!("Docker host {} can be checked again", host.host),
+                format!("The containers of {} could not be checked for {down_for}.", host.host),
+            ),
+            ends_at: Some(humantime::format_rfc3339_seconds(now).to_string()),
+        });
+    }
+    alerts
+}
+
+fn host_labels(host: &str) -> BTreeMap<String, String> {
+    let mut labels = BTreeMap::new();
+    labels.insert("alertname".to_owned(), "DockerHostUnreachable".to_owned());
+    labels.insert("host".to_owned(), host.to_owned());
+    labels
+}
+
+/// The labels identify the alert, so they are built from what is kept in the state (the health, `stopped`, or
+/// `under-replicated` for the services, and the severity of the container's label), and the alerts sent when the
+/// containers recover end the same alerts that were fired.
+fn labels(
+    container_name: &str,
+    hostname: Option<&str>,
+    status: &str,
+    severity: Option<Severity>,
+) -> BTreeMap<String, String> {
+    let mut labels = BTreeMap::new();
+    if let Some(hostname) = hostname {
+        labels.insert("host".to_owned(), hostname.to_owned());
+    }
+    if let Some(severity) = severity {
+        labels.insert("severity".to_owned(), severity.to_string());
+    }
+    if status == UNDER_REPLICATED {
+        labels.insert("alertname".to_owned(), "ServiceUnderReplicated".to_owned());
+        labels.insert("service".to_owned(), container_name.to_owned());
+        return labels;
+    }
+    labels.insert("container".to_owned(), container_name.to_owned());
+    if status == STOPPED {
+        labels.insert("alertname".to_owned(), "ContainerNotRunning".to_owned());
+        labels.insert("status".to_owned(), STOPPED.to_owned());
+    } else {
+        labels.insert("alertname".to_owned(), "ContainerNotHealthy".to_owned());
+        labels.insert("status".to_owned(), "running".to_owned());
+        labels.insert("health".to_owned(), status.to_owned());
+    }
+    labels
+}
+
+fn annotations(summary: String, description: String) -> BTreeMap<String, String> {
+    let mut annotations = BTreeMap::new();
+    annotations.insert("summary".to_owned(), summary);
+    annotations.insert("description".to_owned(), description);
+    annotations
+}
+
+fn health_description(health: &str) -> String {
+    if health == "none" {
+        "running without health status".to_owned()
+    } else {
+        health.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::containers::{
+        ResolvedContainerStatus, ResolvedHost, RunningContainerStatus, StoppedContainerStatus, UnreachableHost,
+    };
+    use crate::swarm::ServiceStatus;
+    use bollard::models::HealthStatusEnum;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn check_alerts() {
+        let formatted_alerts = alerts(
+            &Notification {
+                running_containers: vec![RunningContainerStatus {
+                    name: "test1".to_string(),
+                    health: Some(HealthStatusEnum::UNHEALTHY),
+                    ..Default::default()
+                }],
+                stopped_containers: vec![StoppedContainerStatus {
+                    name: "test2".to_string(),
+                    status: Some("exited".to_string()),
+                    ..Default::default()
+                }],
+                resolved_containers: vec![
+                    ResolvedContainerStatus {
+                        name: "test3".to_string(),
+                        previous_status: "stopped".to_string(),
+                        down_for_seconds: 60,
+                        ..Default::default()
+                    },
+                    ResolvedContainerStatus {
+                        name: "test4".to_string(),
+                        previous_status: "none".to_string(),
+                        down_for_seconds: 120,
+                        ..Default::default()
+                    },
+                ],
+                hostname: Some("myhostname".to_owned()),
+                ..Default::default()
+            },
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            Duration::from_secs(15 * 60),
+        );
+        assert_eq!(
+            serde_json::to_value(formatted_alerts).unwrap(),
+            json!([
+                {
+                    "labels": {"alertname": "ContainerNotHealthy", "container": "test1", "host": "myhostname", "status": "running", "health": "unhealthy"},
+                    "annotations": {"summary": "Container test1 is unhealthy", "description": "The container test1 is running, with health: unhealthy."},
+                    "endsAt": "2023-11-14T22:28:20Z"
+                },
+                {
+                    "labels": {"alertname": "ContainerNotRunning", "container": "test2", "host": "myhostname", "status": "stopped"},
+                    "annotations": {"summary": "Container test2 is not running", "description": "The container test2 is not running, with status: exited."},
+                    "endsAt": "2023-11-14T22:28:20Z"
+                },
+                {
+                    "labels": {"alertname": "ContainerNotRunning", "container": "test3", "host": "myhostname", "status": "stopped"},
+                    "annotations": {"summary": "Container test3 has recovered", "description": "The container test3 was stopped for 1m."},
+                    "endsAt": "2023-11-14T22:13:20Z"
+                },
+                {
+                    "labels": {"alertname": "ContainerNotHealthy", "container": "test4", "host": "myhostname", "status": "running", "health": "none"},
+                    "annotations": {"summary": "Container test4 has recovered", "description": "The container test4 was running without health status for 2m."},
+                    "endsAt": "2023-11-14T22:13:20Z"
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn resolved_alert_has_the_labels_of_the_fired_alert() {
+        let fired = alerts(
+            &Notification {
+                running_containers: vec![RunningContainerStatus {
+                    name: "test1".to_string(),
+                    health: None,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            SystemTime::now(),
+            DEFAULT_ENDS_AFTER,
+        );
+        let resolved = alerts(
+            &Notification {
+                resolved_containers: vec![ResolvedContainerStatus {
+                    name: "test1".to_string(),
+                    previous_status: "none".to_string(),
+                    down_for_seconds: 60,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            SystemTime::now(),
+            DEFAULT_ENDS_AFTER,
+        );
+        assert_eq!(fired[0].labels, resolved[0].labels);
+    }
+
+    #[test]
+    fn severity_label_is_an_alert_label() {
+        let fired = alerts(
+            &Notification {
+                stopped_containers: vec![StoppedContainerStatus {
+                    name: "test1".to_string(),
+                    severity: Some(Severity::Warning),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            SystemTime::now(),
+            DEFAULT_ENDS_AFTER,
+        );
+        assert_eq!(
+            serde_json::to_value(&fired[0].labels).unwrap(),
+            json!({"alertname": "ContainerNotRunning", "container": "test1", "severity": "warning", "status": "stopped"})
+        );
+        let resolved = alerts(
+            &Notification {
+                resolved_containers: vec![ResolvedContainerStatus {
+                    name: "test1".to_string(),
+                    previous_status: "stopped".to_string(),
+                    severity: Some(Severity::Warning),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            SystemTime::now(),
+            DEFAULT_ENDS_AFTER,
+        );
+        assert_eq!(fired[0].labels, resolved[0].labels);
+    }
+
+    #[test]
+    fn containers_have_their_own_host_and_unreachable_hosts_are_alerts() {
+        let formatted_alerts = alerts(
+            &Notification {
+                stopped_containers: vec![StoppedContainerStatus {
+                    name: "test1".to_string(),
+                    status: Some("exited".to_string()),
+                    host: Some("host1".to_string()),
+                    ..Default::default()
+                }],
+                unreachable_hosts: vec![UnreachableHost {
+                    host: "host2".to_string(),
+                    error: "connection refused".to_string(),
+                }],
+                resolved_hosts: vec![ResolvedHost {
+                    host: "host3".to_string(),
+                    down_for_seconds: 60,
+                }],
+                hostname: Some("monitoring".to_owned()),
+                ..Default::default()
+            },
+            UNIX_EPOCH,
+            DEFAULT_ENDS_AFTER,
+        );
+        assert_eq!(
+            serde_json::to_value(formatted_alerts).unwrap(),
+            json!([
+                {
+                    "labels": {"alertname": "ContainerNotRunning", "container": "test1", "host": "host1", "status": "stopped"},
+                    "annotations": {"summary": "Container test1 is not running", "description": "The container test1 is not running, with status: exited."},
+                    "endsAt": "1970-01-01T01:00:00Z"
+                },
+                {
+                    "labels": {"alertname": "DockerHostUnreachable", "host": "host2"},
+                    "annotations": {"summary": "Docker host host2 could not be checked", "description": "The containers of host2 could not be checked: connection refused."},
+                    "endsAt": "1970-01-01T01:00:00Z"
+                },
+                {
+                    "labels": {"alertname": "DockerHostUnreachable", "host": "host3"},
+                    "annotations": {"summary": "Docker host host3 can be checked again", "description": "The containers of host3 could not be checked for 1m."},
+                    "endsAt": "1970-01-01T00:00:00Z"
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn resolved_service_alert_has_the_labels_of_the_fired_alert() {
+        let fired = alerts(
+            &Notification {
+                services: vec![ServiceStatus {
+                    name: "web".to_string(),
+                    desired_replicas: 3,
+                    running_replicas: 1,
+                    task_state: Some("rejected".to_string()),
+                    task_error: Some("no suitable node".to_string()),
+                    host: None,
+                }],
+                hostname: Some("manager1".to_owned()),
+                ..Default::default()
+            },
+            UNIX_EPOCH,
+            DEFAULT_ENDS_AFTER,
+        );
+        assert_eq!(
+            serde_json::to_value(&fired).unwrap(),
+            json!([{
+                "labels": {"alertname": "ServiceUnderReplicated", "service": "web", "host": "manager1"},
+                "annotations": {"summary": "Service web is missing replicas", "description": "The service web has 1/3 replicas running, rejected: no suitable node."},
+                "endsAt": "1970-01-01T01:00:00Z"
+            }])
+        );
+        let resolved = alerts(
+            &Notification {
+                resolved_containers: vec![ResolvedContainerStatus {
+                    name: "web".to_string(),
+                    previous_status: UNDER_REPLICATED.to_string(),
+                    down_for_seconds: 60,
+                    ..Default::default()
+                }],
+                hostname: Some("manager1".to_owned()),
+                ..Default::default()
+            },
+            SystemTime::now(),
+            DEFAULT_ENDS_AFTER,
+        );
+        assert_eq!(fired[0].labels, resolved[0].labels);
+    }
+}
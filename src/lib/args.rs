@@ -1,4 +1,14 @@
+use super::alertmanager::AlertmanagerSettings;
+use super::email::EmailSettings;
+use super::nagios::CheckSettings;
+use super::opsgenie::OpsgenieSettings;
+use super::pagerduty::PagerDutySettings;
+use super::print::OutputFormat;
+use clap::builder::BoolishValueParser;
 use clap::{Parser, Subcommand};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Debug, Parser)]
 #[clap(author = "Giovanni Bassi <giggio@giggio.net>", version = env!("CARGO_PKG_VERSION"), about = "Checks containers status and notifies problems", long_about = None)]
@@ -6,17 +16,110 @@ pub struct Args {
     #[clap(
         short,
         long,
-        help = "Container label to check for stopped containers",
-        required = true
+        help = "Configuration file (TOML or YAML), command line options and environment variables override it",
+        env = "NOTIFYHEALTH_CONFIG"
     )]
-    pub label: String,
-    #[clap(short, long, help = "Host name of docker host")]
+    pub config: Option<PathBuf>,
+    #[clap(
+        short,
+        long,
+        help = "Labels of the containers to check, e.g. `notifyhealth,env=prod,!notifyhealth.ignore|critical`: commas separate the labels that must all match, `|` the alternatives and `!` negates a label",
+        env = "NOTIFYHEALTH_LABEL"
+    )]
+    pub label: Option<String>,
+    #[clap(short, long, help = "Host name of docker host", env = "NOTIFYHEALTH_HOSTNAME")]
     pub hostname: Option<String>,
-    #[clap(short, long, help = "Include running containers which have no health check")]
-    pub report_no_health: bool,
+    #[clap(
+        short,
+        long,
+        value_name = "BOOL",
+        value_parser = BoolishValueParser::new(),
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "true",
+        help = "Include running containers which have no health check, `--report-no-health=false` turns it off when the config file sets it",
+        env = "NOTIFYHEALTH_REPORT_NO_HEALTH"
+    )]
+    pub report_no_health: Option<bool>,
+    #[clap(
+        long,
+        value_name = "BOOL",
+        value_parser = BoolishValueParser::new(),
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "true",
+        help = "Checks the Swarm services with the label for missing replicas, instead of the stopped containers, `--swarm=false` turns it off when the config file sets it",
+        env = "NOTIFYHEALTH_SWARM"
+    )]
+    pub swarm: Option<bool>,
+    #[clap(
+        long,
+        help = "Keeps running, checking the containers again after this interval (e.g. 30s, 5m, 1h)",
+        value_parser = humantime::parse_duration,
+        env = "NOTIFYHEALTH_INTERVAL"
+    )]
+    pub interval: Option<Duration>,
+    #[clap(
+        long,
+        value_name = "BOOL",
+        value_parser = BoolishValueParser::new(),
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "true",
+        help = "Keeps running, listening to Docker events to notify as soon as a container becomes unhealthy or stops, `--events=false` turns it off when the config file sets it",
+        env = "NOTIFYHEALTH_EVENTS"
+    )]
+    pub events: Option<bool>,
+    #[clap(
+        long,
+        help = "Directory where the last seen status of the containers is kept, so only changes are notified",
+        env = "NOTIFYHEALTH_STATE_DIR"
+    )]
+    pub state_dir: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Notifies again about containers that are still with problems after this interval (e.g. 12h), needs a state directory",
+        value_parser = humantime::parse_duration,
+        env = "NOTIFYHEALTH_RENOTIFY_INTERVAL"
+    )]
+    pub renotify_interval: Option<Duration>,
+    #[clap(
+        long,
+        value_name = "BOOL",
+        value_parser = BoolishValueParser::new(),
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "true",
+        help = "Exits with 1 when there are unhealthy containers (or without health status, with --report-no-health), 2 when there are stopped containers and 3 when Docker can't be reached, `--exit-code=false` turns it off when the config file sets it",
+        env = "NOTIFYHEALTH_EXIT_CODE"
+    )]
+    pub exit_code: Option<bool>,
+    #[clap(
+        long,
+        help = "Docker daemon to check, as a unix://, tcp:// or ssh:// url, defaults to the local socket",
+        env = "DOCKER_HOST"
+    )]
+    pub docker_host: Option<String>,
+    #[clap(
+        long,
+        help = "Directory with the ca.pem, cert.pem and key.pem TLS files for a tcp:// Docker host, defaults to ~/.docker",
+        env = "DOCKER_CERT_PATH"
+    )]
+    pub docker_cert_path: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "BOOL",
+        value_parser = parse_docker_tls_verify,
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "true",
+        help = "Connects to a tcp:// Docker host with TLS, verifying the daemon with the CA and authenticating with the client certificate, `--docker-tls-verify=false` turns it off when the config file sets it",
+        env = "DOCKER_TLS_VERIFY"
+    )]
+    pub docker_tls_verify: Option<bool>,
 
     #[clap(subcommand)]
-    pub command: Command,
+    pub command: Option<Command>,
 
     #[clap(flatten)]
     pub verbose: clap_verbosity_flag::Verbosity,
@@ -25,17 +128,87 @@ pub struct Args {
 #[derive(Subcommand, Debug)]
 pub enum Command {
     #[clap(about = "Prints the status to stdout.")]
-    Print {},
+    Print {
+        #[clap(short, long, value_enum, default_value_t, help = "Output format")]
+        output: OutputFormat,
+    },
+    #[clap(about = "Checks the containers as a Nagios or Icinga plugin, with the status in the exit code")]
+    Check(CheckSettings),
+    #[clap(about = "Writes the status as metrics for the node_exporter textfile collector")]
+    WriteTextfile {
+        #[clap(
+            short,
+            long,
+            help = "Directory of the textfile collector, where notifyhealth.prom is written"
+        )]
+        directory: PathBuf,
+    },
     #[clap(about = "Sends a notification through Microsoft Teams")]
     NotifyTeams {
         #[clap(short, long, help = "Teams callback url")]
         callback_url: String,
     },
+    #[clap(about = "Sends a notification through a Slack incoming webhook")]
+    NotifySlack {
+        #[clap(short, long, help = "Slack incoming webhook url")]
+        callback_url: String,
+    },
+    #[clap(about = "Sends a notification through a Discord webhook")]
+    NotifyDiscord {
+        #[clap(short, long, help = "Discord webhook url")]
+        callback_url: String,
+    },
+    #[clap(about = "Sends a notification by email, through an SMTP server")]
+    NotifyEmail(EmailSettings),
+    #[clap(about = "Triggers and resolves PagerDuty incidents through the Events API v2")]
+    NotifyPagerduty(PagerDutySettings),
+    #[clap(about = "Creates and closes Opsgenie alerts")]
+    NotifyOpsgenie(OpsgenieSettings),
+    #[clap(about = "Pushes alerts to a Prometheus Alertmanager")]
+    NotifyAlertmanager(AlertmanagerSettings),
     #[clap(about = "Sends a notification through a webhook")]
     NotifyWebhook {
         #[clap(short, long, help = "Webhook url")]
         callback_url: String,
     },
+    #[clap(about = "Sends the same notification through several targets")]
+    Notify {
+        #[clap(long, help = "Teams callback url, can be repeated")]
+        teams_url: Vec<String>,
+        #[clap(long, help = "Slack incoming webhook url, can be repeated")]
+        slack_url: Vec<String>,
+        #[clap(long, help = "Discord webhook url, can be repeated")]
+        discord_url: Vec<String>,
+        #[clap(long, help = "Alertmanager url, can be repeated")]
+        alertmanager_url: Vec<String>,
+        #[clap(long, help = "Webhook url, can be repeated")]
+        webhook_url: Vec<String>,
+        #[clap(long, help = "Also prints the status to stdout")]
+        print: bool,
+    },
+    #[clap(
+        about = "Keeps running and serves Prometheus metrics of the containers, notifying the targets from the configuration file"
+    )]
+    ServeMetrics {
+        #[clap(
+            long,
+            default_value = "0.0.0.0:9393",
+            help = "Address the metrics are served on, at /metrics",
+            env = "NOTIFYHEALTH_METRICS_LISTEN"
+        )]
+        listen: SocketAddr,
+    },
+    #[clap(about = "Works with the configuration file")]
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    #[clap(about = "Validates the configuration file, reporting the line and field of the errors")]
+    Validate {},
 }
 
 impl Args {
@@ -43,7 +216,7 @@ impl Args {
         Args::parse()
     }
     #[allow(dead_code)]
-    fn new_from<I, T>(args: I) -> Args
+    pub(crate) fn new_from<I, T>(args: I) -> Args
     where
         I: Iterator<Item = T>,
         T: Into<std::ffi::OsString> + Clone,
@@ -58,18 +231,231 @@ impl Default for Args {
     }
 }
 
+/// Like the Docker CLI, an empty `DOCKER_TLS_VERIFY` turns TLS off instead of being an invalid value.
+fn parse_docker_tls_verify(value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "" | "n" | "no" | "f" | "false" | "off" | "0" => Ok(false),
+        "y" | "yes" | "t" | "true" | "on" | "1" => Ok(true),
+        _ => Err("value was not a boolean".to_owned()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::email::SmtpSecurity;
+    use crate::nagios::ProblemState;
 
     #[test]
     fn args_run_simulated() {
         let args = Args::new_from(["notifyhealth", "--label", "foo", "print"].iter());
         match args.command {
-            Command::NotifyTeams { .. } => panic!("Should not be notify teams"),
-            Command::NotifyWebhook { .. } => panic!("Should not notify webhook"),
-            Command::Print {} => (),
+            Some(Command::NotifyTeams { .. }) => panic!("Should not be notify teams"),
+            Some(Command::NotifyWebhook { .. }) => panic!("Should not notify webhook"),
+            Some(Command::NotifySlack { .. }) => panic!("Should not notify slack"),
+            Some(Command::NotifyDiscord { .. }) => panic!("Should not notify discord"),
+            Some(Command::NotifyEmail { .. }) => panic!("Should not notify email"),
+            Some(Command::NotifyPagerduty { .. }) => panic!("Should not notify pagerduty"),
+            Some(Command::NotifyOpsgenie { .. }) => panic!("Should not notify opsgenie"),
+            Some(Command::NotifyAlertmanager { .. }) => panic!("Should not notify alertmanager"),
+            Some(Command::Notify { .. }) => panic!("Should not notify several targets"),
+            Some(Command::ServeMetrics { .. }) => panic!("Should not serve metrics"),
+            Some(Command::WriteTextfile { .. }) => panic!("Should not write textfile"),
+            Some(Command::Check { .. }) => panic!("Should not check"),
+            Some(Command::Config { .. }) => panic!("Should not be config"),
+            None => panic!("Should have a command"),
+            Some(Command::Print { output }) => assert_eq!(OutputFormat::Text, output),
         };
-        assert_eq!("foo", args.label);
+        assert_eq!(Some("foo".to_owned()), args.label);
+        assert_eq!(None, args.interval);
+    }
+
+    #[test]
+    fn args_with_boolean_flags() {
+        let args = Args::new_from(["notifyhealth", "--label", "foo", "--events", "-r", "print"].iter());
+        assert_eq!(Some(true), args.events);
+        assert_eq!(Some(true), args.report_no_health);
+        let turned_off = Args::new_from(["notifyhealth", "--label", "foo", "--events=false", "print"].iter());
+        assert_eq!(Some(false), turned_off.events);
+        assert_eq!(None, turned_off.report_no_health);
+        let swarm_flags = Args::new_from(
+            [
+                "notifyhealth",
+                "--label",
+                "foo",
+                "--swarm",
+                "--exit-code=false",
+                "--docker-tls-verify=no",
+                "print",
+            ]
+            .iter(),
+        );
+        assert_eq!(Some(true), swarm_flags.swarm);
+        assert_eq!(Some(false), swarm_flags.exit_code);
+        assert_eq!(Some(false), swarm_flags.docker_tls_verify);
+    }
+
+    #[test]
+    fn empty_docker_tls_verify_is_off() {
+        assert_eq!(Ok(false), parse_docker_tls_verify(""));
+        assert_eq!(Ok(false), parse_docker_tls_verify("0"));
+        assert_eq!(Ok(true), parse_docker_tls_verify("1"));
+        assert_eq!(Ok(true), parse_docker_tls_verify("True"));
+        assert!(parse_docker_tls_verify("maybe").is_err());
+    }
+
+    #[test]
+    fn args_with_interval() {
+        let args = Args::new_from(["notifyhealth", "--label", "foo", "--interval", "5m", "print"].iter());
+        assert_eq!(Some(Duration::from_secs(300)), args.interval);
+    }
+
+    #[test]
+    fn args_with_state_dir() {
+        let args = Args::new_from(
+            [
+                "notifyhealth",
+                "--label",
+                "foo",
+                "--state-dir",
+                "/tmp/notifyhealth",
+                "--renotify-interval",
+                "1h",
+                "print",
+            ]
+            .iter(),
+        );
+        assert_eq!(Some(PathBuf::from("/tmp/notifyhealth")), args.state_dir);
+        assert_eq!(Some(Duration::from_secs(3600)), args.renotify_interval);
+    }
+
+    #[test]
+    fn args_serve_metrics() {
+        let args = Args::new_from(
+            [
+                "notifyhealth",
+                "--label",
+                "foo",
+                "serve-metrics",
+                "--listen",
+                "127.0.0.1:9000",
+            ]
+            .iter(),
+        );
+        assert!(matches!(
+            args.command,
+            Some(Command::ServeMetrics { listen }) if listen == "127.0.0.1:9000".parse().unwrap()
+        ));
+    }
+
+    #[test]
+    fn args_check() {
+        let args = Args::new_from(
+            [
+                "notifyhealth",
+                "--label",
+                "foo",
+                "check",
+                "--warning",
+                "unhealthy,starting",
+            ]
+            .iter(),
+        );
+        match args.command {
+            Some(Command::Check(check_settings)) => {
+                assert_eq!(
+                    vec![
+                        ProblemState::Stopped,
+                        ProblemState::Unhealthy,
+                        ProblemState::UnderReplicated
+                    ],
+                    check_settings.critical
+                );
+                assert_eq!(
+                    vec![ProblemState::Unhealthy, ProblemState::Starting],
+                    check_settings.warning
+                );
+            }
+            _ => panic!("Should check"),
+        }
+    }
+
+    #[test]
+    fn args_config_validate() {
+        let args = Args::new_from(["notifyhealth", "--config", "notifyhealth.toml", "config", "validate"].iter());
+        assert_eq!(Some(PathBuf::from("notifyhealth.toml")), args.config);
+        assert!(matches!(
+            args.command,
+            Some(Command::Config {
+                command: ConfigCommand::Validate {}
+            })
+        ));
+    }
+
+    #[test]
+    fn args_notify_several_targets() {
+        let args = Args::new_from(
+            [
+                "notifyhealth",
+                "--label",
+                "foo",
+                "notify",
+                "--teams-url",
+                "https://example.com/teams",
+                "--webhook-url",
+                "https://example.com/hook1",
+                "--webhook-url",
+                "https://example.com/hook2",
+                "--print",
+            ]
+            .iter(),
+        );
+        match args.command {
+            Some(Command::Notify {
+                teams_url,
+                webhook_url,
+                print,
+                ..
+            }) => {
+                assert_eq!(vec!["https://example.com/teams"], teams_url);
+                assert_eq!(
+                    vec!["https://example.com/hook1", "https://example.com/hook2"],
+                    webhook_url
+                );
+                assert!(print);
+            }
+            _ => panic!("Should notify several targets"),
+        }
+    }
+
+    #[test]
+    fn args_notify_email() {
+        let args = Args::new_from(
+            [
+                "notifyhealth",
+                "--label",
+                "foo",
+                "notify-email",
+                "--smtp-host",
+                "smtp.example.com",
+                "--smtp-security",
+                "tls",
+                "--from",
+                "notifyhealth@example.com",
+                "--to",
+                "a@example.com",
+                "--to",
+                "b@example.com",
+            ]
+            .iter(),
+        );
+        match args.command {
+            Some(Command::NotifyEmail(email_settings)) => {
+                assert_eq!("smtp.example.com", email_settings.smtp_host);
+                assert_eq!(SmtpSecurity::Tls, email_settings.smtp_security);
+                assert_eq!(vec!["a@example.com", "b@example.com"], email_settings.to);
+            }
+            _ => panic!("Should notify email"),
+        }
     }
 }
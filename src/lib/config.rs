@@ -0,0 +1,998 @@
+use super::alertmanager::AlertmanagerSettings;
+use super::args::{Args, Command};
+use super::docker::DockerHost;
+use super::email::EmailSettings;
+use super::opsgenie::OpsgenieSettings;
+use super::pagerduty::PagerDutySettings;
+use super::print::OutputFormat;
+use super::selector::LabelSelector;
+use isahc::http::Uri;
+use log::*;
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{self, DeserializeSeed, IntoDeserializer, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, PartialEq, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub label: Option<String>,
+    pub hostname: Option<String>,
+    pub report_no_health: Option<bool>,
+    pub swarm: Option<bool>,
+    #[serde(default, with = "humantime_serde")]
+    pub interval: Option<Duration>,
+    pub events: Option<bool>,
+    pub state_dir: Option<PathBuf>,
+    #[serde(default, with = "humantime_serde")]
+    pub renotify_interval: Option<Duration>,
+    pub exit_code: Option<bool>,
+    pub docker_host: Option<String>,
+    pub docker_cert_path: Option<PathBuf>,
+    pub docker_tls_verify: Option<bool>,
+    #[serde(default)]
+    pub hosts: Vec<HostConfig>,
+    #[serde(default)]
+    pub targets: Vec<NamedTarget>,
+}
+
+/// One of several Docker hosts checked by the same instance. The label and the TLS settings default to the ones at
+/// the top of the file.
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HostConfig {
+    pub hostname: String,
+    pub docker_host: Option<String>,
+    pub docker_cert_path: Option<PathBuf>,
+    pub docker_tls_verify: Option<bool>,
+    pub label: Option<String>,
+}
+
+/// A target with the name that containers use in their `notifyhealth.target` label to be only sent to it. Without a
+/// name it is called by its type and position, e.g. `teams (target 1)`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct NamedTarget {
+    pub name: Option<String>,
+    pub target: Target,
+}
+
+impl From<Target> for NamedTarget {
+    fn from(target: Target) -> Self {
+        NamedTarget { name: None, target }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Target {
+    Print { output: OutputFormat },
+    Textfile { directory: PathBuf },
+    Teams { url: String },
+    Slack { url: String },
+    Discord { url: String },
+    Email(EmailSettings),
+    Pagerduty(PagerDutySettings),
+    Opsgenie(OpsgenieSettings),
+    Alertmanager(AlertmanagerSettings),
+    Webhook { url: String },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum TargetType {
+    Print,
+    Textfile,
+    Teams,
+    Slack,
+    Discord,
+    Email,
+    Pagerduty,
+    Opsgenie,
+    Alertmanager,
+    Webhook,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PrintTarget {
+    #[serde(default)]
+    output: OutputFormat,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TextfileTarget {
+    directory: PathBuf,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UrlTarget {
+    #[serde(deserialize_with = "deserialize_url")]
+    url: String,
+}
+
+/// The `type` of a target can be anywhere in it. The fields before it are buffered, the ones after it are not, as they
+/// would all be with `#[serde(flatten)]` or an internally tagged enum, so their errors have the line where they are.
+impl<'de> Deserialize<'de> for NamedTarget {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(NamedTargetVisitor)
+    }
+}
+
+struct NamedTargetVisitor;
+
+impl<'de> Visitor<'de> for NamedTargetVisitor {
+    type Value = NamedTarget;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a target with a type")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<NamedTarget, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut name = None;
+        let mut buffered = vec![];
+        let target_type = loop {
+            match map.next_key::<String>()? {
+                Some(key) if key == "name" => name = map.next_value()?,
+                Some(key) if key == "type" => break map.next_value::<TargetType>()?,
+                Some(key) => {
+                    let value = map.next_value::<serde_json::Value>()?;
+                    buffered.push((key, value));
+                }
+                None => return Err(de::Error::missing_field("type")),
+            }
+        };
+        let fields = MapAccessDeserializer::new(TargetFields {
+            buffered: buffered.into_iter(),
+            buffered_value: None,
+            map: &mut map,
+            name: &mut name,
+        });
+        let url = |url_fields| UrlTarget::deserialize(url_fields).map(|target| target.url);
+        let target = match target_type {
+            TargetType::Print => Target::Print {
+                output: PrintTarget::deserialize(fields)?.output,
+            },
+            TargetType::Textfile => Target::Textfile {
+                directory: TextfileTarget::deserialize(fields)?.directory,
+            },
+            TargetType::Teams => Target::Teams { url: url(fields)? },
+            TargetType::Slack => Target::Slack { url: url(fields)? },
+            TargetType::Discord => Target::Discord { url: url(fields)? },
+            TargetType::Email => Target::Email(EmailSettings::deserialize(fields)?),
+            TargetType::Pagerduty => Target::Pagerduty(PagerDutySettings::deserialize(fields)?),
+            TargetType::Opsgenie => Target::Opsgenie(OpsgenieSettings::deserialize(fields)?),
+            TargetType::Alertmanager => Target::Alertmanager(AlertmanagerSettings::deserialize(fields)?),
+            TargetType::Webhook => Target::Webhook { url: url(fields)? },
+        };
+        Ok(NamedTarget { name, target })
+    }
+}
+
+/// The fields of a target, the buffered ones that were before its `type` and then the ones after it, taking out its
+/// `name`.
+struct TargetFields<'a, A> {
+    buffered: std::vec::IntoIter<(String, serde_json::Value)>,
+    buffered_value: Option<(String, serde_json::Value)>,
+    map: &'a mut A,
+    name: &'a mut Option<String>,
+}
+
+impl<'de, A> MapAccess<'de> for TargetFields<'_, A>
+where
+    A: MapAccess<'de>,
+{
+    type Error = A::Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, A::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        if let Some((key, value)) = self.buffered.next() {
+            let key_value = seed.deserialize(key.as_str().into_deserializer()).map(Some);
+            self.buffered_value = Some((key, value));
+            return key_value;
+        }
+        loop {
+            match self.map.next_key::<String>()? {
+                Some(key) if key == "name" => *self.name = self.map.next_value()?,
+                Some(key) => return seed.deserialize(key.into_deserializer()).map(Some),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, A::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        match self.buffered_value.take() {
+            // The buffered value has lost its position, so the error at least names its field.
+            Some((key, value)) => seed
+                .deserialize(value)
+                .map_err(|err| de::Error::custom(format!("`{key}`: {err}"))),
+            None => self.map.next_value_seed(seed),
+        }
+    }
+}
+
+/// How often the containers are checked when serving metrics without an interval.
+const DEFAULT_METRICS_INTERVAL: Duration = Duration::from_secs(60);
+/// How many intervals the alerts pushed to Alertmanager last, so a late check doesn't resolve them.
+const ALERTMANAGER_ENDS_AFTER_INTERVALS: u32 = 3;
+
+/// A Docker host to check. `hostname` is only set when several hosts are checked, and is then shown with each
+/// container.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Host {
+    pub hostname: Option<String>,
+    pub docker_host: DockerHost,
+    pub label: LabelSelector,
+}
+
+/// The settings used for a run, from the command line and environment variables, falling back to the config file.
+#[derive(Debug, PartialEq)]
+pub struct Settings {
+    pub hosts: Vec<Host>,
+    pub hostname: Option<String>,
+    pub report_no_health: bool,
+    pub swarm: bool,
+    pub interval: Option<Duration>,
+    pub events: bool,
+    pub state_dir: Option<PathBuf>,
+    pub renotify_interval: Option<Duration>,
+    pub exit_code: bool,
+    pub metrics_listen: Option<SocketAddr>,
+    pub targets: Vec<NamedTarget>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, Box<dyn std::error::Error>> {
+        let text = fs::read_to_string(path).map_err(|err| format!("Could not read {}: {err}", path.display()))?;
+        let config = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("yaml") | Some("yml") => Config::from_yaml(&text),
+            _ => Config::from_toml(&text),
+        }
+        .map_err(|err| format!("Invalid configuration file {}: {err}", path.display()))?;
+        info!("Configuration loaded from {}: {:?}", path.display(), config);
+        Ok(config)
+    }
+
+    fn from_toml(text: &str) -> Result<Config, String> {
+        serde_path_to_error::deserialize(toml::Deserializer::new(text)).map_err(|err| {
+            let field = err.path().to_string();
+            let message = err.into_inner().to_string();
+            format!("field `{field}`: {}", message.trim_end())
+        })
+    }
+
+    fn from_yaml(text: &str) -> Result<Config, String> {
+        serde_path_to_error::deserialize(serde_yaml::Deserializer::from_str(text))
+            .map_err(|err| format!("field `{}`: {}", err.path(), err.inner()))
+    }
+}
+
+/// The targets of the notify commands, which have no name.
+fn command_targets(command: &Command) -> Vec<Target> {
+    match command {
+        Command::Print { output } => vec![Target::Print { output: *output }],
+        Command::WriteTextfile { directory } => vec![Target::Textfile {
+            directory: directory.clone(),
+        }],
+        Command::NotifyTeams { callback_url } => vec![Target::Teams {
+            url: callback_url.clone(),
+        }],
+        Command::NotifySlack { callback_url } => vec![Target::Slack {
+            url: callback_url.clone(),
+        }],
+        Command::NotifyDiscord { callback_url } => vec![Target::Discord {
+            url: callback_url.clone(),
+        }],
+        Command::NotifyEmail(email_settings) => vec![Target::Email(email_settings.clone())],
+        Command::NotifyPagerduty(pagerduty_settings) => vec![Target::Pagerduty(pagerduty_settings.clone())],
+        Command::NotifyOpsgenie(opsgenie_settings) => vec![Target::Opsgenie(opsgenie_settings.clone())],
+        Command::NotifyAlertmanager(alertmanager_settings) => vec![Target::Alertmanager(alertmanager_settings.clone())],
+        Command::NotifyWebhook { callback_url } => vec![Target::Webhook {
+            url: callback_url.clone(),
+        }],
+        Command::Notify {
+            teams_url,
+            slack_url,
+            discord_url,
+            alertmanager_url,
+            webhook_url,
+            print,
+        } => {
+            let mut targets = if *print {
+                vec![Target::Print {
+                    output: OutputFormat::Text,
+                }]
+            } else {
+                vec![]
+            };
+            targets.extend(teams_url.iter().map(|url| Target::Teams { url: url.clone() }));
+            targets.extend(slack_url.iter().map(|url| Target::Slack { url: url.clone() }));
+            targets.extend(discord_url.iter().map(|url| Target::Discord { url: url.clone() }));
+            targets.extend(alertmanager_url.iter().map(|url| {
+                Target::Alertmanager(AlertmanagerSettings {
+                    url: url.clone(),
+                    ends_after: None,
+                })
+            }));
+            targets.extend(webhook_url.iter().map(|url| Target::Webhook { url: url.clone() }));
+            targets
+        }
+        Command::Check(_) | Command::ServeMetrics { .. } | Command::Config { .. } => vec![],
+    }
+}
+
+impl Settings {
+    pub fn new(args: &Args, config: Option<Config>) -> Result<Settings, Box<dyn std::error::Error>> {
+        let config = config.unwrap_or_default();
+        let targets = match &args.command {
+            Some(Command::ServeMetrics { .. }) | Some(Command::Config { .. }) | None => config.targets,
+            Some(command) => command_targets(command).into_iter().map(NamedTarget::from).collect(),
+        };
+        let label = args.label.clone().or(config.label);
+        let docker_cert_path = args.docker_cert_path.clone().or(config.docker_cert_path);
+        let docker_tls_verify = args.docker_tls_verify.or(config.docker_tls_verify).unwrap_or(false);
+        let hosts = if config.hosts.is_empty() {
+            vec![Host {
+                hostname: None,
+                docker_host: DockerHost::new(
+                    args.docker_host.as_deref().or(config.docker_host.as_deref()),
+                    docker_cert_path,
+                    docker_tls_verify,
+                )?,
+                label: label
+                    .ok_or("A label is required, use --label or set `label` in the config file.")?
+                    .parse()?,
+            }]
+        } else {
+            let mut hosts: Vec<Host> = vec![];
+            for (index, host) in config.hosts.into_iter().enumerate() {
+                if hosts
+                    .iter()
+                    .any(|h| h.hostname.as_deref() == Some(host.hostname.as_str()))
+                {
+                    return Err(format!(
+                        "field `hosts[{index}].hostname`: the host {} is configured more than once.",
+                        host.hostname
+                    )
+                    .into());
+                }
+                // The label of the host, as the one at the top can come from --label.
+                let label_field = if host.label.is_some() {
+                    format!("field `hosts[{index}].label`: ")
+                } else {
+                    String::new()
+                };
+                hosts.push(Host {
+                    docker_host: DockerHost::new(
+                        host.docker_host.as_deref(),
+                        host.docker_cert_path.or_else(|| docker_cert_path.clone()),
+                        host.docker_tls_verify.unwrap_or(docker_tls_verify),
+                    )
+                    .map_err(|err| format!("field `hosts[{index}].docker_host`: {err} (host {})", host.hostname))?,
+                    label: host
+                        .label
+                        .or_else(|| label.clone())
+                        .ok_or_else(|| {
+                            format!(
+                                "field `hosts[{index}].label`: a label is required for the host {}, set it in the host or \
+                                 `label` at the top of the config file.",
+                                host.hostname
+                            )
+                        })?
+                        .parse::<LabelSelector>()
+                        .map_err(|err| format!("{label_field}{err} (host {})", host.hostname))?,
+                    hostname: Some(host.hostname),
+                });
+            }
+            hosts
+        };
+        let mut settings = Settings {
+            hosts,
+            hostname: args.hostname.clone().or(config.hostname),
+            report_no_health: args.report_no_health.or(config.report_no_health).unwrap_or(false),
+            swarm: args.swarm.or(config.swarm).unwrap_or(false),
+            interval: args.interval.or(config.interval),
+            events: args.events.or(config.events).unwrap_or(false),
+            state_dir: args.state_dir.clone().or(config.state_dir),
+            renotify_interval: args.renotify_interval.or(config.renotify_interval),
+            exit_code: args.exit_code.or(config.exit_code).unwrap_or(false),
+            metrics_listen: match &args.command {
+                Some(Command::ServeMetrics { listen }) => Some(*listen),
+                _ => None,
+            },
+            targets,
+        };
+        if settings.metrics_listen.is_some() && settings.interval.is_none() {
+            settings.interval = Some(DEFAULT_METRICS_INTERVAL);
+        }
+        let interval = settings.interval;
+        for named_target in &mut settings.targets {
+            if let Target::Alertmanager(alertmanager_settings) = &mut named_target.target {
+                alertmanager_settings.ends_after = alertmanager_settings
+                    .ends_after
+                    .or_else(|| interval.map(|interval| interval * ALERTMANAGER_ENDS_AFTER_INTERVALS));
+            }
+        }
+        let is_check = matches!(args.command, Some(Command::Check(_)));
+        if settings.targets.is_empty() && settings.metrics_listen.is_none() && !is_check {
+            return Err("No notification target, use a command or set `targets` in the config file.".into());
+        }
+        if settings.renotify_interval.is_some() && settings.state_dir.is_none() {
+            return Err(
+                "The renotify interval (`renotify_interval`, --renotify-interval) needs a state directory to be set \
+                 (`state_dir`, --state-dir)."
+                    .into(),
+            );
+        }
+        if settings.exit_code && (settings.interval.is_some() || settings.events) {
+            return Err(
+                "The exit code (`exit_code`, --exit-code) can only be used when checking once, without an \
+                 interval (`interval`, --interval) or events (`events`, --events)."
+                    .into(),
+            );
+        }
+        Ok(settings)
+    }
+
+    /// If the containers are exposed as metrics, which then need every checked container and not only the ones with
+    /// problems.
+    pub fn exposes_metrics(&self) -> bool {
+        self.metrics_listen.is_some()
+            || self
+                .targets
+                .iter()
+                .any(|named_target| matches!(named_target.target, Target::Textfile { .. }))
+    }
+}
+
+/// The url is checked while it is read, so the errors have the position of the value and not of the table it is in.
+pub(crate) fn deserialize_url<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_string(UrlVisitor)
+}
+
+struct UrlVisitor;
+
+impl Visitor<'_> for UrlVisitor {
+    type Value = String;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a url")
+    }
+
+    fn visit_str<E>(self, url: &str) -> Result<String, E>
+    where
+        E: de::Error,
+    {
+        match url.parse::<Uri>() {
+            Ok(uri) if uri.scheme().is_some() && uri.host().is_some() => Ok(url.to_owned()),
+            _ => Err(E::custom(format!("invalid url `{url}`"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email::SmtpSecurity;
+    use pretty_assertions::assert_eq;
+
+    const PRINT_TARGET: Target = Target::Print {
+        output: OutputFormat::Text,
+    };
+
+    #[test]
+    fn toml_config_is_loaded() {
+        let config = Config::from_toml(
+            r#"
+label = "monitored"
+hostname = "myhost"
+interval = "5m"
+
+[[targets]]
+type = "teams"
+name = "teams-backend"
+url = "https://example.com/teams"
+
+[[targets]]
+type = "print"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                label: Some("monitored".to_owned()),
+                hostname: Some("myhost".to_owned()),
+                interval: Some(Duration::from_secs(300)),
+                targets: vec![
+                    NamedTarget {
+                        name: Some("teams-backend".to_owned()),
+                        target: Target::Teams {
+                            url: "https://example.com/teams".to_owned()
+                        }
+                    },
+                    PRINT_TARGET.into()
+                ],
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn email_target_is_loaded() {
+        let config = Config::from_yaml(
+            r#"
+targets:
+  - type: email
+    smtp_host: smtp.example.com
+    smtp_username: notifyhealth
+    smtp_password: secret
+    from: notifyhealth@example.com
+    to:
+      - a@example.com
+      - b@example.com
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.targets,
+            vec![Target::Email(EmailSettings {
+                smtp_host: "smtp.example.com".to_owned(),
+                smtp_port: None,
+                smtp_security: SmtpSecurity::Starttls,
+                smtp_username: Some("notifyhealth".to_owned()),
+                smtp_password: Some("secret".to_owned()),
+                from: "notifyhealth@example.com".to_owned(),
+                to: vec!["a@example.com".to_owned(), "b@example.com".to_owned()],
+            })
+            .into()]
+        );
+    }
+
+    #[test]
+    fn pagerduty_target_uses_default_events_url() {
+        let config = Config::from_toml(
+            r#"
+[[targets]]
+type = "pagerduty"
+routing_key = "key"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.targets,
+            vec![Target::Pagerduty(PagerDutySettings {
+                routing_key: "key".to_owned(),
+                events_url: "https://events.pagerduty.com/v2/enqueue".to_owned(),
+            })
+            .into()]
+        );
+    }
+
+    #[test]
+    fn alertmanager_alerts_end_after_three_intervals() {
+        let alertmanager = |ends_after| {
+            vec![NamedTarget::from(Target::Alertmanager(AlertmanagerSettings {
+                url: "http://alertmanager:9093".to_owned(),
+                ends_after,
+            }))]
+        };
+        let config = Config::from_toml(
+            r#"
+label = "foo"
+interval = "5m"
+
+[[targets]]
+type = "alertmanager"
+url = "http://alertmanager:9093"
+"#,
+        )
+        .unwrap();
+        let args = Args::new_from(["notifyhealth"].iter());
+        let settings = Settings::new(&args, Some(config)).unwrap();
+        assert_eq!(settings.targets, alertmanager(Some(Duration::from_secs(15 * 60))));
+        let args_with_ends_after = Args::new_from(
+            [
+                "notifyhealth",
+                "--label",
+                "foo",
+                "--interval",
+                "5m",
+                "notify-alertmanager",
+                "--url",
+                "http://alertmanager:9093",
+                "--ends-after",
+                "1h",
+            ]
+            .iter(),
+        );
+        let settings_with_ends_after = Settings::new(&args_with_ends_after, None).unwrap();
+        assert_eq!(
+            settings_with_ends_after.targets,
+            alertmanager(Some(Duration::from_secs(60 * 60)))
+        );
+        let once = Args::new_from(
+            [
+                "notifyhealth",
+                "--label",
+                "foo",
+                "notify-alertmanager",
+                "--url",
+                "http://alertmanager:9093",
+            ]
+            .iter(),
+        );
+        assert_eq!(Settings::new(&once, None).unwrap().targets, alertmanager(None));
+    }
+
+    #[test]
+    fn yaml_config_is_loaded() {
+        let config = Config::from_yaml(
+            r#"
+label: monitored
+report_no_health: true
+targets:
+  - type: webhook
+    url: https://example.com/hook
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                label: Some("monitored".to_owned()),
+                report_no_health: Some(true),
+                targets: vec![Target::Webhook {
+                    url: "https://example.com/hook".to_owned()
+                }
+                .into()],
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn toml_errors_have_line_and_field() {
+        let target_err = Config::from_toml(
+            r#"
+label = "monitored"
+
+[[targets]]
+type = "teams"
+url = "not a url"
+"#,
+        )
+        .unwrap_err();
+        assert!(
+            target_err.starts_with("field `targets[0].url`: TOML parse error at line 6, column 7"),
+            "{target_err}"
+        );
+        assert!(target_err.contains("invalid url `not a url`"), "{target_err}");
+        let err = Config::from_toml("label = \"monitored\"\nrenotify_interval = 12\n").unwrap_err();
+        assert!(
+            err.starts_with("field `renotify_interval`: TOML parse error at line 2, column 21"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn yaml_errors_have_line_and_field() {
+        let err = Config::from_yaml(
+            r#"
+label: monitored
+interval: soon
+"#,
+        )
+        .unwrap_err();
+        assert!(err.starts_with("field `interval`: "), "{err}");
+        assert!(err.ends_with("at line 3 column 11"), "{err}");
+        let target_err = Config::from_yaml(
+            r#"
+label: monitored
+targets:
+  - type: slack
+    url: https://example.com/slack
+    name: slack-backend
+  - name: webhook-backend
+    type: webhook
+    url: not a url
+"#,
+        )
+        .unwrap_err();
+        assert!(target_err.starts_with("field `targets[1].url`: "), "{target_err}");
+        assert!(target_err.ends_with("at line 9 column 10"), "{target_err}");
+    }
+
+    #[test]
+    fn target_type_can_be_after_its_fields() {
+        let config = Config::from_yaml(
+            r#"
+label: monitored
+targets:
+  - url: https://example.com/slack
+    name: ops
+    type: slack
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.targets,
+            vec![NamedTarget {
+                name: Some("ops".to_owned()),
+                target: Target::Slack {
+                    url: "https://example.com/slack".to_owned()
+                },
+            }]
+        );
+        let toml_config = Config::from_toml(
+            r#"
+label = "monitored"
+[[targets]]
+url = "https://example.com/slack"
+type = "slack"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            toml_config.targets,
+            vec![NamedTarget::from(Target::Slack {
+                url: "https://example.com/slack".to_owned()
+            })]
+        );
+        let err = Config::from_toml(
+            r#"
+label = "monitored"
+[[targets]]
+url = "not a url"
+type = "slack"
+"#,
+        )
+        .unwrap_err();
+        assert!(err.starts_with("field `targets[0]`: "), "{err}");
+        assert!(err.contains("`url`: invalid url `not a url`"), "{err}");
+    }
+
+    #[test]
+    fn cli_args_override_config() {
+        let args = Args::new_from(["notifyhealth", "--label", "foo", "print"].iter());
+        let config = Config {
+            label: Some("bar".to_owned()),
+            hostname: Some("myhost".to_owned()),
+            targets: vec![Target::Teams {
+                url: "https://example.com/teams".to_owned(),
+            }
+            .into()],
+            ..Default::default()
+        };
+        let settings = Settings::new(&args, Some(config)).unwrap();
+        assert_eq!(settings.hosts[0].label.to_string(), "foo");
+        assert_eq!(settings.hostname, Some("myhost".to_owned()));
+        assert_eq!(settings.targets, vec![PRINT_TARGET.into()]);
+    }
+
+    #[test]
+    fn cli_args_turn_off_config_flags() {
+        let config = || Config {
+            label: Some("bar".to_owned()),
+            report_no_health: Some(true),
+            events: Some(true),
+            ..Default::default()
+        };
+        let args = Args::new_from(["notifyhealth", "print"].iter());
+        let settings = Settings::new(&args, Some(config())).unwrap();
+        assert!(settings.report_no_health);
+        assert!(settings.events);
+        let turned_off_args =
+            Args::new_from(["notifyhealth", "--report-no-health=false", "--events=false", "print"].iter());
+        let turned_off = Settings::new(&turned_off_args, Some(config())).unwrap();
+        assert!(!turned_off.report_no_health);
+        assert!(!turned_off.events);
+    }
+
+    #[test]
+    fn notify_command_has_several_targets() {
+        let args = Args::new_from(
+            [
+                "notifyhealth",
+                "--label",
+                "foo",
+                "notify",
+                "--webhook-url",
+                "https://example.com/hook",
+                "--teams-url",
+                "https://example.com/teams",
+                "--print",
+            ]
+            .iter(),
+        );
+        let settings = Settings::new(&args, None).unwrap();
+        assert_eq!(
+            settings.targets,
+            vec![
+                PRINT_TARGET.into(),
+                Target::Teams {
+                    url: "https://example.com/teams".to_owned()
+                }
+                .into(),
+                Target::Webhook {
+                    url: "https://example.com/hook".to_owned()
+                }
+                .into()
+            ]
+        );
+    }
+
+    #[test]
+    fn label_is_a_selector() {
+        let args = Args::new_from(["notifyhealth", "--label", "notifyhealth,!notifyhealth.ignore", "print"].iter());
+        let settings = Settings::new(&args, None).unwrap();
+        assert_eq!(settings.hosts[0].label.docker_filter(), vec!["notifyhealth"]);
+        let invalid_args = Args::new_from(["notifyhealth", "--label", "a,,b", "print"].iter());
+        assert_eq!(
+            Settings::new(&invalid_args, None).unwrap_err().to_string(),
+            "Invalid label selector `a,,b`: a label is empty"
+        );
+    }
+
+    #[test]
+    fn settings_need_label_and_targets() {
+        let args = Args::new_from(["notifyhealth"].iter());
+        assert!(Settings::new(&args, None).is_err());
+        let config_without_targets = Config {
+            label: Some("bar".to_owned()),
+            ..Default::default()
+        };
+        assert!(Settings::new(&args, Some(config_without_targets)).is_err());
+        let config_without_state_dir = Config {
+            label: Some("bar".to_owned()),
+            renotify_interval: Some(Duration::from_secs(60)),
+            targets: vec![PRINT_TARGET.into()],
+            ..Default::default()
+        };
+        assert!(Settings::new(&args, Some(config_without_state_dir))
+            .unwrap_err()
+            .to_string()
+            .contains("(`state_dir`, --state-dir)"));
+        let config_with_exit_code_and_interval = Config {
+            label: Some("bar".to_owned()),
+            interval: Some(Duration::from_secs(60)),
+            exit_code: Some(true),
+            targets: vec![PRINT_TARGET.into()],
+            ..Default::default()
+        };
+        assert!(Settings::new(&args, Some(config_with_exit_code_and_interval))
+            .unwrap_err()
+            .to_string()
+            .starts_with("The exit code (`exit_code`, --exit-code)"));
+    }
+
+    #[test]
+    fn serve_metrics_keeps_targets_from_config_and_checks_every_minute() {
+        let args = Args::new_from(["notifyhealth", "--label", "foo", "serve-metrics"].iter());
+        let settings = Settings::new(&args, None).unwrap();
+        assert_eq!(settings.metrics_listen, Some("0.0.0.0:9393".parse().unwrap()));
+        assert_eq!(settings.interval, Some(Duration::from_secs(60)));
+        assert_eq!(settings.targets, vec![]);
+        let config = Config {
+            interval: Some(Duration::from_secs(10)),
+            targets: vec![PRINT_TARGET.into()],
+            ..Default::default()
+        };
+        let settings_with_config = Settings::new(&args, Some(config)).unwrap();
+        assert_eq!(settings_with_config.interval, Some(Duration::from_secs(10)));
+        assert_eq!(settings_with_config.targets, vec![PRINT_TARGET.into()]);
+        let args_with_events = Args::new_from(["notifyhealth", "--label", "foo", "--events", "serve-metrics"].iter());
+        let settings_with_events = Settings::new(&args_with_events, None).unwrap();
+        assert!(settings_with_events.events);
+        assert_eq!(settings_with_events.interval, Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn boolean_flags_turn_off_the_config() {
+        let config = || Config {
+            label: Some("bar".to_owned()),
+            swarm: Some(true),
+            exit_code: Some(true),
+            targets: vec![PRINT_TARGET.into()],
+            ..Default::default()
+        };
+        let args = Args::new_from(["notifyhealth"].iter());
+        let settings = Settings::new(&args, Some(config())).unwrap();
+        assert!(settings.swarm);
+        assert!(settings.exit_code);
+        let turned_off = Args::new_from(["notifyhealth", "--swarm=false", "--exit-code=false"].iter());
+        let settings_turned_off = Settings::new(&turned_off, Some(config())).unwrap();
+        assert!(!settings_turned_off.swarm);
+        assert!(!settings_turned_off.exit_code);
+    }
+
+    #[test]
+    fn docker_host_from_config() {
+        let args = Args::new_from(["notifyhealth", "--label", "foo", "print"].iter());
+        let config = Config::from_toml(
+            r#"
+docker_host = "tcp://docker.example.com"
+docker_cert_path = "/etc/notifyhealth/certs"
+docker_tls_verify = true
+"#,
+        )
+        .unwrap();
+        let settings = Settings::new(&args, Some(config)).unwrap();
+        assert_eq!(
+            settings.hosts[0].docker_host,
+            DockerHost::Tcp {
+                address: "docker.example.com:2376".to_owned(),
+                cert_path: Some(PathBuf::from("/etc/notifyhealth/certs"))
+            }
+        );
+        let invalid_config = Config {
+            docker_host: Some("docker.example.com".to_owned()),
+            ..Default::default()
+        };
+        assert!(Settings::new(&args, Some(invalid_config)).is_err());
+    }
+
+    #[test]
+    fn several_hosts_from_config() {
+        let args = Args::new_from(["notifyhealth", "print"].iter());
+        let config = Config::from_toml(
+            r#"
+label = "monitored"
+docker_cert_path = "/certs"
+
+[[hosts]]
+hostname = "local"
+
+[[hosts]]
+hostname = "db1"
+docker_host = "tcp://db1.example.com"
+docker_tls_verify = true
+label = "database"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            Settings::new(&args, Some(config)).unwrap().hosts,
+            vec![
+                Host {
+                    hostname: Some("local".to_owned()),
+                    docker_host: DockerHost::Local,
+                    label: "monitored".parse().unwrap()
+                },
+                Host {
+                    hostname: Some("db1".to_owned()),
+                    docker_host: DockerHost::Tcp {
+                        address: "db1.example.com:2376".to_owned(),
+                        cert_path: Some(PathBuf::from("/certs"))
+                    },
+                    label: "database".parse().unwrap()
+                }
+            ]
+        );
+        let duplicated_config =
+            Config::from_toml("label = \"monitored\"\n[[hosts]]\nhostname = \"a\"\n[[hosts]]\nhostname = \"a\"\n")
+                .unwrap();
+        assert_eq!(
+            Settings::new(&args, Some(duplicated_config)).unwrap_err().to_string(),
+            "field `hosts[1].hostname`: the host a is configured more than once."
+        );
+    }
+}
@@ -1,47 +1,89 @@
 #![warn(clippy::shadow_unrelated)]
 use async_trait::async_trait;
-use bollard::container::{InspectContainerOptions, ListContainersOptions};
 use bollard::errors::Error;
-use bollard::models::{ContainerInspectResponse, ContainerSummary, HealthStatusEnum};
+use bollard::models::{ContainerInspectResponse, ContainerSummary, EventMessage, HealthStatusEnum, SystemVersion};
+use bollard::query_parameters::{EventsOptions, InspectContainerOptions, ListContainersOptions};
+use futures::stream::BoxStream;
 use futures_util::Future;
+use log::*;
 #[cfg(test)]
 use mockall::automock;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::pin::Pin;
+use std::time::{Duration, SystemTime};
+
+use super::selector::LabelSelector;
+use super::swarm::ServiceStatus;
 
 use bollard::Docker;
 use futures::prelude::*;
 
+pub const COMPOSE_PROJECT_LABEL: &str = "com.docker.compose.project";
+pub const COMPOSE_SERVICE_LABEL: &str = "com.docker.compose.service";
+pub const COMPOSE_CONTAINER_NUMBER_LABEL: &str = "com.docker.compose.container-number";
+pub const IGNORE_LABEL: &str = "notifyhealth.ignore";
+pub const SEVERITY_LABEL: &str = "notifyhealth.severity";
+pub const GRACE_PERIOD_LABEL: &str = "notifyhealth.grace-period";
+pub const EXPECT_LABEL: &str = "notifyhealth.expect";
+pub const TARGET_LABEL: &str = "notifyhealth.target";
+
+/// The daemon behind the Docker API, as Podman only implements part of it.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Engine {
+    #[default]
+    Docker,
+    Podman,
+}
+
+#[derive(Clone)]
 pub struct Containers {
-    docker: Docker,
+    pub(crate) docker: Docker,
+    engine: Engine,
 }
 
 #[cfg_attr(test, automock)]
 #[async_trait]
 pub trait HasContainers {
-    async fn list_containers<'a>(
-        &'a self,
-        options: Option<ListContainersOptions<&'a str>>,
-    ) -> Result<Vec<ContainerSummary>, Error>;
+    async fn list_containers(&self, options: Option<ListContainersOptions>) -> Result<Vec<ContainerSummary>, Error>;
     async fn inspect_container<'a>(
         &'a self,
         container_name: &'a str,
         options: Option<InspectContainerOptions>,
     ) -> Result<ContainerInspectResponse, Error>;
+    fn events(&self, options: Option<EventsOptions>) -> BoxStream<'static, Result<EventMessage, Error>>;
+    fn engine(&self) -> Engine;
 }
 
 impl Containers {
-    pub fn new(docker: Docker) -> Self {
-        Self { docker }
+    pub fn new(docker: Docker, engine: Engine) -> Self {
+        Self { docker, engine }
     }
+
+    /// Asks the daemon for its version to know if it is Podman, which fails when the daemon can't be reached.
+    pub async fn detect(docker: Docker) -> Result<Self, Error> {
+        let engine = if is_podman(&docker.version().await?) {
+            Engine::Podman
+        } else {
+            Engine::Docker
+        };
+        info!("Container engine: {:?}.", engine);
+        Ok(Self::new(docker, engine))
+    }
+}
+
+fn is_podman(version: &SystemVersion) -> bool {
+    version
+        .components
+        .iter()
+        .flatten()
+        .any(|component| component.name.starts_with("Podman"))
 }
 
 #[async_trait]
 impl HasContainers for Containers {
-    async fn list_containers<'a>(
-        &'a self,
-        options: Option<ListContainersOptions<&'a str>>,
-    ) -> Result<Vec<ContainerSummary>, Error> {
+    async fn list_containers(&self, options: Option<ListContainersOptions>) -> Result<Vec<ContainerSummary>, Error> {
         self.docker.list_containers(options).await
     }
     fn inspect_container<'a, 'async_trait>(
@@ -55,121 +97,579 @@ impl HasContainers for Containers {
     {
         Box::pin(self.docker.inspect_container(container_name, options))
     }
+    fn events(&self, options: Option<EventsOptions>) -> BoxStream<'static, Result<EventMessage, Error>> {
+        Box::pin(self.docker.events(options))
+    }
+    fn engine(&self) -> Engine {
+        self.engine
+    }
 }
 
 pub async fn check_running_containers(
     docker: &dyn HasContainers,
+    label: &LabelSelector,
     report_no_health: bool,
 ) -> Result<Vec<RunningContainerStatus>, Box<dyn std::error::Error>> {
     let mut health_filter = vec!["unhealthy", "starting"];
     if report_no_health {
         health_filter.push("none");
     }
-    let filter = hashmap!["status" => vec!["running"], "health" => health_filter];
+    // Podman has no `none` health status to filter on, as it reports an empty one, so its running containers are all
+    // listed and filtered on the health status they are inspected for.
+    let engine = docker.engine();
+    let filter = match engine {
+        Engine::Docker => hashmap!["status" => vec!["running"], "health" => health_filter],
+        Engine::Podman => hashmap!["status" => vec!["running"]],
+    };
     let containers = docker
         .list_containers(Some(ListContainersOptions {
             all: true,
-            filters: filter,
+            filters: label_filters(filter, label),
             ..Default::default()
         }))
         .await?;
-    Ok(future::join_all(containers.into_iter().map(|container| async move {
+    let containers = containers.into_iter().filter_map(|container| {
+        let monitoring = MonitoringLabels::from_labels(container.labels.as_ref(), get_container_name(&container));
+        (label.matches(container.labels.as_ref()) && !monitoring.ignore).then_some((container, monitoring))
+    });
+    let now = SystemTime::now();
+    let statuses = future::join_all(containers.map(|(container, monitoring)| async move {
         let name = get_container_name(&container);
+        let compose = ComposeService::from_labels(container.labels.as_ref());
         let inpect_result = docker.inspect_container(name, None).await;
-        let health = inpect_result
+        let state = inpect_result.unwrap_or_default().state.unwrap_or_default();
+        if in_grace_period(monitoring.grace_period, state.started_at.as_deref(), now) {
+            debug!("Container {name} started less than its grace period ago, not checking it.");
+            return None;
+        }
+        let health = state.health.unwrap_or_default();
+        Some(RunningContainerStatus {
+            name: name.to_string(),
+            health: health
+                .status
+                .filter(|status| !matches!(status, HealthStatusEnum::EMPTY | HealthStatusEnum::NONE)),
+            compose,
+            severity: monitoring.severity,
+            target: monitoring.target,
+            ..Default::default()
+        })
+    }))
+    .await;
+    Ok(statuses
+        .into_iter()
+        .flatten()
+        .filter(|container| match (engine, container.health) {
+            (Engine::Docker, _) => true,
+            (Engine::Podman, Some(health)) => health != HealthStatusEnum::HEALTHY,
+            (Engine::Podman, None) => report_no_health,
+        })
+        .collect())
+}
+
+/// The running containers with the label that have no problem, for the metrics, so every checked container has a
+/// series and not only the ones in `problems`, which were already reported by [`check_running_containers`].
+pub async fn check_healthy_containers(
+    docker: &dyn HasContainers,
+    label: &LabelSelector,
+    problems: &[RunningContainerStatus],
+) -> Result<Vec<RunningContainerStatus>, Box<dyn std::error::Error>> {
+    let containers = docker
+        .list_containers(Some(ListContainersOptions {
+            all: true,
+            filters: label_filters(hashmap!["status" => vec!["running"]], label),
+            ..Default::default()
+        }))
+        .await?;
+    let containers = containers.into_iter().filter(|container| {
+        let name = get_container_name(container);
+        label.matches(container.labels.as_ref())
+            && !MonitoringLabels::from_labels(container.labels.as_ref(), name).ignore
+            && !problems.iter().any(|problem| problem.name == name)
+    });
+    Ok(future::join_all(containers.map(|container| async move {
+        let name = get_container_name(&container);
+        let inspect_result = docker.inspect_container(name, None).await;
+        let health = inspect_result
             .unwrap_or_default()
             .state
-            .unwrap_or_default()
-            .health
-            .unwrap_or_default();
+            .and_then(|state| state.health)
+            .and_then(|health| health.status)
+            .filter(|status| !matches!(status, HealthStatusEnum::EMPTY | HealthStatusEnum::NONE));
         RunningContainerStatus {
             name: name.to_string(),
-            health: health.status,
+            health,
+            compose: ComposeService::from_labels(container.labels.as_ref()),
+            ..Default::default()
         }
     }))
     .await)
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Default)]
 pub struct StoppedContainerStatus {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub status: Option<String>,
+    /// The display hostname of the Docker host the container is on, when several hosts are checked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compose: Option<ComposeService>,
+    /// From the `notifyhealth.severity` label.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<Severity>,
+    /// The name of the only target notified, from the `notifyhealth.target` label.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// How the container stopped, from inspecting it, to tell a crash from a container stopped by someone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i64>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub oom_killed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// RFC 3339, as the Docker API has it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restart_count: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Default)]
 pub struct RunningContainerStatus {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub health: Option<HealthStatusEnum>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compose: Option<ComposeService>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<Severity>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+/// The severity and the target are the ones of the container when it had problems, so the recovery goes to the same
+/// target and ends the same alert.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Default)]
+pub struct ResolvedContainerStatus {
+    pub name: String,
+    pub previous_status: String,
+    pub down_for_seconds: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<Severity>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+/// The severity a container can set with its `notifyhealth.severity` label, overriding the one of its problem.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Critical,
+    Error,
+    Warning,
+    Info,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self {
+            Severity::Critical => "critical",
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        };
+        write!(f, "{severity}")
+    }
+}
+
+/// A value shown for a container in the notifications, followed by the severity of its label if it has one.
+pub fn with_severity(value: String, severity: Option<Severity>) -> String {
+    match severity {
+        Some(severity) => format!("{value} ({severity})"),
+        None => value,
+    }
+}
+
+/// How a container is monitored, from its `notifyhealth.*` labels, so it can be tuned where the container is defined.
+/// Invalid values are logged and left to the defaults.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct MonitoringLabels {
+    /// `notifyhealth.ignore=true`: the container is never reported.
+    pub ignore: bool,
+    /// `notifyhealth.expect=stopped`: the container is expected to exit, e.g. a one-off job, and is only reported
+    /// when it is unhealthy.
+    pub expect_stopped: bool,
+    /// `notifyhealth.grace-period=5m`: the container is not reported until it has been started, or stopped, for that
+    /// long.
+    pub grace_period: Option<Duration>,
+    pub severity: Option<Severity>,
+    pub target: Option<String>,
+}
+
+impl MonitoringLabels {
+    pub fn from_labels(labels: Option<&HashMap<String, String>>, container_name: &str) -> MonitoringLabels {
+        let empty_labels = HashMap::new();
+        let labels = labels.unwrap_or(&empty_labels);
+        let invalid = |label: &str, value: &str| {
+            warn!("Ignoring the label {label}={value} of the container {container_name}, the value is invalid.");
+        };
+        let ignore = match labels.get(IGNORE_LABEL).map(|value| value.trim()) {
+            None | Some("false") => false,
+            Some("true") | Some("") => true,
+            Some(value) => {
+                invalid(IGNORE_LABEL, value);
+                false
+            }
+        };
+        let expect_stopped = match labels.get(EXPECT_LABEL).map(|value| value.trim()) {
+            None | Some("running") => false,
+            Some("stopped") => true,
+            Some(value) => {
+                invalid(EXPECT_LABEL, value);
+                false
+            }
+        };
+        let grace_period =
+            labels
+                .get(GRACE_PERIOD_LABEL)
+                .and_then(|value| match humantime::parse_duration(value.trim()) {
+                    Ok(grace_period) => Some(grace_period),
+                    Err(_) => {
+                        invalid(GRACE_PERIOD_LABEL, value);
+                        None
+                    }
+                });
+        let severity = labels
+            .get(SEVERITY_LABEL)
+            .and_then(|value| match value.trim().to_lowercase().as_str() {
+                "critical" => Some(Severity::Critical),
+                "error" => Some(Severity::Error),
+                "warning" => Some(Severity::Warning),
+                "info" => Some(Severity::Info),
+                _ => {
+                    invalid(SEVERITY_LABEL, value);
+                    None
+                }
+            });
+        MonitoringLabels {
+            ignore,
+            expect_stopped,
+            grace_period,
+            severity,
+            target: labels
+                .get(TARGET_LABEL)
+                .map(|target| target.trim().to_owned())
+                .filter(|target| !target.is_empty()),
+        }
+    }
+}
+
+/// If the container started, or stopped, less than its grace period ago. The times are the RFC 3339 ones of the Docker
+/// API, which are in the year 1 when the container never started or stopped.
+fn in_grace_period(grace_period: Option<Duration>, since: Option<&str>, now: SystemTime) -> bool {
+    let (Some(grace_period), Some(since)) = (grace_period, since) else {
+        return false;
+    };
+    match humantime::parse_rfc3339_weak(since) {
+        Ok(since) => now.duration_since(since).unwrap_or_default() < grace_period,
+        Err(_) => false,
+    }
+}
+
+/// The Docker Compose service a container was created for, from the labels Compose sets on it.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Default)]
+pub struct ComposeService {
+    pub project: String,
+    pub service: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container_number: Option<u32>,
+}
+
+impl ComposeService {
+    pub fn from_labels(labels: Option<&HashMap<String, String>>) -> Option<ComposeService> {
+        let labels = labels?;
+        Some(ComposeService {
+            project: labels.get(COMPOSE_PROJECT_LABEL)?.to_owned(),
+            service: labels.get(COMPOSE_SERVICE_LABEL)?.to_owned(),
+            container_number: labels
+                .get(COMPOSE_CONTAINER_NUMBER_LABEL)
+                .and_then(|number| number.parse().ok()),
+        })
+    }
+}
+
+/// A Docker host whose containers could not be checked, reported as a problem of its own.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Default)]
+pub struct UnreachableHost {
+    pub host: String,
+    pub error: String,
+}
+
+/// A Docker host that could not be checked before and can be again, to end the problem it was reported as.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Default)]
+pub struct ResolvedHost {
+    pub host: String,
+    pub down_for_seconds: u64,
+}
+
+/// The name shown in the notifications, prefixed by the host when several hosts are checked, so the containers of
+/// each host are grouped together when sorted.
+pub fn display_name(host: Option<&str>, name: &str) -> String {
+    match host {
+        Some(host) => format!("{host}/{name}"),
+        None => name.to_owned(),
+    }
+}
+
+impl RunningContainerStatus {
+    pub fn display_name(&self) -> String {
+        display_name(self.host.as_deref(), &self.name)
+    }
+
+    pub fn project(&self) -> Option<&str> {
+        self.compose.as_ref().map(|compose| compose.project.as_str())
+    }
+}
+
+impl StoppedContainerStatus {
+    pub fn display_name(&self) -> String {
+        display_name(self.host.as_deref(), &self.name)
+    }
+
+    /// The status with how the container stopped, e.g.
+    /// `exited, exit code 137, out of memory, restarted 3 times, finished at 2024-01-01T10:00:00Z, image nginx:1.25`.
+    pub fn summary(&self) -> String {
+        let mut details = vec![self.status.clone().unwrap_or_else(|| "no status".to_owned())];
+        if let Some(exit_code) = self.exit_code {
+            details.push(format!("exit code {exit_code}"));
+        }
+        if self.oom_killed {
+            details.push("out of memory".to_owned());
+        }
+        if let Some(error) = &self.error {
+            details.push(format!("error: {error}"));
+        }
+        match self.restart_count {
+            Some(1) => details.push("restarted once".to_owned()),
+            Some(restart_count) if restart_count > 1 => details.push(format!("restarted {restart_count} times")),
+            _ => {}
+        }
+        if let Some(finished_at) = &self.finished_at {
+            details.push(format!("finished at {finished_at}"));
+        }
+        if let Some(image) = &self.image {
+            details.push(format!("image {image}"));
+        }
+        details.join(", ")
+    }
+
+    pub fn project(&self) -> Option<&str> {
+        self.compose.as_ref().map(|compose| compose.project.as_str())
+    }
+}
+
+/// Groups the containers by Compose project, in the order of the projects' names, with the containers that are not
+/// part of a project last, under `None`. The containers keep their order within a group.
+pub fn by_project<T>(containers: &[T], project: fn(&T) -> Option<&str>) -> Vec<(Option<&str>, Vec<&T>)> {
+    let mut groups: BTreeMap<Option<&str>, Vec<&T>> = BTreeMap::new();
+    for container in containers {
+        groups.entry(project(container)).or_default().push(container);
+    }
+    let mut groups = groups.into_iter().collect::<Vec<_>>();
+    // `None` sorts first, the containers outside of a project go after the projects.
+    if matches!(groups.first(), Some((None, _))) {
+        groups.rotate_left(1);
+    }
+    groups
+}
+
+impl ResolvedContainerStatus {
+    pub fn display_name(&self) -> String {
+        display_name(self.host.as_deref(), &self.name)
+    }
+}
+
+/// The result of a check, used as the process exit code with `--exit-code`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CheckStatus {
+    Healthy,
+    Unhealthy,
+    Stopped,
+    DockerUnreachable,
+}
+
+impl CheckStatus {
+    /// Stopped containers are worse than unhealthy ones, so they win when there are both, and a host that could not
+    /// be checked is worse than both, as nothing is known about its containers. Swarm services missing replicas count
+    /// as stopped containers.
+    pub fn from_containers(
+        running_containers: &[RunningContainerStatus],
+        stopped_containers: &[StoppedContainerStatus],
+        unreachable_hosts: &[UnreachableHost],
+        services: &[ServiceStatus],
+    ) -> CheckStatus {
+        if !unreachable_hosts.is_empty() {
+            CheckStatus::DockerUnreachable
+        } else if !stopped_containers.is_empty() || !services.is_empty() {
+            CheckStatus::Stopped
+        } else if !running_containers.is_empty() {
+            CheckStatus::Unhealthy
+        } else {
+            CheckStatus::Healthy
+        }
+    }
+
+    pub fn exit_code(self) -> i32 {
+        match self {
+            CheckStatus::Healthy => 0,
+            CheckStatus::Unhealthy => 1,
+            CheckStatus::Stopped => 2,
+            CheckStatus::DockerUnreachable => 3,
+        }
+    }
 }
 
 pub async fn check_not_running_containers(
     docker: &dyn HasContainers,
-    label: &str,
+    label: &LabelSelector,
 ) -> Result<Vec<StoppedContainerStatus>, Box<dyn std::error::Error>> {
-    let filter = hashmap!["status" => vec!["created", "paused", "restarting", "removing", "exited", "dead"], "label" => vec![label]];
+    // Podman rejects the states it does not have, and has a `stopped` one for the containers not cleaned up yet.
+    let states = match docker.engine() {
+        Engine::Docker => vec!["created", "paused", "restarting", "removing", "exited", "dead"],
+        Engine::Podman => vec!["created", "paused", "stopped", "exited"],
+    };
     let containers = docker
         .list_containers(Some(ListContainersOptions {
             all: true,
-            filters: filter,
+            filters: label_filters(hashmap!["status" => states], label),
             ..Default::default()
         }))
         .await?;
-    Ok(containers
-        .into_iter()
-        .map(|container| StoppedContainerStatus {
-            name: get_container_name(&container).to_string(),
-            status: container.state,
+    let containers = containers.into_iter().filter_map(|container| {
+        let monitoring = MonitoringLabels::from_labels(container.labels.as_ref(), get_container_name(&container));
+        (label.matches(container.labels.as_ref()) && !monitoring.ignore && !monitoring.expect_stopped)
+            .then_some((container, monitoring))
+    });
+    let now = SystemTime::now();
+    let statuses = future::join_all(containers.map(|(container, monitoring)| async move {
+        let name = get_container_name(&container);
+        let inspected = match docker.inspect_container(name, None).await {
+            Ok(inspected) => inspected,
+            Err(err) => {
+                warn!("Could not inspect the container {name}, reporting it without how it stopped: {err}");
+                Default::default()
+            }
+        };
+        let stopped_state = inspected.state.unwrap_or_default();
+        if in_grace_period(monitoring.grace_period, stopped_state.finished_at.as_deref(), now) {
+            debug!("Container {name} stopped less than its grace period ago, not reporting it yet.");
+            return None;
+        }
+        Some(StoppedContainerStatus {
+            name: name.to_string(),
+            compose: ComposeService::from_labels(container.labels.as_ref()),
+            status: container.state.map(|state| state.to_string()),
+            severity: monitoring.severity,
+            target: monitoring.target,
+            exit_code: stopped_state.exit_code,
+            oom_killed: stopped_state.oom_killed.unwrap_or_default(),
+            error: stopped_state.error.filter(|error| !error.is_empty()),
+            // Containers that never ran have a finish time in the year 1.
+            finished_at: stopped_state
+                .finished_at
+                .filter(|finished_at| !finished_at.starts_with("0001-")),
+            restart_count: inspected.restart_count,
+            image: container
+                .image
+                .or_else(|| inspected.config.and_then(|config| config.image)),
+            ..Default::default()
         })
-        .collect())
+    }))
+    .await;
+    Ok(statuses.into_iter().flatten().collect())
+}
+
+/// The filters of the Docker API, which are keyed by strings.
+pub(crate) fn filters(filters: HashMap<&str, Vec<&str>>) -> Option<HashMap<String, Vec<String>>> {
+    Some(
+        filters
+            .into_iter()
+            .map(|(key, values)| (key.to_owned(), values.into_iter().map(str::to_owned).collect()))
+            .collect(),
+    )
 }
 
+/// The filters with the labels of the selector that Docker can filter on. The objects listed still have to be matched
+/// against the selector, for the labels that it can't.
+pub(crate) fn label_filters(
+    filter: HashMap<&str, Vec<&str>>,
+    label: &LabelSelector,
+) -> Option<HashMap<String, Vec<String>>> {
+    let mut filters = filters(filter);
+    let labels = label.docker_filter();
+    if !labels.is_empty() {
+        filters
+            .get_or_insert_with(HashMap::new)
+            .insert("label".to_owned(), labels);
+    }
+    filters
+}
+
+/// Docker prefixes the names with `/`, Podman does not always, and its containers may have no name at all, which
+/// leaves the id.
 fn get_container_name(container: &ContainerSummary) -> &str {
-    match &container.names {
-        Some(names) => {
-            let name = names.first().unwrap();
+    match container.names.as_ref().and_then(|names| names.first()) {
+        Some(name) => {
             if let Some(name_without_prefix) = name.strip_prefix('/') {
                 name_without_prefix
             } else {
                 name
             }
         }
-        None => container.id.as_ref().unwrap(),
+        None => container.id.as_deref().unwrap_or_default(),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use bollard::models::{ContainerState, Health};
+    use bollard::models::{ContainerConfig, ContainerState, ContainerSummaryStateEnum, Health};
+    use itertools::Itertools;
     use pretty_assertions::assert_eq;
 
     #[tokio::test]
     async fn check_running_containers_test() {
         let mut has_containers_mock = MockHasContainers::new();
-        let filter = hashmap!["status" => vec!["running"], "health" => vec!["unhealthy", "starting", "none"]];
+        has_containers_mock.expect_engine().return_const(Engine::Docker);
+        let filter = hashmap!["status" => vec!["running"], "health" => vec!["unhealthy", "starting", "none"], "label" => vec!["test_label"]];
         has_containers_mock
             .expect_list_containers()
             .withf(move |options| {
                 let opt = options.as_ref().unwrap();
-                opt.all && opt.filters == filter
+                opt.all && opt.filters == filters(filter.clone())
             })
             .times(1)
             .returning(|_| {
+                let summary = |name: &str, labels: HashMap<String, String>| ContainerSummary {
+                    names: Some(vec![name.to_string()]),
+                    labels: Some(labels),
+                    ..Default::default()
+                };
                 Ok(vec![
-                    ContainerSummary {
-                        names: Some(vec!["/test_container".to_string()]),
-                        ..Default::default()
-                    },
-                    ContainerSummary {
-                        names: Some(vec!["/test_container2".to_string()]),
-                        ..Default::default()
-                    },
-                    ContainerSummary {
-                        names: Some(vec!["/test_container3".to_string()]),
-                        ..Default::default()
-                    },
+                    summary("/test_container", hashmap!["test_label".to_owned() => "".to_owned()]),
+                    summary("/test_container2", hashmap!["test_label".to_owned() => "".to_owned()]),
+                    summary("/test_container3", hashmap!["test_label".to_owned() => "".to_owned()]),
+                    summary(
+                        "/ignored_container",
+                        hashmap!["test_label".to_owned() => "".to_owned(), "notifyhealth.ignore".to_owned() => "true".to_owned()],
+                    ),
                 ])
             });
         has_containers_mock
@@ -214,7 +714,8 @@ mod tests {
                     ..Default::default()
                 })
             });
-        let running_containers_result = check_running_containers(&has_containers_mock, true).await;
+        let label = "test_label,!notifyhealth.ignore".parse().unwrap();
+        let running_containers_result = check_running_containers(&has_containers_mock, &label, true).await;
         if running_containers_result.is_err() {
             panic!(
                 "Errors getting running containers: {:?}",
@@ -227,33 +728,99 @@ mod tests {
             vec![
                 RunningContainerStatus {
                     name: "test_container".to_string(),
-                    health: Some(HealthStatusEnum::UNHEALTHY)
+                    health: Some(HealthStatusEnum::UNHEALTHY),
+                    ..Default::default()
                 },
                 RunningContainerStatus {
                     name: "test_container2".to_string(),
-                    health: None
+                    health: None,
+                    ..Default::default()
                 },
                 RunningContainerStatus {
                     name: "test_container3".to_string(),
-                    health: None
+                    health: None,
+                    ..Default::default()
                 }
             ]
         );
     }
 
+    #[tokio::test]
+    async fn check_healthy_containers_test() {
+        let mut has_containers_mock = MockHasContainers::new();
+        let filter = hashmap!["status" => vec!["running"], "label" => vec!["test_label"]];
+        has_containers_mock
+            .expect_list_containers()
+            .withf(move |options| {
+                let opt = options.as_ref().unwrap();
+                opt.all && opt.filters == filters(filter.clone())
+            })
+            .times(1)
+            .returning(|_| {
+                let summary = |name: &str, labels: HashMap<String, String>| ContainerSummary {
+                    names: Some(vec![name.to_string()]),
+                    labels: Some(labels),
+                    ..Default::default()
+                };
+                Ok(vec![
+                    summary("/healthy_container", hashmap!["test_label".to_owned() => "".to_owned()]),
+                    summary("/unhealthy_container", hashmap!["test_label".to_owned() => "".to_owned()]),
+                    summary(
+                        "/ignored_container",
+                        hashmap!["test_label".to_owned() => "".to_owned(), "notifyhealth.ignore".to_owned() => "true".to_owned()],
+                    ),
+                ])
+            });
+        has_containers_mock
+            .expect_inspect_container()
+            .withf(|name, options| name == "healthy_container" && options.is_none())
+            .times(1)
+            .returning(|_, _| {
+                Ok(ContainerInspectResponse {
+                    state: Some(ContainerState {
+                        health: Some(Health {
+                            status: Some(HealthStatusEnum::HEALTHY),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                })
+            });
+        let problems = vec![RunningContainerStatus {
+            name: "unhealthy_container".to_string(),
+            health: Some(HealthStatusEnum::UNHEALTHY),
+            ..Default::default()
+        }];
+        let label = "test_label".parse().unwrap();
+        let healthy_containers = check_healthy_containers(&has_containers_mock, &label, &problems)
+            .await
+            .unwrap();
+        assert_eq!(
+            healthy_containers,
+            vec![RunningContainerStatus {
+                name: "healthy_container".to_string(),
+                health: Some(HealthStatusEnum::HEALTHY),
+                ..Default::default()
+            }]
+        );
+    }
+
     #[tokio::test]
     async fn check_running_containers_without_health_info() {
         let mut has_containers_mock = MockHasContainers::new();
+        has_containers_mock.expect_engine().return_const(Engine::Docker);
         let filter = hashmap!["status" => vec!["running"], "health" => vec!["unhealthy", "starting"]];
         has_containers_mock
             .expect_list_containers()
             .withf(move |options| {
                 let opt = options.as_ref().unwrap();
-                opt.all && opt.filters == filter
+                opt.all && opt.filters == filters(filter.clone())
             })
             .times(1)
             .returning(|_| Ok(vec![]));
-        let running_containers_result = check_running_containers(&has_containers_mock, false).await;
+        let label = "!notifyhealth.ignore".parse().unwrap();
+        let running_containers_result = check_running_containers(&has_containers_mock, &label, false).await;
         if running_containers_result.is_err() {
             panic!(
                 "Errors getting running containers: {:?}",
@@ -267,23 +834,46 @@ mod tests {
     #[tokio::test]
     async fn check_not_running_containers_test() {
         let mut has_containers_mock = MockHasContainers::new();
-        let label = "test_label";
-        let filter = hashmap!["status" => vec!["created", "paused", "restarting", "removing", "exited", "dead"], "label" => vec![label]];
+        has_containers_mock.expect_engine().return_const(Engine::Docker);
+        let filter = hashmap!["status" => vec!["created", "paused", "restarting", "removing", "exited", "dead"], "label" => vec!["test_label"]];
         has_containers_mock
             .expect_list_containers()
             .withf(move |options| {
                 let opt = options.as_ref().unwrap();
-                opt.all && opt.filters == filter
+                opt.all && opt.filters == filters(filter.clone())
             })
             .times(1)
             .returning(|_| {
                 Ok(vec![ContainerSummary {
                     names: Some(vec!["/test_container".to_string()]),
-                    state: Some("stopped".to_owned()),
+                    labels: Some(hashmap!["test_label".to_owned() => "".to_owned()]),
+                    state: Some(ContainerSummaryStateEnum::EXITED),
                     ..Default::default()
                 }])
             });
-        let stopped_containers_result = check_not_running_containers(&has_containers_mock, label).await;
+        has_containers_mock
+            .expect_inspect_container()
+            .withf(|name, options| name == "test_container" && options.is_none())
+            .times(1)
+            .returning(|_, _| {
+                Ok(ContainerInspectResponse {
+                    state: Some(ContainerState {
+                        exit_code: Some(137),
+                        oom_killed: Some(true),
+                        error: Some("".to_owned()),
+                        finished_at: Some("2024-01-01T10:00:00.123456789Z".to_owned()),
+                        ..Default::default()
+                    }),
+                    restart_count: Some(3),
+                    config: Some(ContainerConfig {
+                        image: Some("nginx:1.25".to_owned()),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                })
+            });
+        let label = "test_label".parse().unwrap();
+        let stopped_containers_result = check_not_running_containers(&has_containers_mock, &label).await;
         if stopped_containers_result.is_err() {
             panic!(
                 "Errors getting stopped containers: {:?}",
@@ -295,8 +885,359 @@ mod tests {
             stopped_containers,
             vec![StoppedContainerStatus {
                 name: "test_container".to_string(),
-                status: Some("stopped".to_string())
+                status: Some("exited".to_string()),
+                exit_code: Some(137),
+                oom_killed: true,
+                finished_at: Some("2024-01-01T10:00:00.123456789Z".to_owned()),
+                restart_count: Some(3),
+                image: Some("nginx:1.25".to_owned()),
+                ..Default::default()
+            }]
+        );
+    }
+
+    #[test]
+    fn stopped_container_summary() {
+        let mut container = StoppedContainerStatus {
+            name: "web".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(container.summary(), "no status");
+        container.status = Some("exited".to_string());
+        container.exit_code = Some(137);
+        container.oom_killed = true;
+        container.error = Some("oci runtime error".to_string());
+        container.restart_count = Some(3);
+        container.finished_at = Some("2024-01-01T10:00:00Z".to_string());
+        container.image = Some("nginx:1.25".to_string());
+        assert_eq!(
+            container.summary(),
+            "exited, exit code 137, out of memory, error: oci runtime error, restarted 3 times, finished at \
+             2024-01-01T10:00:00Z, image nginx:1.25"
+        );
+    }
+
+    #[tokio::test]
+    async fn stopped_containers_follow_their_notifyhealth_labels() {
+        let mut has_containers_mock = MockHasContainers::new();
+        has_containers_mock.expect_engine().return_const(Engine::Docker);
+        has_containers_mock.expect_list_containers().times(1).returning(|_| {
+            let summary = |name: &str, labels: &[(&str, &str)]| ContainerSummary {
+                names: Some(vec![name.to_string()]),
+                labels: Some(
+                    labels
+                        .iter()
+                        .map(|(key, value)| (key.to_string(), value.to_string()))
+                        .collect(),
+                ),
+                state: Some(ContainerSummaryStateEnum::EXITED),
+                ..Default::default()
+            };
+            Ok(vec![
+                summary("/ignored", &[(IGNORE_LABEL, "true")]),
+                summary("/job", &[(EXPECT_LABEL, "stopped")]),
+                summary("/restarted", &[(GRACE_PERIOD_LABEL, "5m")]),
+                summary(
+                    "/web",
+                    &[
+                        (GRACE_PERIOD_LABEL, "5m"),
+                        (SEVERITY_LABEL, "warning"),
+                        (TARGET_LABEL, "teams-backend"),
+                    ],
+                ),
+            ])
+        });
+        has_containers_mock
+            .expect_inspect_container()
+            .withf(|name, _| name == "restarted")
+            .times(1)
+            .returning(|_, _| {
+                Ok(ContainerInspectResponse {
+                    state: Some(ContainerState {
+                        finished_at: Some(humantime::format_rfc3339_nanos(SystemTime::now()).to_string()),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                })
+            });
+        has_containers_mock
+            .expect_inspect_container()
+            .withf(|name, _| name == "web")
+            .times(1)
+            .returning(|_, _| {
+                Ok(ContainerInspectResponse {
+                    state: Some(ContainerState {
+                        finished_at: Some("2024-01-01T10:00:00.123456789Z".to_owned()),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                })
+            });
+        let label = "!other".parse().unwrap();
+        assert_eq!(
+            check_not_running_containers(&has_containers_mock, &label)
+                .await
+                .unwrap(),
+            vec![StoppedContainerStatus {
+                name: "web".to_string(),
+                status: Some("exited".to_string()),
+                severity: Some(Severity::Warning),
+                target: Some("teams-backend".to_owned()),
+                finished_at: Some("2024-01-01T10:00:00.123456789Z".to_owned()),
+                ..Default::default()
             }]
         );
     }
+
+    #[tokio::test]
+    async fn alternative_labels_are_matched_on_the_listed_containers() {
+        let mut has_containers_mock = MockHasContainers::new();
+        has_containers_mock.expect_engine().return_const(Engine::Docker);
+        let filter = hashmap!["status" => vec!["created", "paused", "restarting", "removing", "exited", "dead"]];
+        has_containers_mock
+            .expect_list_containers()
+            .withf(move |options| options.as_ref().unwrap().filters == filters(filter.clone()))
+            .times(1)
+            .returning(|_| {
+                Ok([
+                    ("web", "tier", "web"),
+                    ("db", "tier", "db"),
+                    ("batch", "tier", "batch"),
+                    ("cache", "cache", ""),
+                ]
+                .map(|(name, key, value)| ContainerSummary {
+                    names: Some(vec![format!("/{name}")]),
+                    labels: Some(hashmap![key.to_owned() => value.to_owned()]),
+                    ..Default::default()
+                })
+                .to_vec())
+            });
+        has_containers_mock
+            .expect_inspect_container()
+            .times(3)
+            .returning(|_, _| Ok(ContainerInspectResponse::default()));
+        let label = "tier=web|tier=db|cache".parse().unwrap();
+        assert_eq!(
+            check_not_running_containers(&has_containers_mock, &label)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|container| container.name)
+                .collect_vec(),
+            vec!["web", "db", "cache"]
+        );
+    }
+
+    #[tokio::test]
+    async fn podman_running_containers_are_filtered_on_their_health() {
+        let mut has_containers_mock = MockHasContainers::new();
+        has_containers_mock.expect_engine().return_const(Engine::Podman);
+        let filter = hashmap!["status" => vec!["running"]];
+        has_containers_mock
+            .expect_list_containers()
+            .withf(move |options| options.as_ref().unwrap().filters == filters(filter.clone()))
+            .times(2)
+            .returning(|_| {
+                Ok(["healthy", "unhealthy", "no_healthcheck"]
+                    .map(|name| ContainerSummary {
+                        names: Some(vec![name.to_string()]),
+                        ..Default::default()
+                    })
+                    .to_vec())
+            });
+        has_containers_mock.expect_inspect_container().returning(|name, _| {
+            let status = match name {
+                "healthy" => HealthStatusEnum::HEALTHY,
+                "unhealthy" => HealthStatusEnum::UNHEALTHY,
+                _ => HealthStatusEnum::EMPTY,
+            };
+            Ok(ContainerInspectResponse {
+                state: Some(ContainerState {
+                    health: Some(Health {
+                        status: Some(status),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+        });
+        let label = "!notifyhealth.ignore".parse().unwrap();
+        assert_eq!(
+            check_running_containers(&has_containers_mock, &label, false)
+                .await
+                .unwrap(),
+            vec![RunningContainerStatus {
+                name: "unhealthy".to_string(),
+                health: Some(HealthStatusEnum::UNHEALTHY),
+                ..Default::default()
+            }]
+        );
+        assert_eq!(
+            check_running_containers(&has_containers_mock, &label, true)
+                .await
+                .unwrap(),
+            vec![
+                RunningContainerStatus {
+                    name: "unhealthy".to_string(),
+                    health: Some(HealthStatusEnum::UNHEALTHY),
+                    ..Default::default()
+                },
+                RunningContainerStatus {
+                    name: "no_healthcheck".to_string(),
+                    health: None,
+                    ..Default::default()
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn container_names() {
+        let name = |names: Option<Vec<&str>>| {
+            get_container_name(&ContainerSummary {
+                id: Some("f00d".to_string()),
+                names: names.map(|names| names.into_iter().map(str::to_owned).collect()),
+                ..Default::default()
+            })
+            .to_owned()
+        };
+        assert_eq!(name(Some(vec!["/docker_name"])), "docker_name");
+        assert_eq!(name(Some(vec!["podman_name"])), "podman_name");
+        assert_eq!(name(Some(vec![])), "f00d");
+        assert_eq!(name(None), "f00d");
+    }
+
+    #[test]
+    fn compose_service_from_labels() {
+        let labels = hashmap![
+            COMPOSE_PROJECT_LABEL.to_owned() => "shop".to_owned(),
+            COMPOSE_SERVICE_LABEL.to_owned() => "web".to_owned(),
+            COMPOSE_CONTAINER_NUMBER_LABEL.to_owned() => "2".to_owned()
+        ];
+        assert_eq!(
+            ComposeService::from_labels(Some(&labels)),
+            Some(ComposeService {
+                project: "shop".to_owned(),
+                service: "web".to_owned(),
+                container_number: Some(2),
+            })
+        );
+        let without_service = hashmap![COMPOSE_PROJECT_LABEL.to_owned() => "shop".to_owned()];
+        assert_eq!(ComposeService::from_labels(Some(&without_service)), None);
+        assert_eq!(ComposeService::from_labels(None), None);
+    }
+
+    #[test]
+    fn monitoring_labels() {
+        let labels = hashmap![
+            IGNORE_LABEL.to_owned() => "false".to_owned(),
+            EXPECT_LABEL.to_owned() => "stopped".to_owned(),
+            GRACE_PERIOD_LABEL.to_owned() => "1m 30s".to_owned(),
+            SEVERITY_LABEL.to_owned() => "Critical".to_owned(),
+            TARGET_LABEL.to_owned() => "teams-backend".to_owned()
+        ];
+        assert_eq!(
+            MonitoringLabels::from_labels(Some(&labels), "test1"),
+            MonitoringLabels {
+                ignore: false,
+                expect_stopped: true,
+                grace_period: Some(Duration::from_secs(90)),
+                severity: Some(Severity::Critical),
+                target: Some("teams-backend".to_owned()),
+            }
+        );
+        let invalid_labels = hashmap![
+            IGNORE_LABEL.to_owned() => "yes please".to_owned(),
+            EXPECT_LABEL.to_owned() => "paused".to_owned(),
+            GRACE_PERIOD_LABEL.to_owned() => "soon".to_owned(),
+            SEVERITY_LABEL.to_owned() => "urgent".to_owned(),
+            TARGET_LABEL.to_owned() => " ".to_owned()
+        ];
+        assert_eq!(
+            MonitoringLabels::from_labels(Some(&invalid_labels), "test1"),
+            MonitoringLabels::default()
+        );
+        let ignored = hashmap![IGNORE_LABEL.to_owned() => "".to_owned()];
+        assert!(MonitoringLabels::from_labels(Some(&ignored), "test1").ignore);
+    }
+
+    #[test]
+    fn grace_period_is_from_the_docker_times() {
+        let now = humantime::parse_rfc3339("2024-01-01T10:05:00Z").unwrap();
+        let grace_period = Some(Duration::from_secs(300));
+        assert!(in_grace_period(grace_period, Some("2024-01-01T10:00:00.5Z"), now));
+        assert!(!in_grace_period(grace_period, Some("2024-01-01T10:00:00Z"), now));
+        assert!(!in_grace_period(grace_period, Some("0001-01-01T00:00:00Z"), now));
+        assert!(!in_grace_period(None, Some("2024-01-01T10:04:00Z"), now));
+    }
+
+    #[test]
+    fn containers_are_grouped_by_project() {
+        let container = |name: &str, project: Option<&str>| StoppedContainerStatus {
+            name: name.to_owned(),
+            compose: project.map(|project| ComposeService {
+                project: project.to_owned(),
+                service: name.to_owned(),
+                container_number: None,
+            }),
+            ..Default::default()
+        };
+        let containers = vec![
+            container("a", None),
+            container("b", Some("shop")),
+            container("c", Some("blog")),
+            container("d", Some("shop")),
+        ];
+        let names = by_project(&containers, StoppedContainerStatus::project)
+            .into_iter()
+            .map(|(project, group)| (project, group.into_iter().map(|c| c.name.as_str()).collect_vec()))
+            .collect_vec();
+        assert_eq!(
+            names,
+            vec![
+                (Some("blog"), vec!["c"]),
+                (Some("shop"), vec!["b", "d"]),
+                (None, vec!["a"])
+            ]
+        );
+    }
+
+    #[test]
+    fn check_status_from_containers() {
+        let running = vec![RunningContainerStatus {
+            name: "test1".to_string(),
+            health: None,
+            ..Default::default()
+        }];
+        let stopped = vec![StoppedContainerStatus {
+            name: "test2".to_string(),
+            status: Some("exited".to_string()),
+            ..Default::default()
+        }];
+        let unreachable = vec![UnreachableHost {
+            host: "host1".to_string(),
+            error: "connection refused".to_string(),
+        }];
+        let services = vec![ServiceStatus {
+            name: "web".to_string(),
+            desired_replicas: 2,
+            ..Default::default()
+        }];
+        assert_eq!(CheckStatus::from_containers(&[], &[], &[], &[]).exit_code(), 0);
+        assert_eq!(CheckStatus::from_containers(&running, &[], &[], &[]).exit_code(), 1);
+        assert_eq!(CheckStatus::from_containers(&[], &stopped, &[], &[]).exit_code(), 2);
+        assert_eq!(
+            CheckStatus::from_containers(&running, &stopped, &[], &[]).exit_code(),
+            2
+        );
+        assert_eq!(
+            CheckStatus::from_containers(&running, &[], &[], &services).exit_code(),
+            2
+        );
+        assert_eq!(
+            CheckStatus::from_containers(&running, &stopped, &unreachable, &[]).exit_code(),
+            3
+        );
+    }
 }
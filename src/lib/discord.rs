@@ -0,0 +1,371 @@
+use super::containers::with_severity;
+use super::notifier::Notification;
+use bollard::models::HealthStatusEnum;
+use itertools::Itertools;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const DANGER_COLOR: u32 = 0xe01e5a;
+const WARNING_COLOR: u32 = 0xecb22e;
+const NO_HEALTH_COLOR: u32 = 0x9e9e9e;
+const GOOD_COLOR: u32 = 0x2eb67d;
+// Limits from the Discord API, bigger messages are rejected.
+const MAX_FIELDS_PER_EMBED: usize = 25;
+const MAX_EMBEDS_PER_MESSAGE: usize = 10;
+const MAX_FIELD_NAME_LENGTH: usize = 256;
+const MAX_FIELD_VALUE_LENGTH: usize = 1024;
+/// The characters of the titles, field names and values of all the embeds of a message.
+const MAX_EMBED_LENGTH_PER_MESSAGE: usize = 6000;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    pub embeds: Vec<Embed>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Embed {
+    pub title: String,
+    pub color: u32,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Field {
+    pub name: String,
+    pub value: String,
+    pub inline: bool,
+}
+
+pub fn format_message(notification: &Notification) -> Result<Vec<Vec<u8>>, serde_json::Error> {
+    let Notification {
+        running_containers,
+        stopped_containers,
+        resolved_containers,
+        unreachable_hosts,
+        resolved_hosts,
+        services,
+        hostname,
+        ..
+    } = notification;
+    let mut embeds = vec![];
+    for (health_opt, group) in &running_containers.iter().group_by(|c| &c.health) {
+        match health_opt {
+            Some(health) => embeds.extend(split_embeds(
+                "The following running containers are not healthy:",
+                if *health == HealthStatusEnum::UNHEALTHY {
+                    DANGER_COLOR
+                } else {
+                    WARNING_COLOR
+                },
+                group
+                    .map(|c| field(&c.display_name(), with_severity(health.to_string(), c.severity)))
+                    .collect(),
+            )),
+            None => embeds.extend(split_embeds(
+                "The following running containers have no health status:",
+                NO_HEALTH_COLOR,
+                group
+                    .map(|c| {
+                        field(
+                            &c.display_name(),
+                            with_severity("no health status".to_owned(), c.severity),
+                        )
+                    })
+                    .collect(),
+            )),
+        }
+    }
+    if !stopped_containers.is_empty() {
+        embeds.extend(split_embeds(
+            "The following containers are not running:",
+            DANGER_COLOR,
+            stopped_containers
+                .iter()
+                .map(|c| field(&c.display_name(), with_severity(c.summary(), c.severity)))
+                .collect(),
+        ));
+    }
+    if !services.is_empty() {
+        embeds.extend(split_embeds(
+            "The following services are missing replicas:",
+            DANGER_COLOR,
+            services.iter().map(|s| field(&s.display_name(), s.summary())).collect(),
+        ));
+    }
+    if !unreachable_hosts.is_empty() {
+        embeds.extend(split_embeds(
+            "The following hosts could not be checked:",
+            DANGER_COLOR,
+            unreachable_hosts
+                .iter()
+                .map(|h| field(&h.host, h.error.clone()))
+                .collect(),
+        ));
+    }
+    if !resolved_containers.is_empty() {
+        embeds.extend(split_embeds(
+            "The following containers have recovered:",
+            GOOD_COLOR,
+            resolved_containers
+                .iter()
+                .map(|c| {
+                    let down_for = humantime::format_duration(Duration::from_secs(c.down_for_seconds));
+                    field(&c.display_name(), format!("{} for {down_for}", c.previous_status))
+                })
+                .collect(),
+        ));
+    }
+    if !resolved_hosts.is_empty() {
+        embeds.extend(split_embeds(
+            "The following hosts can be checked again:",
+            GOOD_COLOR,
+            resolved_hosts
+                .iter()
+                .map(|h| {
+                    let down_for = humantime::format_duration(Duration::from_secs(h.down_for_seconds));
+                    field(&h.host, format!("unreachable for {down_for}"))
+                })
+                .collect(),
+        ));
+    }
+    let mut content = if !notification.has_problems() {
+        "**Containers recovered! 😌**".to_owned()
+    } else {
+        "**Problem in containers! 🤕**".to_owned()
+    };
+    if let Some(hostname) = hostname {
+        content.push_str(&format!("\nServer: `{hostname}`."));
+    }
+    let mut content = Some(content);
+    let mut messages = vec![];
+    for chunk in split_messages(embeds) {
+        let msg = Message {
+            content: content.take(),
+            embeds: chunk,
+        };
+        info!("Message to be sent: {:?}", msg);
+        messages.push(serde_json::to_vec(&msg)?);
+    }
+    Ok(messages)
+}
+
+fn field(name: &str, value: String) -> Field {
+    Field {
+        name: truncate(name, MAX_FIELD_NAME_LENGTH),
+        value: truncate(&value, MAX_FIELD_VALUE_LENGTH),
+        inline: true,
+    }
+}
+
+/// Discord counts the characters, not the bytes.
+fn truncate(text: &str, max_length: usize) -> String {
+    if text.chars().count() <= max_length {
+        text.to_owned()
+    } else {
+        let mut truncated = text.chars().take(max_length - 1).collect::<String>();
+        truncated.push('…');
+        truncated
+    }
+}
+
+fn field_length(field: &Field) -> usize {
+    field.name.chars().count() + field.value.chars().count()
+}
+
+fn embed_length(embed: &Embed) -> usize {
+    embed.title.chars().count() + embed.fields.iter().map(field_length).sum::<usize>()
+}
+
+/// Splits the fields in embeds that fit in a message on their own.
+fn split_embeds(title: &str, color: u32, fields: Vec<Field>) -> Vec<Embed> {
+    let mut embeds: Vec<Embed> = vec![];
+    for field in fields {
+        let fits = embeds.last().is_some_and(|embed| {
+            embed.fields.len() < MAX_FIELDS_PER_EMBED
+                && embed_length(embed) + field_length(&field) <= MAX_EMBED_LENGTH_PER_MESSAGE
+        });
+        match embeds.last_mut() {
+            Some(embed) if fits => embed.fields.push(field),
+            _ => embeds.push(Embed {
+                title: if embeds.is_empty() {
+                    title.to_owned()
+                } else {
+                    format!("{title} (continued)")
+                },
+                color,
+                fields: vec![field],
+            }),
+        }
+    }
+    embeds
+}
+
+/// Splits the embeds in messages, as a message can only have so many embeds and characters in them.
+fn split_messages(embeds: Vec<Embed>) -> Vec<Vec<Embed>> {
+    let mut messages: Vec<Vec<Embed>> = vec![];
+    let mut message_length = 0;
+    for embed in embeds {
+        let length = embed_length(&embed);
+        match messages.last_mut() {
+            Some(message)
+                if message.len() < MAX_EMBEDS_PER_MESSAGE
+                    && message_length + length <= MAX_EMBED_LENGTH_PER_MESSAGE =>
+            {
+                message.push(embed);
+                message_length += length;
+            }
+            _ => {
+                messages.push(vec![embed]);
+                message_length = length;
+            }
+        }
+    }
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::containers::{RunningContainerStatus, StoppedContainerStatus};
+    use pretty_assertions::assert_eq;
+
+    fn stopped(count: usize) -> Vec<StoppedContainerStatus> {
+        (1..=count)
+            .map(|i| StoppedContainerStatus {
+                name: format!("test{i}"),
+                status: Some("exited".to_string()),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn check_message() {
+        let running_containers = vec![
+            RunningContainerStatus {
+                name: "test1".to_string(),
+                health: None,
+                ..Default::default()
+            },
+            RunningContainerStatus {
+                name: "test2".to_string(),
+                health: Some(HealthStatusEnum::UNHEALTHY),
+                ..Default::default()
+            },
+        ];
+        let stopped_containers = vec![StoppedContainerStatus {
+            name: "test3".to_string(),
+            status: None,
+            exit_code: Some(137),
+            oom_killed: true,
+            ..Default::default()
+        }];
+        let formatted_messages = format_message(&Notification {
+            running_containers,
+            stopped_containers,
+            hostname: Some("myhostname".to_owned()),
+            ..Default::default()
+        })
+        .unwrap();
+        let messages: Vec<Message> = formatted_messages
+            .iter()
+            .map(|bytes| serde_json::from_slice(bytes).unwrap())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![Message {
+                content: Some("**Problem in containers! 🤕**\nServer: `myhostname`.".to_owned()),
+                embeds: vec![
+                    Embed {
+                        title: "The following running containers have no health status:".to_owned(),
+                        color: NO_HEALTH_COLOR,
+                        fields: vec![field("test1", "no health status".to_owned())],
+                    },
+                    Embed {
+                        title: "The following running containers are not healthy:".to_owned(),
+                        color: DANGER_COLOR,
+                        fields: vec![field("test2", "unhealthy".to_owned())],
+                    },
+                    Embed {
+                        title: "The following containers are not running:".to_owned(),
+                        color: DANGER_COLOR,
+                        fields: vec![field("test3", "no status, exit code 137, out of memory".to_owned())],
+                    },
+                ],
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn embeds_are_split_at_25_fields() {
+        let formatted_messages = format_message(&Notification {
+            stopped_containers: stopped(26),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(formatted_messages.len(), 1);
+        let message: Message = serde_json::from_slice(&formatted_messages[0]).unwrap();
+        assert_eq!(message.embeds.len(), 2);
+        assert_eq!(message.embeds[0].fields.len(), 25);
+        assert_eq!(
+            message.embeds[1].title,
+            "The following containers are not running: (continued)"
+        );
+        assert_eq!(message.embeds[1].fields.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn messages_are_split_at_10_embeds() {
+        let formatted_messages = format_message(&Notification {
+            stopped_containers: stopped(11 * 25),
+            ..Default::default()
+        })
+        .unwrap();
+        let messages: Vec<Message> = formatted_messages
+            .iter()
+            .map(|bytes| serde_json::from_slice(bytes).unwrap())
+            .collect();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].embeds.len(), 10);
+        assert!(messages[0].content.is_some());
+        assert_eq!(messages[1].embeds.len(), 1);
+        assert_eq!(messages[1].content, None);
+    }
+
+    #[tokio::test]
+    async fn messages_are_split_at_6000_characters() {
+        let stopped_containers = (1..=12)
+            .map(|i| StoppedContainerStatus {
+                name: format!("test{i}"),
+                status: Some("x".repeat(2000)),
+                ..Default::default()
+            })
+            .collect();
+        let formatted_messages = format_message(&Notification {
+            stopped_containers,
+            ..Default::default()
+        })
+        .unwrap();
+        let messages: Vec<Message> = formatted_messages
+            .iter()
+            .map(|bytes| serde_json::from_slice(bytes).unwrap())
+            .collect();
+        // Five fields of 1024 characters fill an embed, and a message has room for only one of them.
+        assert_eq!(
+            messages
+                .iter()
+                .map(|message| message.embeds.iter().map(|embed| embed.fields.len()).collect())
+                .collect::<Vec<Vec<_>>>(),
+            vec![vec![5], vec![5], vec![2]]
+        );
+        for message in &messages {
+            assert!(message.embeds.iter().map(embed_length).sum::<usize>() <= MAX_EMBED_LENGTH_PER_MESSAGE);
+        }
+        let value = &messages[0].embeds[0].fields[0].value;
+        assert_eq!(value.chars().count(), MAX_FIELD_VALUE_LENGTH);
+        assert!(value.ends_with('…'));
+    }
+}
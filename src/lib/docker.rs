@@ -0,0 +1,402 @@
+use super::containers::{Containers, Engine, HasContainers};
+use async_trait::async_trait;
+use bollard::errors::Error;
+use bollard::models::{ContainerInspectResponse, ContainerSummary, EventMessage};
+use bollard::query_parameters::{EventsOptions, InspectContainerOptions, ListContainersOptions};
+use bollard::{Docker, API_DEFAULT_VERSION};
+use futures::stream::{self, BoxStream, StreamExt};
+use hyper::Uri;
+use log::*;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+const TIMEOUT_SECONDS: u64 = 120;
+const REMOTE_SOCKET: &str = "/var/run/docker.sock";
+const ROOTFUL_PODMAN_SOCKET: &str = "/run/podman/podman.sock";
+const TUNNEL_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Where the Docker daemon is, from `--docker-host` or `DOCKER_HOST`, with the same url schemes as the Docker CLI.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum DockerHost {
+    /// The default socket of the platform, or the one of Podman when there is no Docker socket.
+    Local,
+    Unix(PathBuf),
+    /// `cert_path` is set when the connection uses TLS, with the client certificates in it.
+    Tcp {
+        address: String,
+        cert_path: Option<PathBuf>,
+    },
+    /// Reached through an `ssh` tunnel to the Docker socket of the remote host.
+    Ssh {
+        destination: String,
+        port: Option<u16>,
+    },
+}
+
+impl DockerHost {
+    pub fn new(host: Option<&str>, cert_path: Option<PathBuf>, tls_verify: bool) -> Result<DockerHost, String> {
+        let host = match host {
+            Some(host) if !host.is_empty() => host,
+            _ => return Ok(DockerHost::Local),
+        };
+        if let Some(path) = host.strip_prefix("unix://") {
+            return Ok(DockerHost::Unix(PathBuf::from(path)));
+        }
+        let uri = host
+            .parse::<Uri>()
+            .map_err(|err| format!("Invalid Docker host `{host}`: {err}"))?;
+        let authority = uri
+            .authority()
+            .ok_or_else(|| format!("Invalid Docker host `{host}`, it has no host name."))?;
+        match uri.scheme_str() {
+            Some("tcp") => {
+                let cert_path =
+                    if tls_verify {
+                        Some(cert_path.or_else(default_cert_path).ok_or(
+                            "The directory with the TLS certificates could not be found, use --docker-cert-path.",
+                        )?)
+                    } else {
+                        None
+                    };
+                let port = authority
+                    .port_u16()
+                    .unwrap_or(if cert_path.is_some() { 2376 } else { 2375 });
+                Ok(DockerHost::Tcp {
+                    address: format!("{}:{port}", authority.host()),
+                    cert_path,
+                })
+            }
+            Some("ssh") => {
+                let destination = match authority.port() {
+                    Some(port) => authority.as_str().trim_end_matches(port.as_str()).trim_end_matches(':'),
+                    None => authority.as_str(),
+                };
+                Ok(DockerHost::Ssh {
+                    destination: destination.to_owned(),
+                    port: authority.port_u16(),
+                })
+            }
+            _ => Err(format!(
+                "Unsupported Docker host `{host}`, use a unix://, tcp:// or ssh:// url."
+            )),
+        }
+    }
+}
+
+fn default_cert_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| Path::new(&home).join(".docker"))
+}
+
+/// The Docker client, with the ssh tunnel it goes through, which is closed when this is dropped.
+pub struct DockerConnection {
+    pub docker: Docker,
+    tunnel: Option<SshTunnel>,
+}
+
+impl DockerConnection {
+    /// How the ssh tunnel exited, when it did, after which the connection can't be used anymore.
+    fn tunnel_exit(&mut self) -> Option<ExitStatus> {
+        self.tunnel.as_mut().and_then(SshTunnel::exit_status)
+    }
+}
+
+/// A Docker host that is connected to when it is first used, and again on the next use after connecting failed or its
+/// ssh tunnel exited, so a host that is down is checked again once it is back. The containers are also the events of
+/// the host, which connect to it again when they are subscribed to again.
+#[derive(Clone)]
+pub struct HostConnection {
+    host: DockerHost,
+    connected: Arc<Mutex<Option<(DockerConnection, Containers)>>>,
+}
+
+impl HostConnection {
+    pub fn new(host: DockerHost) -> HostConnection {
+        HostConnection {
+            host,
+            connected: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub async fn containers(&self) -> Result<Containers, String> {
+        let mut connected = self.connected.lock().await;
+        if let Some(status) = connected.as_mut().and_then(|(connection, _)| connection.tunnel_exit()) {
+            warn!(
+                "The ssh tunnel to {:?} exited with {status}, connecting again.",
+                self.host
+            );
+            *connected = None;
+        }
+        if let Some((_, containers)) = connected.as_ref() {
+            return Ok(containers.clone());
+        }
+        let connection = connect(&self.host).await.map_err(|err| err.to_string())?;
+        let containers = Containers::detect(connection.docker.clone())
+            .await
+            .map_err(|err| err.to_string())?;
+        *connected = Some((connection, containers.clone()));
+        Ok(containers)
+    }
+}
+
+/// The connection errors are returned as IO errors, as the Docker API has none of its own for them.
+#[async_trait]
+impl HasContainers for HostConnection {
+    async fn list_containers(&self, options: Option<ListContainersOptions>) -> Result<Vec<ContainerSummary>, Error> {
+        self.containers()
+            .await
+            .map_err(connection_error)?
+            .list_containers(options)
+            .await
+    }
+    async fn inspect_container<'a>(
+        &'a self,
+        container_name: &'a str,
+        options: Option<InspectContainerOptions>,
+    ) -> Result<ContainerInspectResponse, Error> {
+        self.containers()
+            .await
+            .map_err(connection_error)?
+            .inspect_container(container_name, options)
+            .await
+    }
+    fn events(&self, options: Option<EventsOptions>) -> BoxStream<'static, Result<EventMessage, Error>> {
+        let host = self.clone();
+        stream::once(async move { host.containers().await })
+            .flat_map(move |containers| match containers {
+                Ok(containers) => containers.events(options.clone()),
+                Err(err) => stream::once(async move { Err(connection_error(err)) }).boxed(),
+            })
+            .boxed()
+    }
+    /// The engine is only known once connected, before that it is taken as Docker.
+    fn engine(&self) -> Engine {
+        match self.connected.try_lock() {
+            Ok(connected) => connected
+                .as_ref()
+                .map(|(_, containers)| containers.engine())
+                .unwrap_or_default(),
+            Err(_) => Engine::default(),
+        }
+    }
+}
+
+fn connection_error(err: String) -> Error {
+    Error::IOError {
+        err: std::io::Error::other(err),
+    }
+}
+
+pub async fn connect(host: &DockerHost) -> Result<DockerConnection, Box<dyn std::error::Error>> {
+    info!("Connecting to Docker at {:?}.", host);
+    let (docker, tunnel) = match host {
+        #[cfg(unix)]
+        DockerHost::Local => match podman_socket() {
+            Some(path) => (connect_with_unix(&path)?, None),
+            None => (Docker::connect_with_socket_defaults()?, None),
+        },
+        #[cfg(not(unix))]
+        DockerHost::Local => (Docker::connect_with_socket_defaults()?, None),
+        #[cfg(unix)]
+        DockerHost::Unix(path) => (connect_with_unix(path)?, None),
+        DockerHost::Tcp {
+            address,
+            cert_path: None,
+        } => (
+            Docker::connect_with_http(address, TIMEOUT_SECONDS, API_DEFAULT_VERSION)?,
+            None,
+        ),
+        DockerHost::Tcp {
+            address,
+            cert_path: Some(cert_path),
+        } => (
+            Docker::connect_with_ssl(
+                address,
+                &cert_path.join("key.pem"),
+                &cert_path.join("cert.pem"),
+                &cert_path.join("ca.pem"),
+                TIMEOUT_SECONDS,
+                API_DEFAULT_VERSION,
+            )?,
+            None,
+        ),
+        #[cfg(unix)]
+        DockerHost::Ssh { destination, port } => {
+            let tunnel = SshTunnel::open(destination, *port).await?;
+            (connect_with_unix(&tunnel.socket)?, Some(tunnel))
+        }
+        #[cfg(not(unix))]
+        _ => return Err(format!("Docker host {:?} is only supported on Unix.", host).into()),
+    };
+    Ok(DockerConnection { docker, tunnel })
+}
+
+/// The socket of Podman, rootless then rootful, when there is no Docker socket.
+#[cfg(unix)]
+fn podman_socket() -> Option<PathBuf> {
+    if Path::new(REMOTE_SOCKET).exists() {
+        return None;
+    }
+    let rootless = std::env::var_os("XDG_RUNTIME_DIR").map(|dir| Path::new(&dir).join("podman/podman.sock"));
+    let socket = rootless
+        .into_iter()
+        .chain([PathBuf::from(ROOTFUL_PODMAN_SOCKET)])
+        .find(|path| path.exists());
+    if let Some(path) = &socket {
+        info!("No Docker socket, using the Podman socket {}.", path.display());
+    }
+    socket
+}
+
+#[cfg(unix)]
+fn connect_with_unix(path: &Path) -> Result<Docker, bollard::errors::Error> {
+    Docker::connect_with_unix(&path.to_string_lossy(), TIMEOUT_SECONDS, API_DEFAULT_VERSION)
+}
+
+/// An `ssh` process forwarding a local socket to the Docker socket of the remote host, as the Docker API client does
+/// not talk ssh itself. It uses the ssh configuration and keys of the user, and can't ask for passwords.
+struct SshTunnel {
+    socket: PathBuf,
+    process: tokio::process::Child,
+}
+
+impl SshTunnel {
+    async fn open(destination: &str, port: Option<u16>) -> Result<SshTunnel, Box<dyn std::error::Error>> {
+        static TUNNELS: AtomicUsize = AtomicUsize::new(0);
+        let socket = std::env::temp_dir().join(format!(
+            "notifyhealth-{}-{}.sock",
+            std::process::id(),
+            TUNNELS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_file(&socket);
+        let mut command = tokio::process::Command::new("ssh");
+        command
+            .args(["-nNT", "-o", "BatchMode=yes", "-o", "ExitOnForwardFailure=yes", "-L"])
+            .arg(format!("{}:{REMOTE_SOCKET}", socket.display()));
+        if let Some(port) = port {
+            command.args(["-p", &port.to_string()]);
+        }
+        let mut process = command
+            .arg(destination)
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| format!("Could not run ssh to connect to {destination}: {err}"))?;
+        let started = std::time::Instant::now();
+        while !socket.exists() {
+            if let Some(status) = process.try_wait()? {
+                return Err(format!("The ssh tunnel to {destination} exited with {status}.").into());
+            }
+            if started.elapsed() > TUNNEL_TIMEOUT {
+                return Err(format!("The ssh tunnel to {destination} was not ready in time.").into());
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        debug!("Ssh tunnel to {destination} listening on {}.", socket.display());
+        Ok(SshTunnel { socket, process })
+    }
+
+    fn exit_status(&mut self) -> Option<ExitStatus> {
+        self.process.try_wait().ok().flatten()
+    }
+}
+
+impl Drop for SshTunnel {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.socket);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn local_socket_is_the_default() {
+        assert_eq!(DockerHost::new(None, None, false).unwrap(), DockerHost::Local);
+        assert_eq!(DockerHost::new(Some(""), None, false).unwrap(), DockerHost::Local);
+    }
+
+    #[test]
+    fn unix_socket() {
+        assert_eq!(
+            DockerHost::new(Some("unix:///run/user/1000/docker.sock"), None, false).unwrap(),
+            DockerHost::Unix(PathBuf::from("/run/user/1000/docker.sock"))
+        );
+    }
+
+    #[test]
+    fn tcp_with_and_without_tls() {
+        assert_eq!(
+            DockerHost::new(Some("tcp://docker.example.com"), None, false).unwrap(),
+            DockerHost::Tcp {
+                address: "docker.example.com:2375".to_owned(),
+                cert_path: None
+            }
+        );
+        assert_eq!(
+            DockerHost::new(Some("tcp://docker.example.com"), Some(PathBuf::from("/certs")), true).unwrap(),
+            DockerHost::Tcp {
+                address: "docker.example.com:2376".to_owned(),
+                cert_path: Some(PathBuf::from("/certs"))
+            }
+        );
+        assert_eq!(
+            DockerHost::new(Some("tcp://10.0.0.2:3000"), Some(PathBuf::from("/certs")), false).unwrap(),
+            DockerHost::Tcp {
+                address: "10.0.0.2:3000".to_owned(),
+                cert_path: None
+            }
+        );
+    }
+
+    #[test]
+    fn ssh() {
+        assert_eq!(
+            DockerHost::new(Some("ssh://admin@docker.example.com:2222"), None, false).unwrap(),
+            DockerHost::Ssh {
+                destination: "admin@docker.example.com".to_owned(),
+                port: Some(2222)
+            }
+        );
+        assert_eq!(
+            DockerHost::new(Some("ssh://docker.example.com"), None, false).unwrap(),
+            DockerHost::Ssh {
+                destination: "docker.example.com".to_owned(),
+                port: None
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn host_is_connected_to_again_after_failing() {
+        let mut server = mockito::Server::new_async().await;
+        let host = HostConnection::new(DockerHost::Tcp {
+            address: server.host_with_port(),
+            cert_path: None,
+        });
+        let unavailable = server.mock("GET", "/version").with_status(500).create_async().await;
+        assert!(host.containers().await.is_err());
+        unavailable.remove_async().await;
+        let version = server
+            .mock("GET", "/version")
+            .with_body(r#"{"Version":"24.0.7","Components":[{"Name":"Engine","Version":"24.0.7"}]}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        assert_eq!(host.containers().await.unwrap().engine(), Engine::Docker);
+        assert_eq!(host.containers().await.unwrap().engine(), Engine::Docker);
+        version.assert_async().await;
+    }
+
+    #[test]
+    fn unsupported_scheme() {
+        assert_eq!(
+            DockerHost::new(Some("http://docker.example.com"), None, false).unwrap_err(),
+            "Unsupported Docker host `http://docker.example.com`, use a unix://, tcp:// or ssh:// url."
+        );
+    }
+}
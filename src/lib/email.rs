@@ -0,0 +1,337 @@
+use super::containers::with_severity;
+use super::notifier::{with_hostname, Notification, Notifier};
+use super::print;
+use lettre::message::{header::ContentType, Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use log::*;
+use serde::Deserialize;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(clap::ValueEnum, Debug, PartialEq, Eq, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Plain text connection, only for local relays.
+    None,
+    /// Upgrades the connection with STARTTLS, usually on port 587.
+    #[default]
+    Starttls,
+    /// Implicit TLS, usually on port 465.
+    Tls,
+}
+
+#[derive(clap::Args, PartialEq, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EmailSettings {
+    #[clap(long, help = "SMTP server host name")]
+    pub smtp_host: String,
+    #[clap(long, help = "SMTP server port, defaults to the port of the security mode")]
+    pub smtp_port: Option<u16>,
+    #[clap(
+        long,
+        value_enum,
+        default_value_t,
+        help = "How the connection to the SMTP server is secured"
+    )]
+    #[serde(default)]
+    pub smtp_security: SmtpSecurity,
+    #[clap(long, help = "User name to authenticate to the SMTP server")]
+    pub smtp_username: Option<String>,
+    #[clap(
+        long,
+        help = "Password to authenticate to the SMTP server",
+        env = "NOTIFYHEALTH_SMTP_PASSWORD"
+    )]
+    pub smtp_password: Option<String>,
+    #[clap(long, help = "Sender address")]
+    pub from: String,
+    #[clap(long, required = true, help = "Recipient address, can be repeated")]
+    pub to: Vec<String>,
+}
+
+/// The settings are logged, so the password is left out.
+impl fmt::Debug for EmailSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EmailSettings")
+            .field("smtp_host", &self.smtp_host)
+            .field("smtp_port", &self.smtp_port)
+            .field("smtp_security", &self.smtp_security)
+            .field("smtp_username", &self.smtp_username)
+            .field("smtp_password", &self.smtp_password.as_ref().map(|_| "***"))
+            .field("from", &self.from)
+            .field("to", &self.to)
+            .finish()
+    }
+}
+
+pub struct EmailNotifier {
+    name: String,
+    settings: EmailSettings,
+}
+
+impl EmailNotifier {
+    pub fn new(name: String, settings: EmailSettings) -> Self {
+        EmailNotifier { name, settings }
+    }
+
+    fn transport(&self) -> Result<SmtpTransport, Box<dyn std::error::Error>> {
+        let settings = &self.settings;
+        let mut builder = match settings.smtp_security {
+            SmtpSecurity::None => SmtpTransport::builder_dangerous(&settings.smtp_host),
+            SmtpSecurity::Starttls => SmtpTransport::starttls_relay(&settings.smtp_host)?,
+            SmtpSecurity::Tls => SmtpTransport::relay(&settings.smtp_host)?,
+        };
+        if let Some(port) = settings.smtp_port {
+            builder = builder.port(port);
+        }
+        if let Some(username) = &settings.smtp_username {
+            builder = builder.credentials(Credentials::new(
+                username.clone(),
+                settings.smtp_password.clone().unwrap_or_default(),
+            ));
+        }
+        Ok(builder.build())
+    }
+}
+
+impl Notifier for EmailNotifier {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn notify(&self, notification: &Notification) -> Result<(), Box<dyn std::error::Error>> {
+        if notification.is_empty() {
+            return Ok(());
+        }
+        let mut builder = Message::builder()
+            .from(self.settings.from.parse::<Mailbox>()?)
+            .subject(subject(notification));
+        for to in &self.settings.to {
+            builder = builder.to(to.parse::<Mailbox>()?);
+        }
+        let email = builder.multipart(
+            MultiPart::alternative()
+                .singlepart(
+                    SinglePart::builder()
+                        .header(ContentType::TEXT_PLAIN)
+                        .body(format_text(notification)),
+                )
+                .singlepart(
+                    SinglePart::builder()
+                        .header(ContentType::TEXT_HTML)
+                        .body(format_html(notification)),
+                ),
+        )?;
+        let response = self.transport()?.send(&email)?;
+        info!("Email sent, response: {:?}", response.message().collect::<Vec<_>>());
+        Ok(())
+    }
+}
+
+fn subject(notification: &Notification) -> String {
+    let subject = if !notification.has_problems() {
+        "Containers recovered"
+    } else {
+        "Problem in containers"
+    };
+    with_hostname(subject.to_owned(), notification.hostname.as_deref())
+}
+
+/// The groups of containers in the email, with a title and a line for each container.
+fn groups(notification: &Notification) -> Vec<(String, Vec<String>)> {
+    let mut groups = print::running_container_groups(&notification.running_containers);
+    if !notification.stopped_containers.is_empty() {
+        groups.push((
+            "The following containers are stopped:".to_owned(),
+            notification
+                .stopped_containers
+                .iter()
+                .map(|c| {
+                    let line = match &c.status {
+                        Some(status) => format!("{} ({status})", c.display_name()),
+                        None => c.display_name(),
+                    };
+                    with_severity(line, c.severity)
+                })
+                .collect(),
+        ));
+    }
+    if !notification.services.is_empty() {
+        groups.push((
+            "The following services are missing replicas:".to_owned(),
+            notification
+                .services
+                .iter()
+                .map(|s| format!("{} ({})", s.display_name(), s.summary()))
+                .collect(),
+        ));
+    }
+    if !notification.unreachable_hosts.is_empty() {
+        groups.push((
+            "The following hosts could not be checked:".to_owned(),
+            notification
+                .unreachable_hosts
+                .iter()
+                .map(|h| format!("{} ({})", h.host, h.error))
+                .collect(),
+        ));
+    }
+    if !notification.resolved_containers.is_empty() {
+        groups.push((
+            "The following containers have recovered:".to_owned(),
+            notification
+                .resolved_containers
+                .iter()
+                .map(|c| {
+                    let down_for = humantime::format_duration(Duration::from_secs(c.down_for_seconds));
+                    format!("{} ({} for {down_for})", c.display_name(), c.previous_status)
+                })
+                .collect(),
+        ));
+    }
+    if !notification.resolved_hosts.is_empty() {
+        groups.push((
+            "The following hosts can be checked again:".to_owned(),
+            notification
+                .resolved_hosts
+                .iter()
+                .map(|h| {
+                    let down_for = humantime::format_duration(Duration::from_secs(h.down_for_seconds));
+                    format!("{} (unreachable for {down_for})", h.host)
+                })
+                .collect(),
+        ));
+    }
+    groups
+}
+
+fn format_text(notification: &Notification) -> String {
+    let mut text = format!("{}.\n", subject(notification));
+    for (title, lines) in groups(notification) {
+        text.push_str(&format!("\n{title}\n"));
+        for line in lines {
+            text.push_str(&format!("- {line}\n"));
+        }
+    }
+    text
+}
+
+fn format_html(notification: &Notification) -> String {
+    let mut html = format!("<html><body>\n<h1>{}</h1>\n", escape_html(&subject(notification)));
+    for (title, lines) in groups(notification) {
+        html.push_str(&format!("<h2>{}</h2>\n<ul>\n", escape_html(&title)));
+        for line in lines {
+            html.push_str(&format!("<li>{}</li>\n", escape_html(&line)));
+        }
+        html.push_str("</ul>\n");
+    }
+    html.push_str("</body></html>\n");
+    html
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::containers::{ResolvedContainerStatus, RunningContainerStatus, StoppedContainerStatus};
+    use bollard::models::HealthStatusEnum;
+    use pretty_assertions::assert_eq;
+
+    fn notification() -> Notification {
+        Notification {
+            running_containers: vec![RunningContainerStatus {
+                name: "test1".to_string(),
+                health: Some(HealthStatusEnum::UNHEALTHY),
+                ..Default::default()
+            }],
+            stopped_containers: vec![StoppedContainerStatus {
+                name: "test2".to_string(),
+                status: Some("exited".to_string()),
+                ..Default::default()
+            }],
+            resolved_containers: vec![ResolvedContainerStatus {
+                name: "<test3>".to_string(),
+                previous_status: "stopped".to_string(),
+                down_for_seconds: 90,
+                ..Default::default()
+            }],
+            hostname: Some("myhostname".to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn check_text() {
+        assert_eq!(
+            format_text(&notification()),
+            "Problem in containers on myhostname.
+
+Running, unhealthy containers:
+- test1
+
+The following containers are stopped:
+- test2 (exited)
+
+The following containers have recovered:
+- <test3> (stopped for 1m 30s)
+"
+        );
+    }
+
+    #[test]
+    fn check_html() {
+        assert_eq!(
+            format_html(&notification()),
+            "<html><body>
+<h1>Problem in containers on myhostname</h1>
+<h2>Running, unhealthy containers:</h2>
+<ul>
+<li>test1</li>
+</ul>
+<h2>The following containers are stopped:</h2>
+<ul>
+<li>test2 (exited)</li>
+</ul>
+<h2>The following containers have recovered:</h2>
+<ul>
+<li>&lt;test3&gt; (stopped for 1m 30s)</li>
+</ul>
+</body></html>
+"
+        );
+    }
+
+    #[test]
+    fn subject_for_recovered_containers() {
+        let recovered = Notification {
+            running_containers: vec![],
+            stopped_containers: vec![],
+            hostname: None,
+            ..notification()
+        };
+        assert_eq!(subject(&recovered), "Containers recovered");
+    }
+
+    #[test]
+    fn debug_hides_password() {
+        let settings = EmailSettings {
+            smtp_host: "smtp.example.com".to_owned(),
+            smtp_port: None,
+            smtp_security: SmtpSecurity::Starttls,
+            smtp_username: Some("user".to_owned()),
+            smtp_password: Some("secret-password".to_owned()),
+            from: "notifyhealth@example.com".to_owned(),
+            to: vec!["admin@example.com".to_owned()],
+        };
+        let debug = format!("{:?}", settings);
+        assert!(!debug.contains("secret-password"));
+        assert!(debug.contains("smtp_password: Some(\"***\")"));
+    }
+}
@@ -0,0 +1,395 @@
+use super::containers::{
+    ComposeService, HasContainers, MonitoringLabels, RunningContainerStatus, StoppedContainerStatus,
+};
+use super::selector::LabelSelector;
+use bollard::errors::Error;
+use bollard::models::{EventMessage, EventMessageTypeEnum, HealthStatusEnum};
+use bollard::query_parameters::EventsOptions;
+use futures::prelude::*;
+use futures::stream::{self, BoxStream};
+use log::*;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// A container stopping sends `kill`, `die` and `stop`, and `kill` is also used to send it signals, e.g. `HUP` to
+/// reload its configuration, so only `die` tells that it stopped. It has the exit code, and an `oom` comes with a `die`
+/// too, so it is left to the checks, which inspect the container, to tell that it ran out of memory.
+const STOPPED_ACTIONS: [&str; 1] = ["die"];
+/// The container is running again, so it is checked again to resolve it.
+const RECHECK_ACTIONS: [&str; 2] = ["start", "restart"];
+/// How long to wait before subscribing to the events again after a failure, doubled for each failure in a row up to
+/// the maximum.
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+const MAX_RESUBSCRIBE_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ContainerEvent {
+    Running(RunningContainerStatus),
+    Stopped(StoppedContainerStatus),
+    /// The container started or became healthy, and has to be checked to know if it recovered.
+    Recheck {
+        name: String,
+        host: Option<String>,
+    },
+}
+
+impl ContainerEvent {
+    pub fn on_host(self, host: &str) -> ContainerEvent {
+        match self {
+            ContainerEvent::Running(mut container) => {
+                container.host = Some(host.to_owned());
+                ContainerEvent::Running(container)
+            }
+            ContainerEvent::Stopped(mut container) => {
+                container.host = Some(host.to_owned());
+                ContainerEvent::Stopped(container)
+            }
+            ContainerEvent::Recheck { name, .. } => ContainerEvent::Recheck {
+                name,
+                host: Some(host.to_owned()),
+            },
+        }
+    }
+}
+
+/// The health, stop and start events of the containers matching the selector, whose labels are in the event
+/// attributes. The problems of the containers with a grace period are left to the checks, as an event can't tell how
+/// long the container has been up or down.
+pub fn container_events(
+    docker: &dyn HasContainers,
+    label: &LabelSelector,
+) -> BoxStream<'static, Result<ContainerEvent, Error>> {
+    let label = label.clone();
+    let mut actions = vec!["health_status".to_owned()];
+    actions.extend(STOPPED_ACTIONS.iter().map(|action| action.to_string()));
+    actions.extend(RECHECK_ACTIONS.iter().map(|action| action.to_string()));
+    let mut filters = hashmap!["type".to_owned() => vec!["container".to_owned()], "event".to_owned() => actions];
+    let labels = label.docker_filter();
+    if !labels.is_empty() {
+        filters.insert("label".to_owned(), labels);
+    }
+    docker
+        .events(Some(EventsOptions {
+            filters: Some(filters),
+            ..Default::default()
+        }))
+        .try_filter_map(move |event| future::ok(to_container_event(&event, &label)))
+        .boxed()
+}
+
+/// The events of [`container_events`], subscribing to them again with a backoff when the stream fails or ends, e.g.
+/// when Docker restarts, so they are listened to for as long as notifyhealth runs. The failures are only logged, with
+/// the `host` the events are from when several hosts are checked.
+pub fn resubscribed_container_events<D>(
+    docker: D,
+    label: LabelSelector,
+    host: Option<String>,
+) -> BoxStream<'static, ContainerEvent>
+where
+    D: HasContainers + Send + Sync + 'static,
+{
+    let source = match host {
+        Some(hostname) => format!("the Docker host {hostname}"),
+        None => "Docker".to_owned(),
+    };
+    let subscription: Option<BoxStream<'static, Result<ContainerEvent, Error>>> = None;
+    stream::unfold(
+        (docker, label, source, subscription, 0),
+        |(docker, label, source, mut subscription, mut failures)| async move {
+            loop {
+                let events = subscription.get_or_insert_with(|| container_events(&docker, &label));
+                let delay = resubscribe_delay(failures + 1);
+                match events.next().await {
+                    Some(Ok(event)) => return Some((event, (docker, label, source, subscription, 0))),
+                    Some(Err(err)) => error!(
+                        "Error listening to the events of {source}: {err}, subscribing again in {}.",
+                        humantime::format_duration(delay)
+                    ),
+                    None => warn!(
+                        "The events of {source} ended, subscribing again in {}.",
+                        humantime::format_duration(delay)
+                    ),
+                }
+                subscription = None;
+                failures += 1;
+                tokio::time::sleep(delay).await;
+            }
+        },
+    )
+    .boxed()
+}
+
+fn resubscribe_delay(failures: u32) -> Duration {
+    RESUBSCRIBE_DELAY
+        .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .min(MAX_RESUBSCRIBE_DELAY)
+}
+
+fn to_container_event(event: &EventMessage, label: &LabelSelector) -> Option<ContainerEvent> {
+    if event.typ != Some(EventMessageTypeEnum::CONTAINER) {
+        return None;
+    }
+    let action = event.action.as_deref()?;
+    let actor = event.actor.as_ref()?;
+    let empty_attributes = HashMap::new();
+    let attributes = actor.attributes.as_ref().unwrap_or(&empty_attributes);
+    let name = match attributes.get("name").or(actor.id.as_ref()) {
+        Some(name) => name.to_owned(),
+        None => return None,
+    };
+    debug!("Received event '{action}' for container {name}.");
+    let monitoring = MonitoringLabels::from_labels(Some(attributes), &name);
+    if !label.matches(Some(attributes)) || monitoring.ignore {
+        return None;
+    }
+    let health = action.strip_prefix("health_status:").map(str::trim);
+    if RECHECK_ACTIONS.contains(&action) || health == Some("healthy") {
+        Some(ContainerEvent::Recheck { name, host: None })
+    } else if monitoring.grace_period.is_some() {
+        None
+    } else if health == Some("unhealthy") {
+        Some(ContainerEvent::Running(RunningContainerStatus {
+            name,
+            health: Some(HealthStatusEnum::UNHEALTHY),
+            compose: ComposeService::from_labels(Some(attributes)),
+            severity: monitoring.severity,
+            target: monitoring.target,
+            ..Default::default()
+        }))
+    } else if STOPPED_ACTIONS.contains(&action) && !monitoring.expect_stopped {
+        Some(ContainerEvent::Stopped(StoppedContainerStatus {
+            name,
+            status: Some("exited".to_owned()),
+            compose: ComposeService::from_labels(Some(attributes)),
+            severity: monitoring.severity,
+            target: monitoring.target,
+            exit_code: attributes.get("exitCode").and_then(|exit_code| exit_code.parse().ok()),
+            image: attributes.get("image").cloned(),
+            ..Default::default()
+        }))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::containers::{MockHasContainers, Severity};
+    use bollard::models::EventActor;
+    use pretty_assertions::assert_eq;
+
+    fn event(action: &str, attributes: HashMap<String, String>) -> EventMessage {
+        EventMessage {
+            typ: Some(EventMessageTypeEnum::CONTAINER),
+            action: Some(action.to_owned()),
+            actor: Some(EventActor {
+                id: Some("abc123".to_owned()),
+                attributes: Some(attributes),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn container_events_are_converted_to_statuses() {
+        let mut has_containers_mock = MockHasContainers::new();
+        has_containers_mock
+            .expect_events()
+            .withf(|options| {
+                let opt = options.as_ref().unwrap();
+                let filters = opt.filters.as_ref().unwrap();
+                filters["type"] == vec!["container"]
+                    && filters["event"] == vec!["health_status", "die", "start", "restart"]
+                    && filters["label"] == vec!["test_label"]
+            })
+            .times(1)
+            .returning(|_| {
+                stream::iter(vec![
+                    Ok(event(
+                        "health_status: unhealthy",
+                        hashmap!["name".to_owned() => "test1".to_owned(), "test_label".to_owned() => "".to_owned()],
+                    )),
+                    Ok(event(
+                        "health_status: healthy",
+                        hashmap!["name".to_owned() => "test2".to_owned(), "test_label".to_owned() => "".to_owned()],
+                    )),
+                    Ok(event(
+                        "health_status: unhealthy",
+                        hashmap!["name".to_owned() => "test5".to_owned()],
+                    )),
+                    Ok(event(
+                        "die",
+                        hashmap!["name".to_owned() => "test3".to_owned(), "test_label".to_owned() => "".to_owned(), "exitCode".to_owned() => "137".to_owned()],
+                    )),
+                    Ok(event("oom", hashmap!["name".to_owned() => "test4".to_owned()])),
+                ])
+                .boxed()
+            });
+        let events: Vec<ContainerEvent> = container_events(&has_containers_mock, &"test_label".parse().unwrap())
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(
+            events,
+            vec![
+                ContainerEvent::Running(RunningContainerStatus {
+                    name: "test1".to_owned(),
+                    health: Some(HealthStatusEnum::UNHEALTHY),
+                    ..Default::default()
+                }),
+                ContainerEvent::Recheck {
+                    name: "test2".to_owned(),
+                    host: None,
+                },
+                ContainerEvent::Stopped(StoppedContainerStatus {
+                    name: "test3".to_owned(),
+                    status: Some("exited".to_owned()),
+                    exit_code: Some(137),
+                    ..Default::default()
+                }),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn events_are_resubscribed_after_an_error() {
+        let mut has_containers_mock = MockHasContainers::new();
+        let mut subscriptions = vec![
+            vec![
+                Ok(event(
+                    "die",
+                    hashmap!["name".to_owned() => "test1".to_owned(), "test_label".to_owned() => "".to_owned()],
+                )),
+                Err(Error::RequestTimeoutError),
+            ],
+            vec![Err(Error::RequestTimeoutError)],
+            vec![Ok(event(
+                "die",
+                hashmap!["name".to_owned() => "test2".to_owned(), "test_label".to_owned() => "".to_owned()],
+            ))],
+        ]
+        .into_iter();
+        has_containers_mock
+            .expect_events()
+            .times(3)
+            .returning(move |_| stream::iter(subscriptions.next().unwrap()).boxed());
+        let started = tokio::time::Instant::now();
+        let events: Vec<ContainerEvent> =
+            resubscribed_container_events(has_containers_mock, "test_label".parse().unwrap(), None)
+                .take(2)
+                .collect()
+                .await;
+        let stopped = |name: &str| {
+            ContainerEvent::Stopped(StoppedContainerStatus {
+                name: name.to_owned(),
+                status: Some("exited".to_owned()),
+                ..Default::default()
+            })
+        };
+        assert_eq!(events, vec![stopped("test1"), stopped("test2")]);
+        assert_eq!(started.elapsed(), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn resubscribe_delay_doubles_up_to_the_maximum() {
+        assert_eq!(resubscribe_delay(1), Duration::from_secs(1));
+        assert_eq!(resubscribe_delay(2), Duration::from_secs(2));
+        assert_eq!(resubscribe_delay(3), Duration::from_secs(4));
+        assert_eq!(resubscribe_delay(7), Duration::from_secs(60));
+        assert_eq!(resubscribe_delay(100), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn stopped_event_matches_label_with_value() {
+        let stopped = event(
+            "die",
+            hashmap!["name".to_owned() => "test1".to_owned(), "monitor".to_owned() => "yes".to_owned()],
+        );
+        assert_eq!(to_container_event(&stopped, &"monitor=no".parse().unwrap()), None);
+        assert_eq!(
+            to_container_event(&stopped, &"monitor,!monitor=yes".parse().unwrap()),
+            None
+        );
+        assert_eq!(
+            to_container_event(&stopped, &"monitor=yes".parse().unwrap()),
+            Some(ContainerEvent::Stopped(StoppedContainerStatus {
+                name: "test1".to_owned(),
+                status: Some("exited".to_owned()),
+                ..Default::default()
+            }))
+        );
+    }
+
+    #[test]
+    fn signals_do_not_stop_the_containers() {
+        let killed = event(
+            "kill",
+            hashmap!["name".to_owned() => "test1".to_owned(), "signal".to_owned() => "1".to_owned()],
+        );
+        assert_eq!(to_container_event(&killed, &"!other".parse().unwrap()), None);
+        let stopped = event("stop", hashmap!["name".to_owned() => "test1".to_owned()]);
+        assert_eq!(to_container_event(&stopped, &"!other".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn started_containers_are_checked_again() {
+        let recheck = ContainerEvent::Recheck {
+            name: "test1".to_owned(),
+            host: None,
+        };
+        for action in ["restart", "start", "health_status: healthy"] {
+            let started = event(
+                action,
+                hashmap!["name".to_owned() => "test1".to_owned(), "notifyhealth.grace-period".to_owned() => "5m".to_owned()],
+            );
+            assert_eq!(
+                to_container_event(&started, &"!other".parse().unwrap()),
+                Some(recheck.clone())
+            );
+        }
+        assert_eq!(
+            recheck.on_host("host1"),
+            ContainerEvent::Recheck {
+                name: "test1".to_owned(),
+                host: Some("host1".to_owned()),
+            }
+        );
+    }
+
+    #[test]
+    fn notifyhealth_labels_are_applied_to_the_events() {
+        let labelled = |action: &str, labels: &[(&str, &str)]| {
+            let mut attributes = hashmap!["name".to_owned() => "test1".to_owned()];
+            attributes.extend(labels.iter().map(|(key, value)| (key.to_string(), value.to_string())));
+            to_container_event(&event(action, attributes), &"!other".parse().unwrap())
+        };
+        assert_eq!(labelled("die", &[("notifyhealth.ignore", "true")]), None);
+        assert_eq!(labelled("die", &[("notifyhealth.expect", "stopped")]), None);
+        assert_eq!(labelled("die", &[("notifyhealth.grace-period", "5m")]), None);
+        assert_eq!(
+            labelled("health_status: unhealthy", &[("notifyhealth.expect", "stopped")]),
+            Some(ContainerEvent::Running(RunningContainerStatus {
+                name: "test1".to_owned(),
+                health: Some(HealthStatusEnum::UNHEALTHY),
+                ..Default::default()
+            }))
+        );
+        assert_eq!(
+            labelled(
+                "die",
+                &[
+                    ("notifyhealth.severity", "warning"),
+                    ("notifyhealth.target", "teams-backend")
+                ]
+            ),
+            Some(ContainerEvent::Stopped(StoppedContainerStatus {
+                name: "test1".to_owned(),
+                status: Some("exited".to_owned()),
+                severity: Some(Severity::Warning),
+                target: Some("teams-backend".to_owned()),
+                ..Default::default()
+            }))
+        );
+    }
+}
@@ -0,0 +1,378 @@
+use super::containers::{RunningContainerStatus, StoppedContainerStatus, UnreachableHost};
+use super::notifier::Notifier;
+use super::swarm::ServiceStatus;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::*;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// What is exposed on `/metrics`, updated after each check and notification.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    data: Mutex<MetricsData>,
+}
+
+#[derive(Debug, Default)]
+struct MetricsData {
+    running_containers: Vec<RunningContainerStatus>,
+    healthy_containers: Vec<RunningContainerStatus>,
+    stopped_containers: Vec<StoppedContainerStatus>,
+    unreachable_hosts: Vec<UnreachableHost>,
+    services: Vec<ServiceStatus>,
+    last_check_duration: Option<Duration>,
+    last_check_succeeded: bool,
+    notifications_sent: BTreeMap<String, u64>,
+    notifications_failed: BTreeMap<String, u64>,
+}
+
+impl Metrics {
+    /// Starts the counters of every notifier at zero, so they show up before the first notification.
+    pub fn new(notifiers: &[Box<dyn Notifier>]) -> Self {
+        let metrics = Metrics::default();
+        {
+            let mut data = metrics.data.lock().unwrap();
+            for notifier in notifiers {
+                data.notifications_sent.insert(notifier.name(), 0);
+                data.notifications_failed.insert(notifier.name(), 0);
+            }
+        }
+        metrics
+    }
+
+    pub fn record_check(
+        &self,
+        running_containers: &[RunningContainerStatus],
+        healthy_containers: &[RunningContainerStatus],
+        stopped_containers: &[StoppedContainerStatus],
+        unreachable_hosts: &[UnreachableHost],
+        services: &[ServiceStatus],
+        duration: Duration,
+    ) {
+        let mut data = self.data.lock().unwrap();
+        data.running_containers = running_containers.to_vec();
+        data.healthy_containers = healthy_containers.to_vec();
+        data.stopped_containers = stopped_containers.to_vec();
+        data.unreachable_hosts = unreachable_hosts.to_vec();
+        data.services = services.to_vec();
+        data.last_check_duration = Some(duration);
+        data.last_check_succeeded = true;
+    }
+
+    /// Removes the series of the containers when Docker could not be checked, so they don't keep the last values.
+    pub fn record_failed_check(&self, duration: Duration) {
+        let mut data = self.data.lock().unwrap();
+        *data = MetricsData {
+            last_check_duration: Some(duration),
+            last_check_succeeded: false,
+            notifications_sent: std::mem::take(&mut data.notifications_sent),
+            notifications_failed: std::mem::take(&mut data.notifications_failed),
+            ..Default::default()
+        };
+    }
+
+    pub fn record_notification(&self, notifier_name: &str, succeeded: bool) {
+        let mut data = self.data.lock().unwrap();
+        let counters = if succeeded {
+            &mut data.notifications_sent
+        } else {
+            &mut data.notifications_failed
+        };
+        *counters.entry(notifier_name.to_owned()).or_default() += 1;
+    }
+
+    /// The metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let data = self.data.lock().unwrap();
+        let mut text = String::new();
+        container_series(
+            &mut text,
+            &data.running_containers,
+            &data.healthy_containers,
+            &data.stopped_containers,
+            &data.unreachable_hosts,
+            &data.services,
+        );
+        header(
+            &mut text,
+            "notifyhealth_notifications_sent_total",
+            "counter",
+            "Notifications sent, by notifier.",
+        );
+        for (notifier, count) in &data.notifications_sent {
+            let labels = labels(&[("notifier", notifier)]);
+            writeln!(text, "notifyhealth_notifications_sent_total{labels} {count}").unwrap();
+        }
+        header(
+            &mut text,
+            "notifyhealth_notifications_failed_total",
+            "counter",
+            "Notifications that failed, by notifier.",
+        );
+        for (notifier, count) in &data.notifications_failed {
+            let labels = labels(&[("notifier", notifier)]);
+            writeln!(text, "notifyhealth_notifications_failed_total{labels} {count}").unwrap();
+        }
+        if let Some(duration) = data.last_check_duration {
+            header(
+                &mut text,
+                "notifyhealth_last_check_success",
+                "gauge",
+                "If the last check of the containers could talk to Docker.",
+            );
+            writeln!(
+                text,
+                "notifyhealth_last_check_success {}",
+                u8::from(data.last_check_succeeded)
+            )
+            .unwrap();
+            header(
+                &mut text,
+                "notifyhealth_last_check_duration_seconds",
+                "gauge",
+                "How long the last check of the containers took.",
+            );
+            writeln!(
+                text,
+                "notifyhealth_last_check_duration_seconds {}",
+                duration.as_secs_f64()
+            )
+            .unwrap();
+        }
+        text
+    }
+}
+
+/// The gauges with one series for each checked container, which is 1 for the ones with problems, and for each Swarm
+/// service with problems, also used for the node_exporter textfile. The containers have a `host` label when several
+/// hosts are checked.
+pub(crate) fn container_series(
+    text: &mut String,
+    running_containers: &[RunningContainerStatus],
+    healthy_containers: &[RunningContainerStatus],
+    stopped_containers: &[StoppedContainerStatus],
+    unreachable_hosts: &[UnreachableHost],
+    services: &[ServiceStatus],
+) {
+    header(
+        text,
+        "notifyhealth_container_health",
+        "gauge",
+        "Running containers by health status, 1 when it is a problem.",
+    );
+    let running = running_containers.iter().map(|container| (container, 1));
+    let healthy = healthy_containers.iter().map(|container| (container, 0));
+    for (container, value) in running.chain(healthy) {
+        let health = container
+            .health
+            .map(|health| health.to_string())
+            .unwrap_or_else(|| "none".to_owned());
+        let labels = container_labels(&container.host, &[("name", &container.name), ("health", &health)]);
+        writeln!(text, "notifyhealth_container_health{labels} {value}").unwrap();
+    }
+    header(
+        text,
+        "notifyhealth_container_stopped",
+        "gauge",
+        "Containers with the label that are not running, by state.",
+    );
+    for container in stopped_containers {
+        let state = container.status.as_deref().unwrap_or("unknown");
+        let labels = container_labels(&container.host, &[("name", &container.name), ("state", state)]);
+        writeln!(text, "notifyhealth_container_stopped{labels} 1").unwrap();
+    }
+    header(
+        text,
+        "notifyhealth_host_unreachable",
+        "gauge",
+        "Docker hosts that could not be checked.",
+    );
+    for host in unreachable_hosts {
+        let labels = labels(&[("host", &host.host)]);
+        writeln!(text, "notifyhealth_host_unreachable{labels} 1").unwrap();
+    }
+    header(
+        text,
+        "notifyhealth_service_missing_replicas",
+        "gauge",
+        "Swarm services with the label that are missing replicas, by how many are missing.",
+    );
+    for service in services {
+        let labels = container_labels(&service.host, &[("name", &service.name)]);
+        let missing = service.desired_replicas.saturating_sub(service.running_replicas);
+        writeln!(text, "notifyhealth_service_missing_replicas{labels} {missing}").unwrap();
+    }
+}
+
+fn container_labels(host: &Option<String>, container_labels: &[(&str, &str)]) -> String {
+    match host {
+        Some(host) => labels(&[&[("host", host.as_str())], container_labels].concat()),
+        None => labels(container_labels),
+    }
+}
+
+fn header(text: &mut String, name: &str, metric_type: &str, help: &str) {
+    writeln!(text, "# HELP {name} {help}").unwrap();
+    writeln!(text, "# TYPE {name} {metric_type}").unwrap();
+}
+
+fn labels(labels: &[(&str, &str)]) -> String {
+    let labels = labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            format!("{name}=\"{value}\"")
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("{{{labels}}}")
+}
+
+/// Serves the metrics on `/metrics` until the returned future is dropped.
+pub async fn serve(listen: SocketAddr, metrics: Arc<Metrics>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let metrics = metrics.clone();
+                async move { Ok::<_, Infallible>(respond(&metrics, &request)) }
+            }))
+        }
+    });
+    let server = Server::try_bind(&listen)?.serve(make_service);
+    info!("Serving metrics on http://{}/metrics.", server.local_addr());
+    server.await?;
+    Ok(())
+}
+
+fn respond(metrics: &Metrics, request: &Request<Body>) -> Response<Body> {
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header("content-type", "text/plain; version=0.0.4; charset=utf-8")
+            .body(Body::from(metrics.render()))
+            .unwrap(),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("Not found, metrics are at /metrics.\n"))
+            .unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifier::MockNotifier;
+    use bollard::models::HealthStatusEnum;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn check_render() {
+        let mut notifier = MockNotifier::new();
+        notifier.expect_name().return_const("teams (target 1)".to_owned());
+        let notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(notifier)];
+        let metrics = Metrics::new(&notifiers);
+        metrics.record_check(
+            &[RunningContainerStatus {
+                name: "test1".to_string(),
+                health: Some(HealthStatusEnum::UNHEALTHY),
+                ..Default::default()
+            }],
+            &[RunningContainerStatus {
+                name: "test3".to_string(),
+                health: Some(HealthStatusEnum::HEALTHY),
+                ..Default::default()
+            }],
+            &[StoppedContainerStatus {
+                name: "test\"2".to_string(),
+                status: Some("exited".to_string()),
+                host: Some("host1".to_string()),
+                ..Default::default()
+            }],
+            &[UnreachableHost {
+                host: "host2".to_string(),
+                error: "connection refused".to_string(),
+            }],
+            &[ServiceStatus {
+                name: "web".to_string(),
+                desired_replicas: 3,
+                running_replicas: 1,
+                ..Default::default()
+            }],
+            Duration::from_millis(250),
+        );
+        metrics.record_notification("teams (target 1)", true);
+        metrics.record_notification("teams (target 1)", true);
+        metrics.record_notification("teams (target 1)", false);
+        assert_eq!(
+            metrics.render(),
+            r#"# HELP notifyhealth_container_health Running containers by health status, 1 when it is a problem.
+# TYPE notifyhealth_container_health gauge
+notifyhealth_container_health{name="test1",health="unhealthy"} 1
+notifyhealth_container_health{name="test3",health="healthy"} 0
+# HELP notifyhealth_container_stopped Containers with the label that are not running, by state.
+# TYPE notifyhealth_container_stopped gauge
+notifyhealth_container_stopped{host="host1",name="test\"2",state="exited"} 1
+# HELP notifyhealth_host_unreachable Docker hosts that could not be checked.
+# TYPE notifyhealth_host_unreachable gauge
+notifyhealth_host_unreachable{host="host2"} 1
+# HELP notifyhealth_service_missing_replicas Swarm services with the label that are missing replicas, by how many are missing.
+# TYPE notifyhealth_service_missing_replicas gauge
+notifyhealth_service_missing_replicas{name="web"} 2
+# HELP notifyhealth_notifications_sent_total Notifications sent, by notifier.
+# TYPE notifyhealth_notifications_sent_total counter
+notifyhealth_notifications_sent_total{notifier="teams (target 1)"} 2
+# HELP notifyhealth_notifications_failed_total Notifications that failed, by notifier.
+# TYPE notifyhealth_notifications_failed_total counter
+notifyhealth_notifications_failed_total{notifier="teams (target 1)"} 1
+# HELP notifyhealth_last_check_success If the last check of the containers could talk to Docker.
+# TYPE notifyhealth_last_check_success gauge
+notifyhealth_last_check_success 1
+# HELP notifyhealth_last_check_duration_seconds How long the last check of the containers took.
+# TYPE notifyhealth_last_check_duration_seconds gauge
+notifyhealth_last_check_duration_seconds 0.25
+"#
+        );
+    }
+
+    #[test]
+    fn failed_check_removes_the_containers() {
+        let metrics = Metrics::default();
+        metrics.record_check(
+            &[RunningContainerStatus {
+                name: "test1".to_string(),
+                health: Some(HealthStatusEnum::UNHEALTHY),
+                ..Default::default()
+            }],
+            &[],
+            &[],
+            &[],
+            &[],
+            Duration::from_millis(250),
+        );
+        metrics.record_notification("teams (target 1)", true);
+        metrics.record_failed_check(Duration::from_millis(500));
+        let text = metrics.render();
+        assert!(!text.contains("test1"), "{text}");
+        assert!(text.contains("notifyhealth_last_check_success 0\n"), "{text}");
+        assert!(
+            text.contains("notifyhealth_last_check_duration_seconds 0.5\n"),
+            "{text}"
+        );
+        assert!(
+            text.contains("notifyhealth_notifications_sent_total{notifier=\"teams (target 1)\"} 1\n"),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn metrics_are_served_only_on_their_path() {
+        let metrics = Metrics::default();
+        let request = |path: &str| Request::get(path).body(Body::empty()).unwrap();
+        assert_eq!(respond(&metrics, &request("/metrics")).status(), StatusCode::OK);
+        assert_eq!(respond(&metrics, &request("/")).status(), StatusCode::NOT_FOUND);
+    }
+}
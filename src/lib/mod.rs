@@ -1,50 +1,552 @@
 #![warn(clippy::shadow_unrelated)]
 #[macro_use]
 mod macros;
+pub mod alertmanager;
 pub mod args;
+pub mod config;
 pub mod containers;
+pub mod discord;
+pub mod docker;
+pub mod email;
+pub mod events;
+pub mod metrics;
 pub mod msteams;
+pub mod nagios;
+pub mod notifier;
+pub mod opsgenie;
+pub mod pagerduty;
 pub mod print;
+pub mod selector;
+pub mod slack;
+pub mod state;
+pub mod swarm;
 pub mod webhook;
 use args::*;
-use bollard::Docker;
-use containers::Containers;
-use log::{info, warn};
+use config::{Config, Host, Settings};
+use containers::{CheckStatus, UnreachableHost};
+use docker::HostConnection;
+use events::ContainerEvent;
+use futures::future;
+use futures::stream::{self, BoxStream, StreamExt};
+use log::{error, info, warn};
 use log::{Level, LevelFilter};
-use webhook::Webhook;
+use metrics::Metrics;
+use notifier::{Notification, Notifier};
+use state::State;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+use tokio::time::{Interval, MissedTickBehavior};
 
-pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
+/// The exit code of the errors with `--exit-code`, like an invalid configuration, kept apart from the ones of the
+/// status of the containers. Without it errors exit with 1, as they always did.
+const ERROR_EXIT_CODE: i32 = 4;
+
+/// Runs notifyhealth, returning the process exit code: the status of the containers when it checks them only once
+/// with `--exit-code` or as a Nagios plugin, and 0 otherwise.
+pub async fn run() -> Result<i32, Box<dyn std::error::Error>> {
     let args = Args::new();
     let level = to_level_filter(args.verbose.log_level());
     env_logger::Builder::new().filter_level(level).init();
     info!("Log level: {level}");
     info!("Args are {:?}.", args);
-    let docker = Docker::connect_with_socket_defaults().unwrap();
-    let containers = Containers::new(docker);
-    let running_containers = containers::check_running_containers(&containers, args.report_no_health).await?;
-    warn!("Running containers: {:?}", running_containers);
-    let stopped_containers = containers::check_not_running_containers(&containers, &args.label).await?;
-    warn!("Stopped containers: {:?}", stopped_containers);
-    match &args.command {
-        Command::Print {} => {
-            print::running_containers(running_containers);
-            print::stopped_containers(stopped_containers);
+    if let Some(Command::Config {
+        command: ConfigCommand::Validate {},
+    }) = &args.command
+    {
+        validate_config(&args)?;
+        return Ok(0);
+    }
+    let settings = match load_settings(&args) {
+        Ok(settings) => settings,
+        // A Nagios plugin always prints a status line, even when it can't run.
+        Err(err) if matches!(args.command, Some(Command::Check(_))) => return Ok(print_unknown(err.as_ref())),
+        Err(err) if args.exit_code == Some(true) => {
+            error!("{err}");
+            return Ok(ERROR_EXIT_CODE);
         }
-        Command::NotifyTeams { callback_url } => {
-            Webhook::new(Some(msteams::format_message)).notify(
-                callback_url,
-                running_containers,
-                stopped_containers,
-                args.hostname,
-            )?;
+        Err(err) => return Err(err),
+    };
+    info!("Settings are {:?}.", settings);
+    let hosts = settings
+        .hosts
+        .iter()
+        .map(|host| HostContainers {
+            host,
+            connection: HostConnection::new(host.docker_host.clone()),
+        })
+        .collect::<Vec<_>>();
+    let notifiers = notifier::from_targets(&settings.targets);
+    let metrics = Arc::new(Metrics::new(&notifiers));
+    if let Some(Command::Check(check_settings)) = &args.command {
+        let (status, output) = match check(&settings, &hosts, &metrics).await {
+            Ok(notification) => nagios::report(
+                check_settings,
+                &notification.running_containers,
+                &notification.stopped_containers,
+                &notification.unreachable_hosts,
+                &notification.services,
+            ),
+            Err(err) => nagios::unknown(err.as_ref()),
+        };
+        print!("{output}");
+        Ok(status.exit_code())
+    } else if let Some(metrics_listen) = settings.metrics_listen {
+        // The server runs on its own task, so the metrics are still served while the notifiers block the watch.
+        let mut server = tokio::spawn(metrics::serve(metrics_listen, metrics.clone()));
+        tokio::select! {
+            result = &mut server => result?.map_err(|err| err as Box<dyn std::error::Error>)?,
+            result = watch(&settings, &hosts, &notifiers, &metrics) => {
+                server.abort();
+                result?
+            }
         }
-        Command::NotifyWebhook { callback_url } => {
-            Webhook::default().notify(callback_url, running_containers, stopped_containers, args.hostname)?;
+        Ok(0)
+    } else if settings.events || settings.interval.is_some() {
+        watch(&settings, &hosts, &notifiers, &metrics).await?;
+        Ok(0)
+    } else {
+        let notification = match check(&settings, &hosts, &metrics).await {
+            Ok(notification) => notification,
+            Err(err) => return docker_unreachable(&args, &settings, err),
+        };
+        let status = CheckStatus::from_containers(
+            &notification.running_containers,
+            &notification.stopped_containers,
+            &notification.unreachable_hosts,
+            &notification.services,
+        );
+        let notified = notify(&settings, &notifiers, &metrics, notification, Checked::All);
+        if settings.exit_code {
+            // The status of the containers is still known when a target could not be notified, and is what the exit
+            // code is for.
+            if let Err(err) = notified {
+                error!("{err}");
+            }
+            Ok(status.exit_code())
+        } else {
+            notified.map(|()| 0)
         }
     }
+}
+
+/// One of the Docker hosts, which is connected to when it is checked, and again while it can't be reached.
+struct HostContainers<'a> {
+    host: &'a Host,
+    connection: HostConnection,
+}
+
+/// With `--exit-code` or as a Nagios plugin not being able to talk to Docker is reported through the exit code,
+/// otherwise it is an error.
+fn docker_unreachable(
+    args: &Args,
+    settings: &Settings,
+    err: Box<dyn std::error::Error>,
+) -> Result<i32, Box<dyn std::error::Error>> {
+    if let Some(Command::Check(_)) = &args.command {
+        Ok(print_unknown(err.as_ref()))
+    } else if settings.exit_code {
+        error!("Could not check the containers: {err}");
+        Ok(CheckStatus::DockerUnreachable.exit_code())
+    } else {
+        Err(err)
+    }
+}
+
+/// Prints the output of the Nagios plugin for the checks that could not be done, returning its exit code.
+fn print_unknown(err: &dyn std::error::Error) -> i32 {
+    let (status, output) = nagios::unknown(err);
+    print!("{output}");
+    status.exit_code()
+}
+
+fn load_settings(args: &Args) -> Result<Settings, Box<dyn std::error::Error>> {
+    let config = match &args.config {
+        Some(config_path) => Some(Config::load(config_path)?),
+        None => None,
+    };
+    Settings::new(args, config)
+}
+
+fn validate_config(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let config_path = args
+        .config
+        .as_ref()
+        .ok_or("No configuration file to validate, use --config.")?;
+    let config = Config::load(config_path)?;
+    Settings::new(args, Some(config)).map_err(|err| format!("Invalid configuration: {err}"))?;
+    println!("Configuration file {} is valid.", config_path.display());
     Ok(())
 }
 
+/// Watches the containers until a shutdown signal is received. The signal handlers are installed before anything is
+/// checked, and a check in progress is stopped too.
+async fn watch(
+    settings: &Settings,
+    hosts: &[HostContainers<'_>],
+    notifiers: &[Box<dyn Notifier>],
+    metrics: &Metrics,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let shutdown = shutdown_signal();
+    tokio::select! {
+        _ = shutdown => {
+            info!("Shutdown signal received, stopping.");
+            Ok(())
+        }
+        result = watch_containers(settings, hosts, notifiers, metrics) => result,
+    }
+}
+
+async fn watch_containers(
+    settings: &Settings,
+    hosts: &[HostContainers<'_>],
+    notifiers: &[Box<dyn Notifier>],
+    metrics: &Metrics,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut ticker = settings.interval.map(|interval| {
+        info!("Checking containers every {}.", humantime::format_duration(interval));
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        ticker
+    });
+    let mut container_events = if settings.events {
+        info!("Listening to Docker events.");
+        if ticker.is_none() {
+            check_and_notify_logging_errors(settings, hosts, notifiers, metrics).await;
+        }
+        let host_events = hosts.iter().map(host_events).collect::<Vec<_>>();
+        if host_events.is_empty() {
+            None
+        } else {
+            Some(stream::select_all(host_events).boxed())
+        }
+    } else {
+        None
+    };
+    loop {
+        tokio::select! {
+            _ = tick(&mut ticker) => check_and_notify_logging_errors(settings, hosts, notifiers, metrics).await,
+            event = next_event(&mut container_events) => match event {
+                Some(event) => {
+                    let notification = match event {
+                        ContainerEvent::Running(running_container) => Notification {
+                            running_containers: vec![running_container],
+                            hostname: settings.hostname.clone(),
+                            ..Default::default()
+                        },
+                        ContainerEvent::Stopped(stopped_container) => Notification {
+                            stopped_containers: vec![stopped_container],
+                            hostname: settings.hostname.clone(),
+                            ..Default::default()
+                        },
+                        ContainerEvent::Recheck { name, host } => {
+                            if let Err(err) = recheck(settings, hosts, notifiers, metrics, &name, host.as_deref()).await {
+                                error!("Error checking the container {name} again: {err}");
+                            }
+                            continue;
+                        }
+                    };
+                    if let Err(err) = notify(settings, notifiers, metrics, notification, Checked::Event) {
+                        error!("Error notifying container event: {err}");
+                    }
+                }
+                None => return Err("Docker events stream ended.".into()),
+            }
+        }
+    }
+}
+
+async fn tick(ticker: &mut Option<Interval>) {
+    match ticker {
+        Some(ticker) => {
+            ticker.tick().await;
+        }
+        None => future::pending().await,
+    }
+}
+
+/// The events of a host, with the host set on the containers. The errors of the events, including not being able to
+/// connect to the host, are only logged and the events subscribed to again, so the host is still listened to, and with
+/// several hosts it is reported as unreachable on the next check until Docker can be reached again.
+fn host_events(host: &HostContainers<'_>) -> BoxStream<'static, ContainerEvent> {
+    let container_events = events::resubscribed_container_events(
+        host.connection.clone(),
+        host.host.label.clone(),
+        host.host.hostname.clone(),
+    );
+    match host.host.hostname.clone() {
+        None => container_events,
+        Some(hostname) => container_events.map(move |event| event.on_host(&hostname)).boxed(),
+    }
+}
+
+async fn next_event(container_events: &mut Option<BoxStream<'static, ContainerEvent>>) -> Option<ContainerEvent> {
+    match container_events {
+        Some(container_events) => container_events.next().await,
+        None => future::pending().await,
+    }
+}
+
+/// Installs the signal handlers right away, not when the returned future is first polled, so a signal received while
+/// starting up is not missed.
+#[cfg(unix)]
+fn shutdown_signal() -> impl std::future::Future<Output = ()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut sigint = signal(SignalKind::interrupt()).expect("failed to install SIGINT handler");
+    let mut sigterm = signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+    async move {
+        tokio::select! {
+            _ = sigint.recv() => {}
+            _ = sigterm.recv() => {}
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn shutdown_signal() -> impl std::future::Future<Output = ()> {
+    let mut ctrl_c = tokio::signal::windows::ctrl_c().expect("failed to install Ctrl+C handler");
+    async move {
+        ctrl_c.recv().await;
+    }
+}
+
+async fn check_and_notify_logging_errors(
+    settings: &Settings,
+    hosts: &[HostContainers<'_>],
+    notifiers: &[Box<dyn Notifier>],
+    metrics: &Metrics,
+) {
+    if let Err(err) = check_and_notify(settings, hosts, notifiers, metrics).await {
+        error!("Error checking containers: {err}");
+    }
+}
+
+async fn check_and_notify(
+    settings: &Settings,
+    hosts: &[HostContainers<'_>],
+    notifiers: &[Box<dyn Notifier>],
+    metrics: &Metrics,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let notification = check(settings, hosts, metrics).await?;
+    notify(settings, notifiers, metrics, notification, Checked::All)
+}
+
+/// Checks the container of an event that tells it may have recovered, notifying its recovery or its problem if it
+/// still has one. Without a state directory nothing is resolved, so there is nothing to check.
+async fn recheck(
+    settings: &Settings,
+    hosts: &[HostContainers<'_>],
+    notifiers: &[Box<dyn Notifier>],
+    metrics: &Metrics,
+    name: &str,
+    hostname: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if settings.state_dir.is_none() {
+        return Ok(());
+    }
+    let host = match hosts.iter().find(|host| host.host.hostname.as_deref() == hostname) {
+        Some(host) => host,
+        None => return Ok(()),
+    };
+    let mut host_notification = check_host(settings, host).await?;
+    let notification = Notification {
+        running_containers: host_notification
+            .running_containers
+            .drain(..)
+            .filter(|container| container.name == name)
+            .collect(),
+        stopped_containers: host_notification
+            .stopped_containers
+            .drain(..)
+            .filter(|container| container.name == name)
+            .collect(),
+        hostname: settings.hostname.clone(),
+        ..Default::default()
+    };
+    let display_name = containers::display_name(hostname, name);
+    notify(
+        settings,
+        notifiers,
+        metrics,
+        notification,
+        Checked::Container(&display_name),
+    )
+}
+
+/// Checks the hosts concurrently, merging their containers in the order of the hosts. When several hosts are checked
+/// the ones that fail are reported as unreachable, otherwise the error is returned.
+async fn check(
+    settings: &Settings,
+    hosts: &[HostContainers<'_>],
+    metrics: &Metrics,
+) -> Result<Notification, Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    let results = future::join_all(hosts.iter().map(|host| check_host(settings, host))).await;
+    let mut notification = Notification {
+        hostname: settings.hostname.clone(),
+        ..Default::default()
+    };
+    for (host, result) in hosts.iter().zip(results) {
+        match (result, &host.host.hostname) {
+            (Ok(host_notification), _) => {
+                notification
+                    .running_containers
+                    .extend(host_notification.running_containers);
+                notification
+                    .stopped_containers
+                    .extend(host_notification.stopped_containers);
+                notification.services.extend(host_notification.services);
+                notification
+                    .healthy_containers
+                    .extend(host_notification.healthy_containers);
+            }
+            (Err(err), Some(hostname)) => {
+                warn!("Could not check the Docker host {hostname}: {err}");
+                notification.unreachable_hosts.push(UnreachableHost {
+                    host: hostname.clone(),
+                    error: err.to_string(),
+                });
+            }
+            (Err(err), None) => {
+                metrics.record_failed_check(started.elapsed());
+                return Err(err);
+            }
+        }
+    }
+    metrics.record_check(
+        &notification.running_containers,
+        &notification.healthy_containers,
+        &notification.stopped_containers,
+        &notification.unreachable_hosts,
+        &notification.services,
+        started.elapsed(),
+    );
+    Ok(notification)
+}
+
+/// The problems of one host: the running containers, and the stopped containers with the label or, with `--swarm`,
+/// the services with the label that are missing replicas. The healthy containers are only checked for the metrics.
+async fn check_host(
+    settings: &Settings,
+    host: &HostContainers<'_>,
+) -> Result<Notification, Box<dyn std::error::Error>> {
+    let containers = &host.connection.containers().await?;
+    let mut running_containers =
+        containers::check_running_containers(containers, &host.host.label, settings.report_no_health).await?;
+    warn!("Running containers: {:?}", running_containers);
+    let (mut stopped_containers, mut services) = if settings.swarm {
+        (vec![], swarm::check_services(containers, &host.host.label).await?)
+    } else {
+        (
+            containers::check_not_running_containers(containers, &host.host.label).await?,
+            vec![],
+        )
+    };
+    warn!("Stopped containers: {:?}", stopped_containers);
+    warn!("Services missing replicas: {:?}", services);
+    let mut healthy_containers = if settings.exposes_metrics() {
+        containers::check_healthy_containers(containers, &host.host.label, &running_containers).await?
+    } else {
+        vec![]
+    };
+    for container in &mut running_containers {
+        container.host = host.host.hostname.clone();
+    }
+    for container in &mut stopped_containers {
+        container.host = host.host.hostname.clone();
+    }
+    for service in &mut services {
+        service.host = host.host.hostname.clone();
+    }
+    for container in &mut healthy_containers {
+        container.host = host.host.hostname.clone();
+    }
+    Ok(Notification {
+        running_containers,
+        stopped_containers,
+        services,
+        healthy_containers,
+        ..Default::default()
+    })
+}
+
+/// What the containers of a notification are the result of, to know which of the containers in the state recovered.
+enum Checked<'a> {
+    /// A complete check, so the containers missing from it have recovered.
+    All,
+    /// A check of only the container with this display name.
+    Container(&'a str),
+    /// An event, which tells nothing about the other containers.
+    Event,
+}
+
+/// Sends the notifications to the targets. When a state directory is set only changes are notified, including the
+/// containers that recovered, which are the ones missing from what was `checked`.
+fn notify(
+    settings: &Settings,
+    notifiers: &[Box<dyn Notifier>],
+    metrics: &Metrics,
+    mut notification: Notification,
+    checked: Checked,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let state_dir = match &settings.state_dir {
+        Some(state_dir) => state_dir,
+        _ => return notifier::notify_all(notifiers, &notification, &notification, &mut BTreeMap::new(), metrics),
+    };
+    let now = SystemTime::now();
+    let mut state = State::load(state_dir)?;
+    match checked {
+        Checked::All => {
+            notification.resolved_containers = state.remove_recovered(
+                &notification.running_containers,
+                &notification.stopped_containers,
+                &notification.unreachable_hosts,
+                now,
+            );
+            notification.resolved_containers.extend(state.remove_recovered_services(
+                &notification.services,
+                &notification.unreachable_hosts,
+                now,
+            ));
+            notification.resolved_hosts = state.remove_reachable_hosts(&notification.unreachable_hosts, now);
+        }
+        Checked::Container(display_name) => {
+            notification.resolved_containers = state.remove_recovered_container(
+                display_name,
+                &notification.running_containers,
+                &notification.stopped_containers,
+                now,
+            );
+        }
+        Checked::Event => {}
+    }
+    let (changed_running_containers, changed_stopped_containers, changed_unreachable_hosts) = state.track_problems(
+        notification.running_containers.clone(),
+        notification.stopped_containers.clone(),
+        notification.unreachable_hosts.clone(),
+        now,
+        settings.renotify_interval,
+    );
+    let changed_services = state.track_services(notification.services.clone(), now, settings.renotify_interval);
+    let changes = Notification {
+        running_containers: changed_running_containers,
+        stopped_containers: changed_stopped_containers,
+        resolved_containers: notification.resolved_containers.clone(),
+        unreachable_hosts: changed_unreachable_hosts,
+        resolved_hosts: notification.resolved_hosts.clone(),
+        services: changed_services,
+        hostname: settings.hostname.clone(),
+        ..Default::default()
+    };
+    // The state is saved even when a target failed, otherwise the targets that were notified would be sent the
+    // same changes again on the next check. The changes of the targets that failed are kept in it, to send them again.
+    let notified = notifier::notify_all(notifiers, &notification, &changes, &mut state.pending, metrics);
+    match (notified, state.save(state_dir)) {
+        (Err(notify_err), Err(save_err)) => Err(format!("{notify_err} Could not save the state: {save_err}").into()),
+        (notified, saved) => notified.and(saved),
+    }
+}
+
 fn to_level_filter(level: Option<Level>) -> LevelFilter {
     match level {
         None => LevelFilter::Off,
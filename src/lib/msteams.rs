@@ -1,21 +1,43 @@
-use super::containers::{RunningContainerStatus, StoppedContainerStatus};
+use super::containers::{with_severity, RunningContainerStatus, StoppedContainerStatus};
+use super::notifier::Notification;
 use itertools::Itertools;
 use log::*;
 use mhteams::{Fact, Message, Section};
+use std::collections::BTreeMap;
+use std::time::Duration;
 
-pub fn format_message(
-    running_containers: &[RunningContainerStatus],
-    stopped_containers: &[StoppedContainerStatus],
-    hostname: Option<String>,
-) -> Result<Vec<u8>, serde_json::Error> {
-    let mut sections = vec![];
-    if !running_containers.is_empty() {
-        for (health_opt, group) in &running_containers.iter().group_by(|c| &c.health) {
+pub fn format_message(notification: &Notification) -> Result<Vec<Vec<u8>>, serde_json::Error> {
+    let Notification {
+        running_containers,
+        stopped_containers,
+        resolved_containers,
+        unreachable_hosts,
+        resolved_hosts,
+        services,
+        hostname,
+        ..
+    } = notification;
+    let mut sections = project_sections(running_containers, stopped_containers);
+    let running_outside_projects = running_containers
+        .iter()
+        .filter(|c| c.project().is_none())
+        .collect_vec();
+    let stopped_outside_projects = stopped_containers
+        .iter()
+        .filter(|c| c.project().is_none())
+        .collect_vec();
+    if !running_outside_projects.is_empty() {
+        for (health_opt, group) in &running_outside_projects.into_iter().group_by(|c| &c.health) {
             if let Some(health) = health_opt {
                 sections.push(
                     Section::new()
                         .text("The following running containers are not healthy:")
-                        .facts(group.into_iter().map(|c| Fact::new(c.name.clone(), health)).collect()),
+                        .facts(
+                            group
+                                .into_iter()
+                                .map(|c| Fact::new(c.display_name(), with_severity(health.to_string(), c.severity)))
+                                .collect(),
+                        ),
                 );
             } else {
                 sections.push(
@@ -24,45 +46,140 @@ pub fn format_message(
                         .facts(
                             group
                                 .into_iter()
-                                .map(|c| Fact::new(c.name.clone(), "no health status"))
+                                .map(|c| {
+                                    Fact::new(
+                                        c.display_name(),
+                                        with_severity("no health status".to_owned(), c.severity),
+                                    )
+                                })
                                 .collect(),
                         ),
                 );
             }
         }
-        warn!("Sections after unhealthy: {:?}", sections);
+        debug!("Sections after unhealthy: {:?}", sections);
     }
-    if !stopped_containers.is_empty() {
+    if !stopped_outside_projects.is_empty() {
         sections.push(
             Section::new().text("The following containers are not running:").facts(
-                stopped_containers
+                stopped_outside_projects
+                    .into_iter()
+                    .map(|c| Fact::new(c.display_name(), with_severity(c.summary(), c.severity)))
+                    .collect(),
+            ),
+        );
+        debug!("Sections after stopped: {:?}", sections);
+    }
+    if !services.is_empty() {
+        sections.push(
+            Section::new()
+                .text("The following services are missing replicas:")
+                .facts(
+                    services
+                        .iter()
+                        .map(|s| Fact::new(s.display_name(), s.summary()))
+                        .collect(),
+                ),
+        );
+    }
+    if !unreachable_hosts.is_empty() {
+        sections.push(
+            Section::new().text("The following hosts could not be checked:").facts(
+                unreachable_hosts
+                    .iter()
+                    .map(|h| Fact::new(h.host.clone(), h.error.clone()))
+                    .collect(),
+            ),
+        );
+    }
+    if !resolved_containers.is_empty() {
+        sections.push(
+            Section::new().text("The following containers have recovered:").facts(
+                resolved_containers
                     .iter()
                     .map(|c| {
-                        if let Some(status) = &c.status {
-                            Fact::new(c.name.clone(), status.clone())
-                        } else {
-                            Fact::new(c.name.clone(), "no status")
-                        }
+                        let down_for = humantime::format_duration(Duration::from_secs(c.down_for_seconds));
+                        Fact::new(c.display_name(), format!("{} for {down_for}", c.previous_status))
+                    })
+                    .collect(),
+            ),
+        );
+        debug!("Sections after resolved: {:?}", sections);
+    }
+    if !resolved_hosts.is_empty() {
+        sections.push(
+            Section::new().text("The following hosts can be checked again:").facts(
+                resolved_hosts
+                    .iter()
+                    .map(|h| {
+                        let down_for = humantime::format_duration(Duration::from_secs(h.down_for_seconds));
+                        Fact::new(h.host.clone(), format!("unreachable for {down_for}"))
                     })
                     .collect(),
             ),
         );
-        warn!("Sections after stopped: {:?}", sections);
     }
-    let mut msg = Message::new() // todo add server name
-        .title("Problem in containers! 🤕")
-        .summary("Problems in containers");
+    let mut msg = if !notification.has_problems() {
+        Message::new()
+            .title("Containers recovered! 😌")
+            .summary("Containers recovered")
+    } else {
+        Message::new()
+            .title("Problem in containers! 🤕")
+            .summary("Problems in containers")
+    };
     if let Some(hostname) = hostname {
         msg = msg.text(format!("Server: `{hostname}`."));
     }
     msg = msg.sections(sections);
     info!("Message to be sent: {:?}", msg);
-    serde_json::to_vec(&msg)
+    Ok(vec![serde_json::to_vec(&msg)?])
+}
+
+/// One section for each Docker Compose project with problems, with all its running and stopped containers, so a
+/// broken stack stands out.
+fn project_sections(
+    running_containers: &[RunningContainerStatus],
+    stopped_containers: &[StoppedContainerStatus],
+) -> Vec<Section> {
+    let mut projects: BTreeMap<&str, Vec<Fact>> = BTreeMap::new();
+    for c in running_containers {
+        if let Some(project) = c.project() {
+            let health = match &c.health {
+                Some(health) => health.to_string(),
+                None => "no health status".to_owned(),
+            };
+            projects
+                .entry(project)
+                .or_default()
+                .push(Fact::new(c.display_name(), with_severity(health, c.severity)));
+        }
+    }
+    for c in stopped_containers {
+        if let Some(project) = c.project() {
+            let status = format!("not running ({})", c.summary());
+            projects
+                .entry(project)
+                .or_default()
+                .push(Fact::new(c.display_name(), with_severity(status, c.severity)));
+        }
+    }
+    projects
+        .into_iter()
+        .map(|(project, facts)| {
+            Section::new()
+                .text(format!(
+                    "The following containers of the Compose project `{project}` have problems:"
+                ))
+                .facts(facts)
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::containers::{ComposeService, ResolvedContainerStatus, UnreachableHost};
     use bollard::models::HealthStatusEnum;
     use pretty_assertions::assert_eq;
 
@@ -72,24 +189,34 @@ mod tests {
             RunningContainerStatus {
                 name: "test1".to_string(),
                 health: None,
+                ..Default::default()
             },
             RunningContainerStatus {
                 name: "test2".to_string(),
                 health: Some(HealthStatusEnum::UNHEALTHY),
+                ..Default::default()
             },
         ];
         let stopped_containers = vec![
             StoppedContainerStatus {
                 name: "test3".to_string(),
                 status: Some("exited".to_string()),
+                ..Default::default()
             },
             StoppedContainerStatus {
                 name: "test4".to_string(),
                 status: None,
+                ..Default::default()
             },
         ];
-        let formatted_message_bytes =
-            format_message(&running_containers, &stopped_containers, Some("myhostname".to_owned())).unwrap();
+        let formatted_message_bytes = format_message(&Notification {
+            running_containers,
+            stopped_containers,
+            hostname: Some("myhostname".to_owned()),
+            ..Default::default()
+        })
+        .unwrap()
+        .remove(0);
 
         let msg = Message::new()
             .title("Problem in containers! 🤕")
@@ -111,4 +238,149 @@ mod tests {
         let formatted_message = std::str::from_utf8(&formatted_message_bytes).unwrap();
         assert_eq!(formatted_message, expected_message);
     }
+
+    #[tokio::test]
+    async fn check_resolved_message() {
+        let resolved_containers = vec![
+            ResolvedContainerStatus {
+                name: "test1".to_string(),
+                previous_status: "unhealthy".to_string(),
+                down_for_seconds: 3660,
+                ..Default::default()
+            },
+            ResolvedContainerStatus {
+                name: "test2".to_string(),
+                previous_status: "stopped".to_string(),
+                down_for_seconds: 30,
+                ..Default::default()
+            },
+        ];
+        let formatted_message_bytes = format_message(&Notification {
+            resolved_containers,
+            ..Default::default()
+        })
+        .unwrap()
+        .remove(0);
+
+        let msg = Message::new()
+            .title("Containers recovered! 😌")
+            .summary("Containers recovered")
+            .sections(vec![Section::new()
+                .text("The following containers have recovered:")
+                .facts(vec![
+                    Fact::new("test1", "unhealthy for 1h 1m"),
+                    Fact::new("test2", "stopped for 30s"),
+                ])]);
+        let expected_message_bytes = serde_json::to_vec::<Message>(&msg).unwrap();
+        let expected_message = std::str::from_utf8(&expected_message_bytes).unwrap();
+        let formatted_message = std::str::from_utf8(&formatted_message_bytes).unwrap();
+        assert_eq!(formatted_message, expected_message);
+    }
+
+    #[tokio::test]
+    async fn check_message_with_several_hosts() {
+        let stopped_containers = vec![
+            StoppedContainerStatus {
+                name: "web".to_string(),
+                status: Some("exited".to_string()),
+                host: Some("host1".to_string()),
+                exit_code: Some(137),
+                oom_killed: true,
+                restart_count: Some(3),
+                ..Default::default()
+            },
+            StoppedContainerStatus {
+                name: "web".to_string(),
+                status: Some("dead".to_string()),
+                host: Some("host2".to_string()),
+                ..Default::default()
+            },
+        ];
+        let unreachable_hosts = vec![UnreachableHost {
+            host: "host3".to_string(),
+            error: "connection refused".to_string(),
+        }];
+        let formatted_message_bytes = format_message(&Notification {
+            stopped_containers,
+            unreachable_hosts,
+            ..Default::default()
+        })
+        .unwrap()
+        .remove(0);
+
+        let msg = Message::new()
+            .title("Problem in containers! 🤕")
+            .summary("Problems in containers")
+            .sections(vec![
+                Section::new()
+                    .text("The following containers are not running:")
+                    .facts(vec![
+                        Fact::new("host1/web", "exited, exit code 137, out of memory, restarted 3 times"),
+                        Fact::new("host2/web", "dead"),
+                    ]),
+                Section::new()
+                    .text("The following hosts could not be checked:")
+                    .facts(vec![Fact::new("host3", "connection refused")]),
+            ]);
+        let expected_message_bytes = serde_json::to_vec::<Message>(&msg).unwrap();
+        let expected_message = std::str::from_utf8(&expected_message_bytes).unwrap();
+        let formatted_message = std::str::from_utf8(&formatted_message_bytes).unwrap();
+        assert_eq!(formatted_message, expected_message);
+    }
+
+    #[tokio::test]
+    async fn check_message_with_compose_project() {
+        let shop = |service: &str| {
+            Some(ComposeService {
+                project: "shop".to_string(),
+                service: service.to_string(),
+                container_number: Some(1),
+            })
+        };
+        let running_containers = vec![
+            RunningContainerStatus {
+                name: "shop-web-1".to_string(),
+                health: Some(HealthStatusEnum::UNHEALTHY),
+                compose: shop("web"),
+                ..Default::default()
+            },
+            RunningContainerStatus {
+                name: "test1".to_string(),
+                health: Some(HealthStatusEnum::UNHEALTHY),
+                ..Default::default()
+            },
+        ];
+        let stopped_containers = vec![StoppedContainerStatus {
+            name: "shop-db-1".to_string(),
+            status: Some("exited".to_string()),
+            compose: shop("db"),
+            ..Default::default()
+        }];
+        let formatted_message_bytes = format_message(&Notification {
+            running_containers,
+            stopped_containers,
+            ..Default::default()
+        })
+        .unwrap()
+        .remove(0);
+
+        let msg = Message::new()
+            .title("Problem in containers! 🤕")
+            .summary("Problems in containers")
+            .sections(vec![
+                Section::new()
+                    .text("The following containers of the Compose project `shop` have problems:")
+                    .facts(vec![
+                        Fact::new("shop-web-1", "unhealthy"),
+                        Fact::new("shop-db-1", "not running (exited)"),
+                    ]),
+                Section::new()
+                    .text("The following running containers are not healthy:")
+                    .facts(vec![Fact::new("test1", "unhealthy")]),
+            ]);
+        let expected_message_bytes = serde_json::to_vec::<Message>(&msg).unwrap();
+        let expected_message = std::str::from_utf8(&expected_message_bytes).unwrap();
+        let formatted_message = std::str::from_utf8(&formatted_message_bytes).unwrap();
+        assert_eq!(formatted_message, expected_message);
+    }
 }
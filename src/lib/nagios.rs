@@ -0,0 +1,374 @@
+use super::containers::{with_severity, RunningContainerStatus, Severity, StoppedContainerStatus, UnreachableHost};
+use super::swarm::ServiceStatus;
+use bollard::models::HealthStatusEnum;
+use std::fmt::Write;
+
+/// The states a container with problems can be in, which can be counted as warning or critical.
+#[derive(clap::ValueEnum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ProblemState {
+    Stopped,
+    Unhealthy,
+    Starting,
+    NoHealth,
+    /// Swarm services missing replicas, with `--swarm`.
+    UnderReplicated,
+}
+
+impl ProblemState {
+    const ALL: [ProblemState; 5] = [
+        ProblemState::Stopped,
+        ProblemState::Unhealthy,
+        ProblemState::Starting,
+        ProblemState::NoHealth,
+        ProblemState::UnderReplicated,
+    ];
+
+    fn description(self) -> &'static str {
+        match self {
+            ProblemState::Stopped => "stopped",
+            ProblemState::Unhealthy => "unhealthy",
+            ProblemState::Starting => "starting",
+            ProblemState::NoHealth => "without health status",
+            ProblemState::UnderReplicated => "service(s) missing replicas",
+        }
+    }
+
+    fn perfdata_label(self) -> &'static str {
+        match self {
+            ProblemState::Stopped => "stopped",
+            ProblemState::Unhealthy => "unhealthy",
+            ProblemState::Starting => "starting",
+            ProblemState::NoHealth => "no_health",
+            ProblemState::UnderReplicated => "under_replicated",
+        }
+    }
+
+    fn of_running(container: &RunningContainerStatus) -> ProblemState {
+        match container.health {
+            Some(HealthStatusEnum::UNHEALTHY) => ProblemState::Unhealthy,
+            Some(HealthStatusEnum::STARTING) => ProblemState::Starting,
+            _ => ProblemState::NoHealth,
+        }
+    }
+}
+
+#[derive(clap::Args, Debug, PartialEq, Clone)]
+pub struct CheckSettings {
+    #[clap(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "stopped,unhealthy,under-replicated",
+        help = "Container states that make the check critical"
+    )]
+    pub critical: Vec<ProblemState>,
+    #[clap(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "starting,no-health",
+        help = "Container states that make the check a warning, containers without health status are only checked with --report-no-health"
+    )]
+    pub warning: Vec<ProblemState>,
+}
+
+/// The plugin return codes from the Nagios plugin guidelines.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum NagiosStatus {
+    Ok,
+    Warning,
+    Critical,
+    Unknown,
+}
+
+impl NagiosStatus {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            NagiosStatus::Ok => 0,
+            NagiosStatus::Warning => 1,
+            NagiosStatus::Critical => 2,
+            NagiosStatus::Unknown => 3,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            NagiosStatus::Ok => "OK",
+            NagiosStatus::Warning => "WARNING",
+            NagiosStatus::Critical => "CRITICAL",
+            NagiosStatus::Unknown => "UNKNOWN",
+        }
+    }
+}
+
+/// The status and the plugin output: the status line with the perfdata, then a line for each container and Swarm
+/// service. Hosts that could not be checked make the status unknown, unless it is already critical. The containers with
+/// a `notifyhealth.severity` label count as critical for `critical` and `error`, as a warning for `warning`, and do
+/// not change the status for `info`, whatever their state.
+pub fn report(
+    settings: &CheckSettings,
+    running_containers: &[RunningContainerStatus],
+    stopped_containers: &[StoppedContainerStatus],
+    unreachable_hosts: &[UnreachableHost],
+    services: &[ServiceStatus],
+) -> (NagiosStatus, String) {
+    let mut containers = vec![];
+    for container in stopped_containers {
+        let detail = match &container.status {
+            Some(status) => format!("stopped ({status})"),
+            None => "stopped".to_owned(),
+        };
+        containers.push((
+            ProblemState::Stopped,
+            container.severity,
+            format!(
+                "{}: {}",
+                container.display_name(),
+                with_severity(detail, container.severity)
+            ),
+        ));
+    }
+    for container in running_containers {
+        let state = ProblemState::of_running(container);
+        containers.push((
+            state,
+            container.severity,
+            format!(
+                "{}: {}",
+                container.display_name(),
+                with_severity(state.description().to_owned(), container.severity)
+            ),
+        ));
+    }
+    for service in services {
+        containers.push((
+            ProblemState::UnderReplicated,
+            None,
+            format!("{}: {}", service.display_name(), service.summary()),
+        ));
+    }
+    let count = |state: ProblemState| containers.iter().filter(|(s, _, _)| *s == state).count();
+    let has_any = |states: &[ProblemState], severities: &[Severity]| {
+        containers.iter().any(|(state, severity, _)| match severity {
+            Some(severity) => severities.contains(severity),
+            None => states.contains(state),
+        })
+    };
+    let status = if has_any(&settings.critical, &[Severity::Critical, Severity::Error]) {
+        NagiosStatus::Critical
+    } else if !unreachable_hosts.is_empty() {
+        NagiosStatus::Unknown
+    } else if has_any(&settings.warning, &[Severity::Warning]) {
+        NagiosStatus::Warning
+    } else {
+        NagiosStatus::Ok
+    };
+    let mut summary = ProblemState::ALL
+        .iter()
+        .filter(|state| count(**state) > 0)
+        .map(|state| format!("{} {}", count(*state), state.description()))
+        .collect::<Vec<_>>();
+    if !unreachable_hosts.is_empty() {
+        summary.push(format!("{} unreachable host(s)", unreachable_hosts.len()));
+    }
+    let summary = if summary.is_empty() {
+        "no container with problems".to_owned()
+    } else {
+        summary.join(", ")
+    };
+    let perfdata = ProblemState::ALL
+        .iter()
+        .map(|state| {
+            // In the range syntax of the thresholds `0` alerts above 0, as a single container in the state does.
+            let warning = if settings.warning.contains(state) { "0" } else { "" };
+            let critical = if settings.critical.contains(state) { "0" } else { "" };
+            format!("{}={};{warning};{critical};0", state.perfdata_label(), count(*state))
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut output = format!("CONTAINERS {} - {summary} | {perfdata}\n", status.name());
+    for (_, _, detail) in containers {
+        writeln!(output, "{detail}").unwrap();
+    }
+    for host in unreachable_hosts {
+        writeln!(output, "{}: unreachable ({})", host.host, host.error).unwrap();
+    }
+    (status, output)
+}
+
+/// The output when the containers could not be checked.
+pub fn unknown(err: &dyn std::error::Error) -> (NagiosStatus, String) {
+    let status = NagiosStatus::Unknown;
+    (status, format!("CONTAINERS {} - {err}\n", status.name()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn settings() -> CheckSettings {
+        CheckSettings {
+            critical: vec![
+                ProblemState::Stopped,
+                ProblemState::Unhealthy,
+                ProblemState::UnderReplicated,
+            ],
+            warning: vec![ProblemState::Starting, ProblemState::NoHealth],
+        }
+    }
+
+    #[test]
+    fn critical_report() {
+        let (status, output) = report(
+            &settings(),
+            &[RunningContainerStatus {
+                name: "test1".to_string(),
+                health: Some(HealthStatusEnum::UNHEALTHY),
+                ..Default::default()
+            }],
+            &[
+                StoppedContainerStatus {
+                    name: "test2".to_string(),
+                    status: Some("exited".to_string()),
+                    ..Default::default()
+                },
+                StoppedContainerStatus {
+                    name: "test3".to_string(),
+                    status: None,
+                    ..Default::default()
+                },
+            ],
+            &[],
+            &[],
+        );
+        assert_eq!(status, NagiosStatus::Critical);
+        assert_eq!(
+            output,
+            "CONTAINERS CRITICAL - 2 stopped, 1 unhealthy | stopped=2;;0;0 unhealthy=1;;0;0 starting=0;0;;0 no_health=0;0;;0 under_replicated=0;;0;0
+test2: stopped (exited)
+test3: stopped
+test1: unhealthy
+"
+        );
+    }
+
+    #[test]
+    fn states_can_be_moved_between_warning_and_critical() {
+        let running_containers = [RunningContainerStatus {
+            name: "test1".to_string(),
+            health: Some(HealthStatusEnum::UNHEALTHY),
+            ..Default::default()
+        }];
+        let relaxed_settings = CheckSettings {
+            critical: vec![ProblemState::Stopped],
+            warning: vec![ProblemState::Unhealthy],
+        };
+        assert_eq!(
+            report(&relaxed_settings, &running_containers, &[], &[], &[]).0,
+            NagiosStatus::Warning
+        );
+        let ignoring_settings = CheckSettings {
+            critical: vec![],
+            warning: vec![],
+        };
+        assert_eq!(
+            report(&ignoring_settings, &running_containers, &[], &[], &[]).0,
+            NagiosStatus::Ok
+        );
+    }
+
+    #[test]
+    fn severity_label_overrides_the_state() {
+        let stopped = |severity| StoppedContainerStatus {
+            name: "test1".to_string(),
+            severity: Some(severity),
+            ..Default::default()
+        };
+        assert_eq!(
+            report(&settings(), &[], &[stopped(Severity::Info)], &[], &[]).0,
+            NagiosStatus::Ok
+        );
+        let (status, output) = report(&settings(), &[], &[stopped(Severity::Warning)], &[], &[]);
+        assert_eq!(status, NagiosStatus::Warning);
+        assert!(output.ends_with("\ntest1: stopped (warning)\n"), "{output}");
+        let running = [RunningContainerStatus {
+            name: "test2".to_string(),
+            health: Some(HealthStatusEnum::STARTING),
+            severity: Some(Severity::Error),
+            ..Default::default()
+        }];
+        assert_eq!(report(&settings(), &running, &[], &[], &[]).0, NagiosStatus::Critical);
+    }
+
+    #[test]
+    fn ok_report() {
+        let (status, output) = report(&settings(), &[], &[], &[], &[]);
+        assert_eq!(status.exit_code(), 0);
+        assert_eq!(
+            output,
+            "CONTAINERS OK - no container with problems | stopped=0;;0;0 unhealthy=0;;0;0 starting=0;0;;0 no_health=0;0;;0 under_replicated=0;;0;0\n"
+        );
+    }
+
+    #[test]
+    fn services_missing_replicas_are_critical() {
+        let (status, output) = report(
+            &settings(),
+            &[],
+            &[],
+            &[],
+            &[ServiceStatus {
+                name: "web".to_string(),
+                desired_replicas: 3,
+                running_replicas: 1,
+                task_state: Some("rejected".to_string()),
+                task_error: Some("no suitable node".to_string()),
+                host: None,
+            }],
+        );
+        assert_eq!(status, NagiosStatus::Critical);
+        assert_eq!(
+            output,
+            "CONTAINERS CRITICAL - 1 service(s) missing replicas | stopped=0;;0;0 unhealthy=0;;0;0 starting=0;0;;0 no_health=0;0;;0 under_replicated=1;;0;0
+web: 1/3 replicas running, rejected: no suitable node
+"
+        );
+    }
+
+    #[test]
+    fn unreachable_hosts_are_unknown_unless_critical() {
+        let unreachable_hosts = [UnreachableHost {
+            host: "host2".to_string(),
+            error: "connection refused".to_string(),
+        }];
+        let (status, output) = report(
+            &settings(),
+            &[RunningContainerStatus {
+                name: "test1".to_string(),
+                health: Some(HealthStatusEnum::STARTING),
+                host: Some("host1".to_string()),
+                ..Default::default()
+            }],
+            &[],
+            &unreachable_hosts,
+            &[],
+        );
+        assert_eq!(status, NagiosStatus::Unknown);
+        assert_eq!(
+            output,
+            "CONTAINERS UNKNOWN - 1 starting, 1 unreachable host(s) | stopped=0;;0;0 unhealthy=0;;0;0 starting=1;0;;0 no_health=0;0;;0 under_replicated=0;;0;0
+host1/test1: starting
+host2: unreachable (connection refused)
+"
+        );
+        let stopped_containers = [StoppedContainerStatus {
+            name: "test2".to_string(),
+            ..Default::default()
+        }];
+        assert_eq!(
+            report(&settings(), &[], &stopped_containers, &unreachable_hosts, &[]).0,
+            NagiosStatus::Critical
+        );
+    }
+}
@@ -0,0 +1,512 @@
+use super::alertmanager::{AlertmanagerNotifier, DEFAULT_ENDS_AFTER};
+use super::config::{NamedTarget, Target};
+use super::containers::{
+    ResolvedContainerStatus, ResolvedHost, RunningContainerStatus, StoppedContainerStatus, UnreachableHost,
+};
+use super::discord;
+use super::email::EmailNotifier;
+use super::metrics::Metrics;
+use super::msteams;
+use super::opsgenie::OpsgenieNotifier;
+use super::pagerduty::PagerDutyNotifier;
+use super::print::{self, OutputFormat};
+use super::slack;
+use super::swarm::ServiceStatus;
+use super::webhook::Webhook;
+use itertools::Itertools;
+use log::*;
+#[cfg(test)]
+use mockall::automock;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// What is sent to the notifiers after a check. The changes a target could not be sent are kept in the state.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Default)]
+pub struct Notification {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub running_containers: Vec<RunningContainerStatus>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stopped_containers: Vec<StoppedContainerStatus>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub resolved_containers: Vec<ResolvedContainerStatus>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unreachable_hosts: Vec<UnreachableHost>,
+    /// The hosts that could not be checked before and can be again.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub resolved_hosts: Vec<ResolvedHost>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub services: Vec<ServiceStatus>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// The running containers without problems, which are only written to the metrics.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub healthy_containers: Vec<RunningContainerStatus>,
+}
+
+impl Notification {
+    pub fn is_empty(&self) -> bool {
+        self.running_containers.is_empty()
+            && self.stopped_containers.is_empty()
+            && self.resolved_containers.is_empty()
+            && self.unreachable_hosts.is_empty()
+            && self.resolved_hosts.is_empty()
+            && self.services.is_empty()
+    }
+
+    /// If there is anything wrong, as opposed to only containers and hosts that recovered.
+    pub fn has_problems(&self) -> bool {
+        !self.running_containers.is_empty()
+            || !self.stopped_containers.is_empty()
+            || !self.unreachable_hosts.is_empty()
+            || !self.services.is_empty()
+    }
+
+    /// The notification for the target named `target_name`, without the containers sent to another of the
+    /// `target_names` by their `notifyhealth.target` label. The Swarm services and the hosts go to every target.
+    pub fn for_target(&self, target_name: &str, target_names: &[String]) -> Notification {
+        let is_for_target = |target: &Option<String>| match target {
+            Some(target) => target == target_name || !target_names.contains(target),
+            None => true,
+        };
+        Notification {
+            running_containers: self
+                .running_containers
+                .iter()
+                .filter(|c| is_for_target(&c.target))
+                .cloned()
+                .collect(),
+            stopped_containers: self
+                .stopped_containers
+                .iter()
+                .filter(|c| is_for_target(&c.target))
+                .cloned()
+                .collect(),
+            resolved_containers: self
+                .resolved_containers
+                .iter()
+                .filter(|c| is_for_target(&c.target))
+                .cloned()
+                .collect(),
+            ..self.clone()
+        }
+    }
+
+    /// These changes, which could not be sent before, followed by the new `changes`, which replace the ones of the same
+    /// containers and hosts. A recovery is dropped when the container has a problem again, as the problem is sent
+    /// before the recoveries and would be ended by it.
+    fn followed_by(mut self, changes: &Notification) -> Notification {
+        let has_problem = |name: &str| {
+            changes.running_containers.iter().any(|c| c.display_name() == name)
+                || changes.stopped_containers.iter().any(|c| c.display_name() == name)
+        };
+        self.running_containers.retain(|c| !has_problem(&c.display_name()));
+        self.stopped_containers.retain(|c| !has_problem(&c.display_name()));
+        self.resolved_containers.retain(|c| {
+            !has_problem(&c.display_name())
+                && !changes
+                    .resolved_containers
+                    .iter()
+                    .any(|resolved| resolved.display_name() == c.display_name())
+        });
+        let host_changed = |name: &str| {
+            changes.unreachable_hosts.iter().any(|host| host.host == name)
+                || changes.resolved_hosts.iter().any(|host| host.host == name)
+        };
+        self.unreachable_hosts.retain(|h| !host_changed(&h.host));
+        self.resolved_hosts.retain(|h| !host_changed(&h.host));
+        self.services.retain(|s| {
+            !changes
+                .services
+                .iter()
+                .any(|service| service.display_name() == s.display_name())
+        });
+        self.running_containers
+            .extend(changes.running_containers.iter().cloned());
+        self.stopped_containers
+            .extend(changes.stopped_containers.iter().cloned());
+        self.resolved_containers
+            .extend(changes.resolved_containers.iter().cloned());
+        self.unreachable_hosts.extend(changes.unreachable_hosts.iter().cloned());
+        self.resolved_hosts.extend(changes.resolved_hosts.iter().cloned());
+        self.services.extend(changes.services.iter().cloned());
+        Notification {
+            hostname: changes.hostname.clone(),
+            healthy_containers: changes.healthy_containers.clone(),
+            ..self
+        }
+    }
+
+    fn targets(&self) -> impl Iterator<Item = &String> {
+        let running = self.running_containers.iter().filter_map(|c| c.target.as_ref());
+        let stopped = self.stopped_containers.iter().filter_map(|c| c.target.as_ref());
+        let resolved = self.resolved_containers.iter().filter_map(|c| c.target.as_ref());
+        running.chain(stopped).chain(resolved)
+    }
+}
+
+#[cfg_attr(test, automock)]
+pub trait Notifier {
+    fn name(&self) -> String;
+    fn notify(&self, notification: &Notification) -> Result<(), Box<dyn std::error::Error>>;
+    /// Notifiers that only get what changed since the last notification, when a state directory is set. The
+    /// others always get the full status, with the containers that recovered since the last check.
+    fn only_changes(&self) -> bool {
+        true
+    }
+}
+
+pub struct WebhookNotifier {
+    name: String,
+    url: String,
+    webhook: Webhook,
+}
+
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    fn notify(&self, notification: &Notification) -> Result<(), Box<dyn std::error::Error>> {
+        self.webhook.notify(&self.url, notification)
+    }
+}
+
+pub struct PrintNotifier {
+    name: String,
+    output: OutputFormat,
+}
+
+impl Notifier for PrintNotifier {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    fn notify(&self, notification: &Notification) -> Result<(), Box<dyn std::error::Error>> {
+        print::output(self.output, notification)
+    }
+    fn only_changes(&self) -> bool {
+        false
+    }
+}
+
+/// Writes the full status for the node_exporter textfile collector on every check.
+pub struct TextfileNotifier {
+    name: String,
+    directory: PathBuf,
+}
+
+impl Notifier for TextfileNotifier {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    fn notify(&self, notification: &Notification) -> Result<(), Box<dyn std::error::Error>> {
+        print::textfile(
+            &self.directory,
+            &notification.running_containers,
+            &notification.healthy_containers,
+            &notification.stopped_containers,
+            &notification.unreachable_hosts,
+            &notification.services,
+        )
+    }
+    fn only_changes(&self) -> bool {
+        false
+    }
+}
+
+pub fn from_targets(targets: &[NamedTarget]) -> Vec<Box<dyn Notifier>> {
+    targets
+        .iter()
+        .enumerate()
+        .map(|(index, named_target)| -> Box<dyn Notifier> {
+            let number = index + 1;
+            let name = |target_type: &str| {
+                named_target
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("{target_type} (target {number})"))
+            };
+            match &named_target.target {
+                Target::Print { output } => Box::new(PrintNotifier {
+                    name: named_target.name.clone().unwrap_or_else(|| "print".to_owned()),
+                    output: *output,
+                }),
+                Target::Textfile { directory } => Box::new(TextfileNotifier {
+                    name: named_target.name.clone().unwrap_or_else(|| "textfile".to_owned()),
+                    directory: directory.clone(),
+                }),
+                Target::Teams { url } => Box::new(WebhookNotifier {
+                    name: name("teams"),
+                    url: url.clone(),
+                    webhook: Webhook::new(Some(msteams::format_message)),
+                }),
+                Target::Slack { url } => Box::new(WebhookNotifier {
+                    name: name("slack"),
+                    url: url.clone(),
+                    webhook: Webhook::new(Some(slack::format_message)),
+                }),
+                Target::Discord { url } => Box::new(WebhookNotifier {
+                    name: name("discord"),
+                    url: url.clone(),
+                    webhook: Webhook::new(Some(discord::format_message)),
+                }),
+                Target::Email(email_settings) => Box::new(EmailNotifier::new(name("email"), email_settings.clone())),
+                Target::Pagerduty(pagerduty_settings) => {
+                    Box::new(PagerDutyNotifier::new(name("pagerduty"), pagerduty_settings.clone()))
+                }
+                Target::Opsgenie(opsgenie_settings) => {
+                    Box::new(OpsgenieNotifier::new(name("opsgenie"), opsgenie_settings.clone()))
+                }
+                Target::Alertmanager(alertmanager_settings) => Box::new(AlertmanagerNotifier::new(
+                    name("alertmanager"),
+                    &alertmanager_settings.url,
+                    alertmanager_settings.ends_after.unwrap_or(DEFAULT_ENDS_AFTER),
+                )),
+                Target::Webhook { url } => Box::new(WebhookNotifier {
+                    name: name("webhook"),
+                    url: url.clone(),
+                    webhook: Webhook::default(),
+                }),
+            }
+        })
+        .collect()
+}
+
+/// Sends the notification through every notifier, even when some of them fail, using `changes` for the notifiers
+/// that only get what changed. Each failure is logged and the error returned lists the notifiers that failed. The
+/// containers with a `notifyhealth.target` label only go to the notifier with that name, or to all of them when none
+/// has it. The changes a notifier failed to send are kept in `pending`, by the notifier name, and sent before its next
+/// changes, so a trigger or a recovery is not lost.
+pub fn notify_all(
+    notifiers: &[Box<dyn Notifier>],
+    notification: &Notification,
+    changes: &Notification,
+    pending: &mut BTreeMap<String, Notification>,
+    metrics: &Metrics,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let names = notifiers.iter().map(|notifier| notifier.name()).collect::<Vec<_>>();
+    for target in notification.targets().chain(changes.targets()).unique() {
+        if !names.contains(target) {
+            warn!("No target is named {target}, the containers labelled with it are sent to all the targets.");
+        }
+    }
+    pending.retain(|name, _| names.contains(name));
+    let mut failed = vec![];
+    for notifier in notifiers {
+        let notifier_notification = if notifier.only_changes() {
+            match pending.remove(&notifier.name()) {
+                Some(pending_changes) => pending_changes.followed_by(changes),
+                None => changes.clone(),
+            }
+        } else {
+            notification.clone()
+        };
+        let notifier_notification = notifier_notification.for_target(&notifier.name(), &names);
+        match notifier.notify(&notifier_notification) {
+            Ok(()) => {
+                info!("Notified through {}.", notifier.name());
+                if !notifier_notification.is_empty() {
+                    metrics.record_notification(&notifier.name(), true);
+                }
+            }
+            Err(err) => {
+                error!("Error notifying through {}: {err}", notifier.name());
+                metrics.record_notification(&notifier.name(), false);
+                if notifier.only_changes() && !notifier_notification.is_empty() {
+                    pending.insert(notifier.name(), notifier_notification);
+                }
+                failed.push(notifier.name());
+            }
+        }
+    }
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Failed to notify through: {}.", failed.join(", ")).into())
+    }
+}
+
+/// Where an alert or incident comes from: the host of the container, or notifyhealth itself without one.
+pub(crate) fn source(hostname: Option<&str>) -> String {
+    hostname.unwrap_or("notifyhealth").to_owned()
+}
+
+/// Appends the host to a message, when there is one.
+pub(crate) fn with_hostname(message: String, hostname: Option<&str>) -> String {
+    match hostname {
+        Some(hostname) => format!("{message} on {hostname}"),
+        None => message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn notification() -> Notification {
+        Notification {
+            stopped_containers: vec![StoppedContainerStatus {
+                name: "test1".to_owned(),
+                status: Some("exited".to_owned()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn mock_notifier(name: &'static str, fails: bool, only_changes: bool) -> Box<dyn Notifier> {
+        let mut notifier = MockNotifier::new();
+        notifier.expect_name().return_const(name.to_owned());
+        notifier.expect_only_changes().return_const(only_changes);
+        let expected = if only_changes {
+            Notification::default()
+        } else {
+            notification()
+        };
+        notifier
+            .expect_notify()
+            .withf(move |n| *n == expected)
+            .times(1)
+            .returning(move |_| if fails { Err("boom".into()) } else { Ok(()) });
+        Box::new(notifier)
+    }
+
+    #[test]
+    fn all_notifiers_are_called_even_when_one_fails() {
+        let notifiers = vec![
+            mock_notifier("first", false, true),
+            mock_notifier("second", true, true),
+            mock_notifier("third", false, false),
+        ];
+        let metrics = Metrics::default();
+        let mut pending = BTreeMap::new();
+        let result = notify_all(
+            &notifiers,
+            &notification(),
+            &Notification::default(),
+            &mut pending,
+            &metrics,
+        );
+        assert_eq!(result.unwrap_err().to_string(), "Failed to notify through: second.");
+        assert!(metrics
+            .render()
+            .contains("notifyhealth_notifications_failed_total{notifier=\"second\"} 1"));
+        assert!(metrics
+            .render()
+            .contains("notifyhealth_notifications_sent_total{notifier=\"third\"} 1"));
+        assert!(!metrics.render().contains("notifier=\"first\""));
+    }
+
+    #[test]
+    fn failed_changes_are_sent_again_with_the_next_ones() {
+        let resolved = |name: &str| ResolvedContainerStatus {
+            name: name.to_owned(),
+            previous_status: "stopped".to_owned(),
+            ..Default::default()
+        };
+        let changes = Notification {
+            resolved_containers: vec![resolved("test2"), resolved("test3")],
+            ..notification()
+        };
+        let mut failing = MockNotifier::new();
+        failing.expect_name().return_const("pagerduty".to_owned());
+        failing.expect_only_changes().return_const(true);
+        failing
+            .expect_notify()
+            .times(1)
+            .returning(|_| Err("Internal Server Error".into()));
+        let mut pending = BTreeMap::new();
+        let metrics = Metrics::default();
+        let failing_notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(failing)];
+        assert!(notify_all(&failing_notifiers, &changes, &changes, &mut pending, &metrics).is_err());
+        assert_eq!(pending, BTreeMap::from([("pagerduty".to_owned(), changes.clone())]));
+        let next_changes = Notification {
+            stopped_containers: vec![StoppedContainerStatus {
+                name: "test2".to_owned(),
+                status: Some("dead".to_owned()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut notifier = MockNotifier::new();
+        notifier.expect_name().return_const("pagerduty".to_owned());
+        notifier.expect_only_changes().return_const(true);
+        let expected = Notification {
+            stopped_containers: vec![
+                notification().stopped_containers[0].clone(),
+                next_changes.stopped_containers[0].clone(),
+            ],
+            resolved_containers: vec![resolved("test3")],
+            ..Default::default()
+        };
+        notifier
+            .expect_notify()
+            .withf(move |n| *n == expected)
+            .times(1)
+            .returning(|_| Ok(()));
+        let notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(notifier)];
+        notify_all(&notifiers, &next_changes, &next_changes, &mut pending, &metrics).unwrap();
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn labelled_containers_only_go_to_their_target() {
+        let stopped = |name: &str, target: Option<&str>| StoppedContainerStatus {
+            name: name.to_owned(),
+            target: target.map(str::to_owned),
+            ..Default::default()
+        };
+        let notification = Notification {
+            stopped_containers: vec![
+                stopped("test1", None),
+                stopped("test2", Some("backend")),
+                stopped("test3", Some("unknown")),
+            ],
+            ..Default::default()
+        };
+        let names = |target_notification: &Notification| {
+            target_notification
+                .stopped_containers
+                .iter()
+                .map(|c| c.name.clone())
+                .collect::<Vec<_>>()
+        };
+        let target_names = ["backend".to_owned(), "frontend".to_owned()];
+        assert_eq!(
+            names(&notification.for_target("backend", &target_names)),
+            vec!["test1", "test2", "test3"]
+        );
+        assert_eq!(
+            names(&notification.for_target("frontend", &target_names)),
+            vec!["test1", "test3"]
+        );
+    }
+
+    #[test]
+    fn notifiers_are_created_from_targets() {
+        let notifiers = from_targets(&[
+            Target::Print {
+                output: OutputFormat::Text,
+            }
+            .into(),
+            Target::Teams {
+                url: "https://example.com/teams".to_owned(),
+            }
+            .into(),
+            NamedTarget {
+                name: Some("backend".to_owned()),
+                target: Target::Webhook {
+                    url: "https://example.com/hook".to_owned(),
+                },
+            },
+        ]);
+        assert_eq!(
+            notifiers.iter().map(|n| n.name()).collect::<Vec<_>>(),
+            vec!["print", "teams (target 2)", "backend"]
+        );
+        assert_eq!(
+            notifiers.iter().map(|n| n.only_changes()).collect::<Vec<_>>(),
+            vec![false, true, true]
+        );
+    }
+}
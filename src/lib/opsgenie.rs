@@ -0,0 +1,479 @@
+use super::containers::{
+    ResolvedContainerStatus, ResolvedHost, RunningContainerStatus, Severity, StoppedContainerStatus, UnreachableHost,
+};
+use super::notifier::{source, with_hostname, Notification, Notifier};
+use super::swarm::{ServiceStatus, UNDER_REPLICATED};
+use super::webhook::{self, MyHttpClient, SendsHttp};
+use isahc::{HttpClient, Request};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+pub const DEFAULT_API_URL: &str = "https://api.opsgenie.com";
+
+#[derive(clap::ValueEnum, Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy)]
+pub enum Priority {
+    P1,
+    P2,
+    P3,
+    P4,
+    P5,
+}
+
+/// The priority of the containers with a `notifyhealth.severity` label, instead of the configured ones.
+impl From<Severity> for Priority {
+    fn from(severity: Severity) -> Self {
+        match severity {
+            Severity::Critical => Priority::P1,
+            Severity::Error => Priority::P2,
+            Severity::Warning => Priority::P3,
+            Severity::Info => Priority::P5,
+        }
+    }
+}
+
+#[derive(clap::Args, PartialEq, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct OpsgenieSettings {
+    #[clap(long, help = "Opsgenie API key", env = "NOTIFYHEALTH_OPSGENIE_API_KEY")]
+    pub api_key: String,
+    #[clap(
+        long,
+        default_value = DEFAULT_API_URL,
+        help = "Opsgenie API url, use https://api.eu.opsgenie.com for the EU instance"
+    )]
+    #[serde(default = "default_api_url", deserialize_with = "super::config::deserialize_url")]
+    pub api_url: String,
+    #[clap(
+        long,
+        value_enum,
+        default_value = "p2",
+        help = "Priority of the alerts for stopped containers"
+    )]
+    #[serde(default = "default_stopped_priority")]
+    pub stopped_priority: Priority,
+    #[clap(
+        long,
+        value_enum,
+        default_value = "p3",
+        help = "Priority of the alerts for unhealthy containers"
+    )]
+    #[serde(default = "default_unhealthy_priority")]
+    pub unhealthy_priority: Priority,
+}
+
+/// The settings are logged, so the API key is left out.
+impl fmt::Debug for OpsgenieSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpsgenieSettings")
+            .field("api_key", &"***")
+            .field("api_url", &self.api_url)
+            .field("stopped_priority", &self.stopped_priority)
+            .field("unhealthy_priority", &self.unhealthy_priority)
+            .finish()
+    }
+}
+
+fn default_api_url() -> String {
+    DEFAULT_API_URL.to_owned()
+}
+
+fn default_stopped_priority() -> Priority {
+    Priority::P2
+}
+
+fn default_unhealthy_priority() -> Priority {
+    Priority::P3
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct CreateAlert {
+    pub message: String,
+    pub alias: String,
+    pub description: String,
+    pub priority: Priority,
+    pub source: String,
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct CloseAlert {
+    pub source: String,
+    pub note: String,
+}
+
+pub struct OpsgenieNotifier {
+    name: String,
+    settings: OpsgenieSettings,
+    http_client: Box<dyn SendsHttp + Sync>,
+}
+
+impl OpsgenieNotifier {
+    pub fn new(name: String, settings: OpsgenieSettings) -> Self {
+        OpsgenieNotifier {
+            name,
+            settings,
+            http_client: Box::new(MyHttpClient {
+                client: HttpClient::new().expect("shared client failed to initialize"),
+            }),
+        }
+    }
+
+    fn post<T: Serialize>(&self, path: &str, body: &T) -> Result<(), Box<dyn std::error::Error>> {
+        let req = Request::post(format!("{}{path}", self.settings.api_url.trim_end_matches('/')))
+            .header("content-type", "application/json")
+            .header("authorization", format!("GenieKey {}", self.settings.api_key))
+            .body(serde_json::to_vec(body)?)?;
+        webhook::send(self.http_client.as_ref(), req)
+    }
+}
+
+impl Notifier for OpsgenieNotifier {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn notify(&self, notification: &Notification) -> Result<(), Box<dyn std::error::Error>> {
+        let hostname = notification.hostname.as_deref();
+        for container in &notification.running_containers {
+            let host = container.host.as_deref().or(hostname);
+            let priority = container
+                .severity
+                .map_or(self.settings.unhealthy_priority, Priority::from);
+            let alert = running_alert(container, host, priority);
+            info!("Alert to be created: {:?}", alert);
+            self.post("/v2/alerts", &alert)?;
+        }
+        for container in &notification.stopped_containers {
+            let host = container.host.as_deref().or(hostname);
+            let priority = container
+                .severity
+                .map_or(self.settings.stopped_priority, Priority::from);
+            let alert = stopped_alert(container, host, priority);
+            info!("Alert to be created: {:?}", alert);
+            self.post("/v2/alerts", &alert)?;
+        }
+        for service in &notification.services {
+            let host = service.host.as_deref().or(hostname);
+            let alert = service_alert(service, host, self.settings.stopped_priority);
+            info!("Alert to be created: {:?}", alert);
+            self.post("/v2/alerts", &alert)?;
+        }
+        for container in &notification.resolved_containers {
+            let host = container.host.as_deref().or(hostname);
+            let alias = alias(host, &container.name);
+            info!("Alert to be closed: {alias}");
+            self.post(
+                &format!("/v2/alerts/{}/close?identifierType=alias", encode_path_segment(&alias)),
+                &close_alert(container, host),
+            )?;
+        }
+        for host in &notification.unreachable_hosts {
+            let alert = unreachable_alert(host, self.settings.stopped_priority);
+            info!("Alert to be created: {:?}", alert);
+            self.post("/v2/alerts", &alert)?;
+        }
+        for host in &notification.resolved_hosts {
+            let alias = alias(Some(&host.host), "docker");
+            info!("Alert to be closed: {alias}");
+            self.post(
+                &format!("/v2/alerts/{}/close?identifierType=alias", encode_path_segment(&alias)),
+                &close_host_alert(host),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The same container on the same host always has the same alias, so Opsgenie deduplicates the alerts and they can be
+/// closed later.
+fn alias(hostname: Option<&str>, container_name: &str) -> String {
+    match hostname {
+        Some(hostname) => format!("{container_name}@{hostname}"),
+        None => container_name.to_owned(),
+    }
+}
+
+fn running_alert(container: &RunningContainerStatus, hostname: Option<&str>, priority: Priority) -> CreateAlert {
+    let health = container
+        .health
+        .map(|health| health.to_string())
+        .unwrap_or_else(|| "no health status".to_owned());
+    CreateAlert {
+        message: with_hostname(format!("Container {} is {health}", container.name), hostname),
+        alias: alias(hostname, &container.name),
+        description: format!("The container {} is running, with health: {health}.", container.name),
+        priority,
+        source: source(hostname),
+        tags: vec!["notifyhealth".to_owned(), "running".to_owned()],
+    }
+}
+
+fn stopped_alert(container: &StoppedContainerStatus, hostname: Option<&str>, priority: Priority) -> CreateAlert {
+    let status = container.status.clone().unwrap_or_else(|| "no status".to_owned());
+    CreateAlert {
+        message: with_hostname(format!("Container {} is not running", container.name), hostname),
+        alias: alias(hostname, &container.name),
+        description: format!(
+            "The container {} is not running, with status: {status}.",
+            container.name
+        ),
+        priority,
+        source: source(hostname),
+        tags: vec!["notifyhealth".to_owned(), "stopped".to_owned()],
+    }
+}
+
+/// Uses the priority of the stopped containers, as the missing replicas are containers that are not running.
+fn service_alert(service: &ServiceStatus, hostname: Option<&str>, priority: Priority) -> CreateAlert {
+    CreateAlert {
+        message: with_hostname(format!("Service {} is missing replicas", service.name), hostname),
+        alias: alias(hostname, &service.name),
+        description: format!("The service {} has {}.", service.name, service.summary()),
+        priority,
+        source: source(hostname),
+        tags: vec!["notifyhealth".to_owned(), UNDER_REPLICATED.to_owned()],
+    }
+}
+
+/// Uses the priority of the stopped containers, as none of the containers of the host are known to be running.
+fn unreachable_alert(host: &UnreachableHost, priority: Priority) -> CreateAlert {
+    CreateAlert {
+        message: format!("Docker host {} could not be checked", host.host),
+        alias: alias(Some(&host.host), "docker"),
+        description: format!("The containers of {} could not be checked: {}.", host.host, host.error),
+        priority,
+        source: source(Some(&host.host)),
+        tags: vec!["notifyhealth".to_owned(), "unreachable".to_owned()],
+    }
+}
+
+fn close_host_alert(host: &ResolvedHost) -> CloseAlert {
+    let down_for = humantime::format_duration(Duration::from_secs(host.down_for_seconds));
+    CloseAlert {
+        source: source(Some(&host.host)),
+        note: format!("Docker host can be checked again, it could not be for {down_for}."),
+    }
+}
+
+fn close_alert(container: &ResolvedContainerStatus, hostname: Option<&str>) -> CloseAlert {
+    let down_for = humantime::format_duration(Duration::from_secs(container.down_for_seconds));
+    CloseAlert {
+        source: source(hostname),
+        note: if container.previous_status == UNDER_REPLICATED {
+            format!("Service recovered, it was missing replicas for {down_for}.")
+        } else {
+            format!(
+                "Container recovered, it was {} for {down_for}.",
+                container.previous_status
+            )
+        },
+    }
+}
+
+fn encode_path_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'@' => (byte as char).to_string(),
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webhook::MockSendsHttp;
+    use bollard::models::HealthStatusEnum;
+    use isahc::{Body, Response};
+    use pretty_assertions::assert_eq;
+
+    const API_URL: &str = "http://localhost:8080";
+
+    fn notifier(http_client: MockSendsHttp) -> OpsgenieNotifier {
+        OpsgenieNotifier {
+            name: "opsgenie".to_owned(),
+            settings: OpsgenieSettings {
+                api_key: "key".to_owned(),
+                api_url: API_URL.to_owned(),
+                stopped_priority: Priority::P2,
+                unhealthy_priority: Priority::P3,
+            },
+            http_client: Box::new(http_client),
+        }
+    }
+
+    fn expect_alert(client: &mut MockSendsHttp, expected: CreateAlert) {
+        client
+            .expect_send()
+            .withf(move |req| {
+                *req.uri() == *format!("{API_URL}/v2/alerts")
+                    && req.method() == "POST"
+                    && req.headers()["authorization"] == "GenieKey key"
+                    && serde_json::from_slice::<CreateAlert>(req.body()).unwrap() == expected
+            })
+            .times(1)
+            .return_once(|_| Ok(Response::builder().status(202).body(Body::from("")).unwrap()));
+    }
+
+    #[test]
+    fn alerts_are_created_with_priorities() {
+        let mut client = MockSendsHttp::new();
+        expect_alert(
+            &mut client,
+            CreateAlert {
+                message: "Container test1 is unhealthy on myhostname".to_owned(),
+                alias: "test1@myhostname".to_owned(),
+                description: "The container test1 is running, with health: unhealthy.".to_owned(),
+                priority: Priority::P3,
+                source: "myhostname".to_owned(),
+                tags: vec!["notifyhealth".to_owned(), "running".to_owned()],
+            },
+        );
+        expect_alert(
+            &mut client,
+            CreateAlert {
+                message: "Container test2 is not running on myhostname".to_owned(),
+                alias: "test2@myhostname".to_owned(),
+                description: "The container test2 is not running, with status: exited.".to_owned(),
+                priority: Priority::P2,
+                source: "myhostname".to_owned(),
+                tags: vec!["notifyhealth".to_owned(), "stopped".to_owned()],
+            },
+        );
+        notifier(client)
+            .notify(&Notification {
+                running_containers: vec![RunningContainerStatus {
+                    name: "test1".to_string(),
+                    health: Some(HealthStatusEnum::UNHEALTHY),
+                    ..Default::default()
+                }],
+                stopped_containers: vec![StoppedContainerStatus {
+                    name: "test2".to_string(),
+                    status: Some("exited".to_string()),
+                    ..Default::default()
+                }],
+                hostname: Some("myhostname".to_owned()),
+                ..Default::default()
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn severity_label_sets_the_priority() {
+        let mut client = MockSendsHttp::new();
+        expect_alert(
+            &mut client,
+            CreateAlert {
+                message: "Container test1 is not running".to_owned(),
+                alias: "test1".to_owned(),
+                description: "The container test1 is not running, with status: exited.".to_owned(),
+                priority: Priority::P5,
+                source: "notifyhealth".to_owned(),
+                tags: vec!["notifyhealth".to_owned(), "stopped".to_owned()],
+            },
+        );
+        notifier(client)
+            .notify(&Notification {
+                stopped_containers: vec![StoppedContainerStatus {
+                    name: "test1".to_string(),
+                    status: Some("exited".to_string()),
+                    severity: Some(Severity::Info),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn alerts_are_closed_when_containers_recover() {
+        let mut client = MockSendsHttp::new();
+        client
+            .expect_send()
+            .withf(|req| {
+                *req.uri() == *format!("{API_URL}/v2/alerts/test1@my%20host/close?identifierType=alias")
+                    && serde_json::from_slice::<CloseAlert>(req.body()).unwrap()
+                        == CloseAlert {
+                            source: "my host".to_owned(),
+                            note: "Container recovered, it was stopped for 2m.".to_owned(),
+                        }
+            })
+            .times(1)
+            .return_once(|_| Ok(Response::builder().status(202).body(Body::from("")).unwrap()));
+        notifier(client)
+            .notify(&Notification {
+                resolved_containers: vec![ResolvedContainerStatus {
+                    name: "test1".to_string(),
+                    previous_status: "stopped".to_string(),
+                    down_for_seconds: 120,
+                    ..Default::default()
+                }],
+                hostname: Some("my host".to_owned()),
+                ..Default::default()
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn alerts_are_closed_when_hosts_can_be_checked_again() {
+        let mut client = MockSendsHttp::new();
+        client
+            .expect_send()
+            .withf(|req| {
+                *req.uri() == *format!("{API_URL}/v2/alerts/docker@host1/close?identifierType=alias")
+                    && serde_json::from_slice::<CloseAlert>(req.body()).unwrap()
+                        == CloseAlert {
+                            source: "host1".to_owned(),
+                            note: "Docker host can be checked again, it could not be for 5m.".to_owned(),
+                        }
+            })
+            .times(1)
+            .return_once(|_| Ok(Response::builder().status(202).body(Body::from("")).unwrap()));
+        notifier(client)
+            .notify(&Notification {
+                resolved_hosts: vec![ResolvedHost {
+                    host: "host1".to_string(),
+                    down_for_seconds: 300,
+                }],
+                ..Default::default()
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn errors_are_returned() {
+        let mut client = MockSendsHttp::new();
+        client
+            .expect_send()
+            .times(1)
+            .return_once(|_| Ok(Response::builder().status(422).body(Body::from("invalid")).unwrap()));
+        let result = notifier(client).notify(&Notification {
+            stopped_containers: vec![StoppedContainerStatus {
+                name: "test1".to_string(),
+                status: None,
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Error: status code: 422 Unprocessable Entity. Body: invalid"
+        );
+    }
+
+    #[test]
+    fn debug_hides_api_key() {
+        let settings = OpsgenieSettings {
+            api_key: "secret-key".to_owned(),
+            api_url: API_URL.to_owned(),
+            stopped_priority: Priority::P2,
+            unhealthy_priority: Priority::P3,
+        };
+        let debug = format!("{:?}", settings);
+        assert!(!debug.contains("secret-key"));
+        assert!(debug.contains("api_key: \"***\""));
+    }
+}
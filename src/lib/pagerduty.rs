@@ -0,0 +1,374 @@
+pub use super::containers::Severity;
+use super::containers::{RunningContainerStatus, StoppedContainerStatus};
+use super::notifier::{source, with_hostname, Notification, Notifier};
+use super::webhook::Webhook;
+use bollard::models::HealthStatusEnum;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+pub const DEFAULT_EVENTS_URL: &str = "https://events.pagerduty.com/v2/enqueue";
+
+#[derive(clap::Args, PartialEq, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PagerDutySettings {
+    #[clap(
+        long,
+        help = "Integration key of the PagerDuty service",
+        env = "NOTIFYHEALTH_PAGERDUTY_ROUTING_KEY"
+    )]
+    pub routing_key: String,
+    #[clap(long, default_value = DEFAULT_EVENTS_URL, help = "PagerDuty Events API v2 url")]
+    #[serde(default = "default_events_url", deserialize_with = "super::config::deserialize_url")]
+    pub events_url: String,
+}
+
+/// The settings are logged, so the routing key is left out.
+impl fmt::Debug for PagerDutySettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PagerDutySettings")
+            .field("routing_key", &"***")
+            .field("events_url", &self.events_url)
+            .finish()
+    }
+}
+
+fn default_events_url() -> String {
+    DEFAULT_EVENTS_URL.to_owned()
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub routing_key: String,
+    pub event_action: EventAction,
+    pub dedup_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<Payload>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventAction {
+    Trigger,
+    Resolve,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Payload {
+    pub summary: String,
+    pub source: String,
+    pub severity: Severity,
+    pub component: String,
+}
+
+pub struct PagerDutyNotifier {
+    name: String,
+    settings: PagerDutySettings,
+    webhook: Webhook,
+}
+
+impl PagerDutyNotifier {
+    pub fn new(name: String, settings: PagerDutySettings) -> Self {
+        PagerDutyNotifier {
+            name,
+            settings,
+            webhook: Webhook::default(),
+        }
+    }
+}
+
+impl Notifier for PagerDutyNotifier {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn notify(&self, notification: &Notification) -> Result<(), Box<dyn std::error::Error>> {
+        for event in events(&self.settings.routing_key, notification) {
+            info!(
+                "Event to be sent: {:?} {}, {:?}",
+                event.event_action, event.dedup_key, event.payload
+            );
+            self.webhook
+                .post(&self.settings.events_url, serde_json::to_vec(&event)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// One event for each container: a trigger for the containers with problems and a resolve for the recovered ones. The
+/// hosts that could not be checked are triggered too, and resolved when they can be checked again.
+pub fn events(routing_key: &str, notification: &Notification) -> Vec<Event> {
+    let hostname = notification.hostname.as_deref();
+    let trigger = |host: Option<&str>, component: &str, summary: String, severity: Severity| Event {
+        routing_key: routing_key.to_owned(),
+        event_action: EventAction::Trigger,
+        dedup_key: dedup_key(host, component),
+        payload: Some(Payload {
+            summary,
+            source: source(host),
+            severity,
+            component: component.to_owned(),
+        }),
+    };
+    let mut events = vec![];
+    for container in &notification.running_containers {
+        let host = container.host.as_deref().or(hostname);
+        events.push(trigger(
+            host,
+            &container.name,
+            running_summary(container, host),
+            container.severity.unwrap_or_else(|| running_severity(container)),
+        ));
+    }
+    for container in &notification.stopped_containers {
+        let host = container.host.as_deref().or(hostname);
+        events.push(trigger(
+            host,
+            &container.name,
+            stopped_summary(container, host),
+            container.severity.unwrap_or(Severity::Critical),
+        ));
+    }
+    for service in &notification.services {
+        let host = service.host.as_deref().or(hostname);
+        events.push(trigger(
+            host,
+            &service.name,
+            with_hostname(
+                format!("Service {} is missing replicas: {}", service.name, service.summary()),
+                host,
+            ),
+            Severity::Critical,
+        ));
+    }
+    for container in &notification.resolved_containers {
+        events.push(Event {
+            routing_key: routing_key.to_owned(),
+            event_action: EventAction::Resolve,
+            dedup_key: dedup_key(container.host.as_deref().or(hostname), &container.name),
+            payload: None,
+        });
+    }
+    for host in &notification.unreachable_hosts {
+        events.push(trigger(
+            Some(&host.host),
+            "docker",
+            format!("Docker host {} could not be checked: {}", host.host, host.error),
+            Severity::Critical,
+        ));
+    }
+    for host in &notification.resolved_hosts {
+        events.push(Event {
+            routing_key: routing_key.to_owned(),
+            event_action: EventAction::Resolve,
+            dedup_key: dedup_key(Some(&host.host), "docker"),
+            payload: None,
+        });
+    }
+    events
+}
+
+/// Stable for the same container or Swarm service on the same host, so PagerDuty groups the triggers and resolves the
+/// incident.
+fn dedup_key(hostname: Option<&str>, container_name: &str) -> String {
+    match hostname {
+        Some(hostname) => format!("notifyhealth/{hostname}/{container_name}"),
+        None => format!("notifyhealth/{container_name}"),
+    }
+}
+
+fn running_severity(container: &RunningContainerStatus) -> Severity {
+    match container.health {
+        Some(HealthStatusEnum::UNHEALTHY) => Severity::Error,
+        Some(HealthStatusEnum::STARTING) => Severity::Warning,
+        _ => Severity::Info,
+    }
+}
+
+fn running_summary(container: &RunningContainerStatus, hostname: Option<&str>) -> String {
+    let summary = match &container.health {
+        Some(health) => format!("Container {} is {health}", container.name),
+        None => format!("Container {} has no health status", container.name),
+    };
+    with_hostname(summary, hostname)
+}
+
+fn stopped_summary(container: &StoppedContainerStatus, hostname: Option<&str>) -> String {
+    let summary = match &container.status {
+        Some(status) => format!("Container {} is not running ({status})", container.name),
+        None => format!("Container {} is not running", container.name),
+    };
+    with_hostname(summary, hostname)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::containers::{ResolvedContainerStatus, ResolvedHost, UnreachableHost};
+    use crate::swarm::ServiceStatus;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn check_events() {
+        let notification = Notification {
+            running_containers: vec![
+                RunningContainerStatus {
+                    name: "test1".to_string(),
+                    health: Some(HealthStatusEnum::UNHEALTHY),
+                    ..Default::default()
+                },
+                RunningContainerStatus {
+                    name: "test2".to_string(),
+                    health: None,
+                    ..Default::default()
+                },
+            ],
+            stopped_containers: vec![StoppedContainerStatus {
+                name: "test3".to_string(),
+                status: Some("exited".to_string()),
+                ..Default::default()
+            }],
+            resolved_containers: vec![ResolvedContainerStatus {
+                name: "test4".to_string(),
+                previous_status: "stopped".to_string(),
+                down_for_seconds: 60,
+                ..Default::default()
+            }],
+            unreachable_hosts: vec![UnreachableHost {
+                host: "host2".to_string(),
+                error: "connection refused".to_string(),
+            }],
+            resolved_hosts: vec![ResolvedHost {
+                host: "host3".to_string(),
+                down_for_seconds: 300,
+            }],
+            services: vec![ServiceStatus {
+                name: "web".to_string(),
+                desired_replicas: 2,
+                running_replicas: 1,
+                ..Default::default()
+            }],
+            hostname: Some("myhostname".to_owned()),
+            ..Default::default()
+        };
+        let payload = |summary: &str, severity, component: &str| {
+            Some(Payload {
+                summary: summary.to_owned(),
+                source: "myhostname".to_owned(),
+                severity,
+                component: component.to_owned(),
+            })
+        };
+        assert_eq!(
+            events("key", &notification),
+            vec![
+                Event {
+                    routing_key: "key".to_owned(),
+                    event_action: EventAction::Trigger,
+                    dedup_key: "notifyhealth/myhostname/test1".to_owned(),
+                    payload: payload("Container test1 is unhealthy on myhostname", Severity::Error, "test1"),
+                },
+                Event {
+                    routing_key: "key".to_owned(),
+                    event_action: EventAction::Trigger,
+                    dedup_key: "notifyhealth/myhostname/test2".to_owned(),
+                    payload: payload(
+                        "Container test2 has no health status on myhostname",
+                        Severity::Info,
+                        "test2"
+                    ),
+                },
+                Event {
+                    routing_key: "key".to_owned(),
+                    event_action: EventAction::Trigger,
+                    dedup_key: "notifyhealth/myhostname/test3".to_owned(),
+                    payload: payload(
+                        "Container test3 is not running (exited) on myhostname",
+                        Severity::Critical,
+                        "test3"
+                    ),
+                },
+                Event {
+                    routing_key: "key".to_owned(),
+                    event_action: EventAction::Trigger,
+                    dedup_key: "notifyhealth/myhostname/web".to_owned(),
+                    payload: payload(
+                        "Service web is missing replicas: 1/2 replicas running on myhostname",
+                        Severity::Critical,
+                        "web"
+                    ),
+                },
+                Event {
+                    routing_key: "key".to_owned(),
+                    event_action: EventAction::Resolve,
+                    dedup_key: "notifyhealth/myhostname/test4".to_owned(),
+                    payload: None,
+                },
+                Event {
+                    routing_key: "key".to_owned(),
+                    event_action: EventAction::Trigger,
+                    dedup_key: "notifyhealth/host2/docker".to_owned(),
+                    payload: Some(Payload {
+                        summary: "Docker host host2 could not be checked: connection refused".to_owned(),
+                        source: "host2".to_owned(),
+                        severity: Severity::Critical,
+                        component: "docker".to_owned(),
+                    }),
+                },
+                Event {
+                    routing_key: "key".to_owned(),
+                    event_action: EventAction::Resolve,
+                    dedup_key: "notifyhealth/host3/docker".to_owned(),
+                    payload: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn severity_depends_on_health() {
+        let severity = |health| {
+            running_severity(&RunningContainerStatus {
+                name: "test1".to_string(),
+                health,
+                ..Default::default()
+            })
+        };
+        assert_eq!(severity(Some(HealthStatusEnum::UNHEALTHY)), Severity::Error);
+        assert_eq!(severity(Some(HealthStatusEnum::STARTING)), Severity::Warning);
+        assert_eq!(severity(None), Severity::Info);
+    }
+
+    #[test]
+    fn severity_label_overrides_the_severity_of_the_problem() {
+        let notification = Notification {
+            stopped_containers: vec![StoppedContainerStatus {
+                name: "test1".to_string(),
+                severity: Some(Severity::Warning),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let severities = events("key", &notification)
+            .into_iter()
+            .map(|event| event.payload.unwrap().severity)
+            .collect::<Vec<_>>();
+        assert_eq!(severities, vec![Severity::Warning]);
+    }
+
+    #[test]
+    fn debug_hides_routing_key() {
+        let settings = PagerDutySettings {
+            routing_key: "secret-key".to_owned(),
+            events_url: DEFAULT_EVENTS_URL.to_owned(),
+        };
+        let debug = format!("{:?}", settings);
+        assert!(!debug.contains("secret-key"));
+        assert!(debug.contains("routing_key: \"***\""));
+    }
+
+    #[test]
+    fn dedup_key_without_hostname() {
+        assert_eq!(dedup_key(None, "test1"), "notifyhealth/test1");
+    }
+}
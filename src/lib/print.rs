@@ -1,45 +1,413 @@
 use bollard::models::HealthStatusEnum;
 use itertools::Itertools;
+use log::*;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
 
-use super::containers::{RunningContainerStatus, StoppedContainerStatus};
+use super::containers::{by_project, with_severity, RunningContainerStatus, StoppedContainerStatus, UnreachableHost};
+use super::metrics;
+use super::notifier::Notification;
+use super::swarm::{ServiceStatus, UNDER_REPLICATED};
+use super::webhook::WebHookNotifyBody;
+
+const TEXTFILE_NAME: &str = "notifyhealth.prom";
+const TABLE_HEADERS: [&str; 5] = ["NAME", "STATE", "HEALTH", "EXIT CODE", "PROJECT"];
+
+#[derive(clap::ValueEnum, Debug, PartialEq, Eq, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Text for people to read.
+    #[default]
+    Text,
+    /// The same JSON posted by the webhook.
+    Json,
+    Yaml,
+    /// Aligned columns.
+    Table,
+    Markdown,
+    Csv,
+}
+
+/// Prints the status in the format, to stdout.
+pub fn output(output_format: OutputFormat, notification: &Notification) -> Result<(), Box<dyn std::error::Error>> {
+    match output_format {
+        OutputFormat::Text => {
+            running_containers(notification.running_containers.clone());
+            stopped_containers(notification.stopped_containers.clone());
+            services(&notification.services);
+            unreachable_hosts(&notification.unreachable_hosts);
+        }
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&WebHookNotifyBody::new(notification))?
+        ),
+        OutputFormat::Yaml => print!("{}", serde_yaml::to_string(&WebHookNotifyBody::new(notification))?),
+        OutputFormat::Table => print!("{}", table(&rows(notification))),
+        OutputFormat::Markdown => print!("{}", markdown(&rows(notification))),
+        OutputFormat::Csv => print!("{}", csv(&rows(notification))),
+    }
+    Ok(())
+}
 
 pub fn running_containers(running_containers: Vec<RunningContainerStatus>) {
     if running_containers.is_empty() {
         println!("No running containers.");
     } else {
-        let containers_grouped_by_health = running_containers.into_iter().group_by(|c| c.health);
-        for (health_status, group) in containers_grouped_by_health.into_iter() {
-            match health_status {
-                Some(HealthStatusEnum::UNHEALTHY) => {
-                    println!("Running, unhealthy containers:");
-                    for container in group {
-                        println!("{name}", name = container.name);
-                    }
-                }
-                Some(status) => {
-                    println!("Running containers ({status}):");
-                    for container in group {
-                        println!("{name}", name = container.name);
-                    }
-                }
-                None => {
-                    println!("Running containers without health status:");
-                    for container in group {
-                        println!("{name}", name = container.name);
-                    }
+        for (project, containers) in by_project(&running_containers, RunningContainerStatus::project) {
+            if let Some(project) = project {
+                println!("Compose project {project}:");
+            }
+            for (title, names) in running_container_groups(&containers.into_iter().cloned().collect_vec()) {
+                println!("{title}");
+                for name in names {
+                    println!("{name}");
                 }
             }
         }
     }
 }
 
+/// Groups the running containers by health status, with a title for each group.
+pub fn running_container_groups(running_containers: &[RunningContainerStatus]) -> Vec<(String, Vec<String>)> {
+    let containers_grouped_by_health = running_containers.iter().group_by(|c| c.health);
+    containers_grouped_by_health
+        .into_iter()
+        .map(|(health_status, group)| {
+            let title = match health_status {
+                Some(HealthStatusEnum::UNHEALTHY) => "Running, unhealthy containers:".to_owned(),
+                Some(status) => format!("Running containers ({status}):"),
+                None => "Running containers without health status:".to_owned(),
+            };
+            let names = group.map(|container| with_severity(container.display_name(), container.severity));
+            (title, names.collect())
+        })
+        .collect()
+}
+
 pub fn stopped_containers(stopped_containers: Vec<StoppedContainerStatus>) {
     if stopped_containers.is_empty() {
         println!("No container that was supposed to be running is stopped.");
     } else {
         println!("The following containers are stopped:");
-        for container in stopped_containers.into_iter() {
-            println!("{name}", name = container.name);
+        for (project, containers) in by_project(&stopped_containers, StoppedContainerStatus::project) {
+            if let Some(project) = project {
+                println!("Compose project {project}:");
+            }
+            for container in containers {
+                println!(
+                    "{}: {}",
+                    container.display_name(),
+                    with_severity(container.summary(), container.severity)
+                );
+            }
+        }
+    }
+}
+
+pub fn services(services: &[ServiceStatus]) {
+    if !services.is_empty() {
+        println!("The following services are missing replicas:");
+        for service in services {
+            println!("{}: {}", service.display_name(), service.summary());
+        }
+    }
+}
+
+pub fn unreachable_hosts(unreachable_hosts: &[UnreachableHost]) {
+    if !unreachable_hosts.is_empty() {
+        println!("The following hosts could not be checked:");
+        for host in unreachable_hosts {
+            println!("{}: {}", host.host, host.error);
+        }
+    }
+}
+
+/// One row for each container, with the name, state, health, exit code and Compose project, then one for each Swarm
+/// service missing replicas and one for each host that could not be checked. The containers of a project are
+/// together, before the ones that are not in a project.
+fn rows(notification: &Notification) -> Vec<[String; 5]> {
+    let running = notification.running_containers.iter().map(|container| {
+        [
+            container.display_name(),
+            "running".to_owned(),
+            container
+                .health
+                .map(|health| health.to_string())
+                .unwrap_or_else(|| "none".to_owned()),
+            String::new(),
+            container.project().unwrap_or_default().to_owned(),
+        ]
+    });
+    let stopped = notification.stopped_containers.iter().map(|container| {
+        [
+            container.display_name(),
+            container.status.clone().unwrap_or_default(),
+            String::new(),
+            container
+                .exit_code
+                .map(|exit_code| exit_code.to_string())
+                .unwrap_or_default(),
+            container.project().unwrap_or_default().to_owned(),
+        ]
+    });
+    let services = notification.services.iter().map(|service| {
+        [
+            service.display_name(),
+            service
+                .task_state
+                .clone()
+                .unwrap_or_else(|| UNDER_REPLICATED.to_owned()),
+            String::new(),
+            String::new(),
+            String::new(),
+        ]
+    });
+    let unreachable = notification.unreachable_hosts.iter().map(|host| {
+        [
+            host.host.clone(),
+            "unreachable".to_owned(),
+            String::new(),
+            String::new(),
+            String::new(),
+        ]
+    });
+    running
+        .chain(stopped)
+        .sorted_by_key(|row| (row[4].is_empty(), row[4].clone()))
+        .chain(services)
+        .chain(unreachable)
+        .collect()
+}
+
+fn table(rows: &[[String; 5]]) -> String {
+    let mut widths = TABLE_HEADERS.map(|header| header.chars().count());
+    for row in rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
         }
     }
+    let line = |values: [&str; 5]| {
+        let columns = values
+            .iter()
+            .zip(widths)
+            .map(|(value, width)| format!("{value:width$}"))
+            .join("   ");
+        format!("{}\n", columns.trim_end())
+    };
+    let mut text = line(TABLE_HEADERS);
+    for row in rows {
+        text.push_str(&line(row.each_ref().map(String::as_str)));
+    }
+    text
+}
+
+fn markdown(rows: &[[String; 5]]) -> String {
+    let line = |values: Vec<String>| format!("| {} |\n", values.join(" | "));
+    let mut text = line(vec![
+        "Name".to_owned(),
+        "State".to_owned(),
+        "Health".to_owned(),
+        "Exit code".to_owned(),
+        "Project".to_owned(),
+    ]);
+    text.push_str(&line(vec!["---".to_owned(); 5]));
+    for row in rows {
+        text.push_str(&line(row.iter().map(|value| value.replace('|', "\\|")).collect()));
+    }
+    text
+}
+
+fn csv(rows: &[[String; 5]]) -> String {
+    let mut text = "name,state,health,exit_code,project\n".to_owned();
+    for row in rows {
+        let mut values = row.iter().map(|value| {
+            if value.contains([',', '"', '\n']) {
+                format!("\"{}\"", value.replace('"', "\"\""))
+            } else {
+                value.clone()
+            }
+        });
+        text.push_str(&format!("{}\n", values.join(",")));
+    }
+    text
+}
+
+/// Writes the containers to `notifyhealth.prom` in the directory, for the node_exporter textfile collector. The file
+/// is written to a temporary file first and then renamed, so the collector never reads it half written.
+pub fn textfile(
+    directory: &Path,
+    running_containers: &[RunningContainerStatus],
+    healthy_containers: &[RunningContainerStatus],
+    stopped_containers: &[StoppedContainerStatus],
+    unreachable_hosts: &[UnreachableHost],
+    services: &[ServiceStatus],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut text = String::new();
+    metrics::container_series(
+        &mut text,
+        running_containers,
+        healthy_containers,
+        stopped_containers,
+        unreachable_hosts,
+        services,
+    );
+    let path = directory.join(TEXTFILE_NAME);
+    let temp_path = path.with_extension("prom.tmp");
+    fs::write(&temp_path, text).map_err(|err| format!("Could not write {}: {err}", temp_path.display()))?;
+    fs::rename(&temp_path, &path)?;
+    debug!("Metrics written to {}.", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::containers::ComposeService;
+    use pretty_assertions::assert_eq;
+    use std::collections::BTreeMap;
+
+    fn notification() -> Notification {
+        Notification {
+            running_containers: vec![RunningContainerStatus {
+                name: "test1".to_string(),
+                health: Some(HealthStatusEnum::UNHEALTHY),
+                ..Default::default()
+            }],
+            stopped_containers: vec![
+                StoppedContainerStatus {
+                    name: "long,name".to_string(),
+                    status: Some("exited".to_string()),
+                    exit_code: Some(1),
+                    finished_at: Some("2024-01-01T10:00:00Z".to_string()),
+                    restart_count: Some(2),
+                    image: Some("busybox".to_string()),
+                    ..Default::default()
+                },
+                StoppedContainerStatus {
+                    name: "shop-db-1".to_string(),
+                    status: Some("dead".to_string()),
+                    compose: Some(ComposeService {
+                        project: "shop".to_string(),
+                        service: "db".to_string(),
+                        container_number: Some(1),
+                    }),
+                    ..Default::default()
+                },
+            ],
+            services: vec![ServiceStatus {
+                name: "web".to_string(),
+                desired_replicas: 3,
+                running_replicas: 1,
+                task_state: Some("rejected".to_string()),
+                ..Default::default()
+            }],
+            hostname: Some("myhostname".to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn check_table() {
+        assert_eq!(
+            table(&rows(&notification())),
+            "NAME        STATE      HEALTH      EXIT CODE   PROJECT
+shop-db-1   dead                               shop
+test1       running    unhealthy
+long,name   exited                 1
+web         rejected
+"
+        );
+    }
+
+    #[test]
+    fn check_markdown() {
+        assert_eq!(
+            markdown(&rows(&notification())),
+            "| Name | State | Health | Exit code | Project |
+| --- | --- | --- | --- | --- |
+| shop-db-1 | dead |  |  | shop |
+| test1 | running | unhealthy |  |  |
+| long,name | exited |  | 1 |  |
+| web | rejected |  |  |  |
+"
+        );
+    }
+
+    #[test]
+    fn check_csv() {
+        assert_eq!(
+            csv(&rows(&notification())),
+            "name,state,health,exit_code,project
+shop-db-1,dead,,,shop
+test1,running,unhealthy,,
+\"long,name\",exited,,1,
+web,rejected,,,
+"
+        );
+    }
+
+    #[test]
+    fn json_is_the_webhook_body() {
+        let json = serde_json::to_string(&WebHookNotifyBody::new(&notification())).unwrap();
+        assert!(
+            json.contains(r#""exit_code":1,"finished_at":"2024-01-01T10:00:00Z","restart_count":2,"image":"busybox""#),
+            "{json}"
+        );
+        assert_eq!(
+            serde_json::from_str::<WebHookNotifyBody>(&json).unwrap(),
+            WebHookNotifyBody {
+                running_containers: notification().running_containers,
+                stopped_containers: notification().stopped_containers,
+                resolved_containers: vec![],
+                unreachable_hosts: vec![],
+                resolved_hosts: vec![],
+                services: notification().services,
+                hostname: Some("myhostname".to_owned()),
+                projects: BTreeMap::from([("shop".to_owned(), vec!["shop-db-1".to_owned()])]),
+            }
+        );
+    }
+
+    #[test]
+    fn textfile_is_written() {
+        let directory = tempfile::tempdir().unwrap();
+        textfile(
+            directory.path(),
+            &[RunningContainerStatus {
+                name: "test1".to_string(),
+                health: None,
+                ..Default::default()
+            }],
+            &[RunningContainerStatus {
+                name: "test3".to_string(),
+                health: Some(HealthStatusEnum::HEALTHY),
+                ..Default::default()
+            }],
+            &[StoppedContainerStatus {
+                name: "test2".to_string(),
+                status: Some("exited".to_string()),
+                ..Default::default()
+            }],
+            &[],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(directory.path().join("notifyhealth.prom")).unwrap(),
+            r#"# HELP notifyhealth_container_health Running containers by health status, 1 when it is a problem.
+# TYPE notifyhealth_container_health gauge
+notifyhealth_container_health{name="test1",health="none"} 1
+notifyhealth_container_health{name="test3",health="healthy"} 0
+# HELP notifyhealth_container_stopped Containers with the label that are not running, by state.
+# TYPE notifyhealth_container_stopped gauge
+notifyhealth_container_stopped{name="test2",state="exited"} 1
+# HELP notifyhealth_host_unreachable Docker hosts that could not be checked.
+# TYPE notifyhealth_host_unreachable gauge
+# HELP notifyhealth_service_missing_replicas Swarm services with the label that are missing replicas, by how many are missing.
+# TYPE notifyhealth_service_missing_replicas gauge
+"#
+        );
+        assert_eq!(fs::read_dir(directory.path()).unwrap().count(), 1);
+    }
 }
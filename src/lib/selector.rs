@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Which containers are checked, from their labels. Commas separate the labels that must all match and `|` separates
+/// the alternatives, e.g. `notifyhealth,env=prod,!notifyhealth.ignore|critical`. Each label is a key, which the
+/// container must have, `key=value`, or either negated with a leading `!`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LabelSelector {
+    groups: Vec<Vec<LabelTerm>>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct LabelTerm {
+    key: String,
+    value: Option<String>,
+    negated: bool,
+}
+
+impl LabelTerm {
+    fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        let found = match &self.value {
+            Some(value) => labels.get(&self.key) == Some(value),
+            None => labels.contains_key(&self.key),
+        };
+        found != self.negated
+    }
+}
+
+impl LabelSelector {
+    /// The `label` filters of the Docker API, which are all required, so only the labels of a selector without
+    /// alternatives that are not negated can be sent. The rest is checked with `matches`.
+    pub fn docker_filter(&self) -> Vec<String> {
+        match self.groups.as_slice() {
+            [terms] => terms
+                .iter()
+                .filter(|term| !term.negated)
+                .map(|term| match &term.value {
+                    Some(value) => format!("{}={value}", term.key),
+                    None => term.key.clone(),
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    pub fn matches(&self, labels: Option<&HashMap<String, String>>) -> bool {
+        let empty_labels = HashMap::new();
+        let labels = labels.unwrap_or(&empty_labels);
+        self.groups
+            .iter()
+            .any(|terms| terms.iter().all(|term| term.matches(labels)))
+    }
+}
+
+impl FromStr for LabelSelector {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let groups = text
+            .split('|')
+            .map(|group| group.split(',').map(parse_term).collect::<Result<Vec<_>, _>>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("Invalid label selector `{text}`: {err}"))?;
+        Ok(LabelSelector { groups })
+    }
+}
+
+fn parse_term(text: &str) -> Result<LabelTerm, String> {
+    let text = text.trim();
+    let (negated, label) = match text.strip_prefix('!') {
+        Some(label) => (true, label.trim_start()),
+        None => (false, text),
+    };
+    let (key, value) = match label.split_once('=') {
+        Some((key, value)) => (key.trim_end(), Some(value.trim_start().to_owned())),
+        None => (label, None),
+    };
+    if key.is_empty() {
+        return Err(if text.is_empty() {
+            "a label is empty".to_owned()
+        } else {
+            format!("the label `{text}` has no key")
+        });
+    }
+    Ok(LabelTerm {
+        key: key.to_owned(),
+        value,
+        negated,
+    })
+}
+
+impl fmt::Display for LabelSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let groups = self.groups.iter().map(|terms| {
+            terms
+                .iter()
+                .map(|term| {
+                    let negation = if term.negated { "!" } else { "" };
+                    match &term.value {
+                        Some(value) => format!("{negation}{}={value}", term.key),
+                        None => format!("{negation}{}", term.key),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(",")
+        });
+        write!(f, "{}", groups.collect::<Vec<_>>().join("|"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn labels(labels: &[(&str, &str)]) -> HashMap<String, String> {
+        labels
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn labels_are_anded_and_groups_ored() {
+        let selector: LabelSelector = "notifyhealth, env=prod, !notifyhealth.ignore | critical"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            selector.to_string(),
+            "notifyhealth,env=prod,!notifyhealth.ignore|critical"
+        );
+        assert!(selector.matches(Some(&labels(&[("notifyhealth", ""), ("env", "prod")]))));
+        assert!(!selector.matches(Some(&labels(&[("notifyhealth", ""), ("env", "dev")]))));
+        assert!(!selector.matches(Some(&labels(&[
+            ("notifyhealth", ""),
+            ("env", "prod"),
+            ("notifyhealth.ignore", "true")
+        ]))));
+        assert!(selector.matches(Some(&labels(&[("critical", "yes"), ("notifyhealth.ignore", "true")]))));
+        assert!(!selector.matches(None));
+    }
+
+    #[test]
+    fn negated_values_match_containers_without_the_label() {
+        let selector: LabelSelector = "!env=dev".parse().unwrap();
+        assert!(selector.matches(None));
+        assert!(selector.matches(Some(&labels(&[("env", "prod")]))));
+        assert!(!selector.matches(Some(&labels(&[("env", "dev")]))));
+    }
+
+    #[test]
+    fn only_required_labels_of_a_single_group_are_docker_filters() {
+        let docker_filter = |selector: &str| selector.parse::<LabelSelector>().unwrap().docker_filter();
+        assert_eq!(docker_filter("autoheal"), vec!["autoheal"]);
+        assert_eq!(
+            docker_filter("notifyhealth,env=prod,!notifyhealth.ignore"),
+            vec!["notifyhealth", "env=prod"]
+        );
+        assert_eq!(docker_filter("!notifyhealth.ignore"), Vec::<String>::new());
+        assert_eq!(docker_filter("web|db"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn invalid_selectors() {
+        assert_eq!(
+            "notifyhealth,".parse::<LabelSelector>().unwrap_err(),
+            "Invalid label selector `notifyhealth,`: a label is empty"
+        );
+        assert_eq!(
+            "a|!=b".parse::<LabelSelector>().unwrap_err(),
+            "Invalid label selector `a|!=b`: the label `!=b` has no key"
+        );
+    }
+}
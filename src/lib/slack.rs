@@ -0,0 +1,281 @@
+use super::containers::with_severity;
+use super::notifier::Notification;
+use bollard::models::HealthStatusEnum;
+use itertools::Itertools;
+use log::*;
+use serde::Serialize;
+use std::time::Duration;
+
+const DANGER_COLOR: &str = "#e01e5a";
+const WARNING_COLOR: &str = "#ecb22e";
+const NO_HEALTH_COLOR: &str = "#9e9e9e";
+const GOOD_COLOR: &str = "#2eb67d";
+// Slack accepts at most 10 fields in a section block.
+const MAX_FIELDS_PER_SECTION: usize = 10;
+
+#[derive(Debug, PartialEq, Serialize)]
+struct Message {
+    text: String,
+    blocks: Vec<Block>,
+    attachments: Vec<Attachment>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct Attachment {
+    color: String,
+    blocks: Vec<Block>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Block {
+    Header {
+        text: Text,
+    },
+    Section {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        text: Option<Text>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        fields: Vec<Text>,
+    },
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Text {
+    PlainText { text: String },
+    Mrkdwn { text: String },
+}
+
+pub fn format_message(notification: &Notification) -> Result<Vec<Vec<u8>>, serde_json::Error> {
+    let Notification {
+        running_containers,
+        stopped_containers,
+        resolved_containers,
+        unreachable_hosts,
+        resolved_hosts,
+        services,
+        hostname,
+        ..
+    } = notification;
+    let mut attachments = vec![];
+    for (health_opt, group) in &running_containers.iter().group_by(|c| &c.health) {
+        let (color, text, facts) = match health_opt {
+            Some(health) => (
+                if *health == HealthStatusEnum::UNHEALTHY {
+                    DANGER_COLOR
+                } else {
+                    WARNING_COLOR
+                },
+                "The following running containers are not healthy:",
+                group
+                    .map(|c| (c.display_name(), with_severity(health.to_string(), c.severity)))
+                    .collect_vec(),
+            ),
+            None => (
+                NO_HEALTH_COLOR,
+                "The following running containers have no health status:",
+                group
+                    .map(|c| {
+                        (
+                            c.display_name(),
+                            with_severity("no health status".to_owned(), c.severity),
+                        )
+                    })
+                    .collect_vec(),
+            ),
+        };
+        attachments.push(attachment(color, text, facts));
+    }
+    if !stopped_containers.is_empty() {
+        attachments.push(attachment(
+            DANGER_COLOR,
+            "The following containers are not running:",
+            stopped_containers
+                .iter()
+                .map(|c| (c.display_name(), with_severity(c.summary(), c.severity)))
+                .collect(),
+        ));
+    }
+    if !services.is_empty() {
+        attachments.push(attachment(
+            DANGER_COLOR,
+            "The following services are missing replicas:",
+            services.iter().map(|s| (s.display_name(), s.summary())).collect(),
+        ));
+    }
+    if !unreachable_hosts.is_empty() {
+        attachments.push(attachment(
+            DANGER_COLOR,
+            "The following hosts could not be checked:",
+            unreachable_hosts
+                .iter()
+                .map(|h| (h.host.clone(), h.error.clone()))
+                .collect(),
+        ));
+    }
+    if !resolved_containers.is_empty() {
+        attachments.push(attachment(
+            GOOD_COLOR,
+            "The following containers have recovered:",
+            resolved_containers
+                .iter()
+                .map(|c| {
+                    let down_for = humantime::format_duration(Duration::from_secs(c.down_for_seconds));
+                    (c.display_name(), format!("{} for {down_for}", c.previous_status))
+                })
+                .collect(),
+        ));
+    }
+    if !resolved_hosts.is_empty() {
+        attachments.push(attachment(
+            GOOD_COLOR,
+            "The following hosts can be checked again:",
+            resolved_hosts
+                .iter()
+                .map(|h| {
+                    let down_for = humantime::format_duration(Duration::from_secs(h.down_for_seconds));
+                    (h.host.clone(), format!("unreachable for {down_for}"))
+                })
+                .collect(),
+        ));
+    }
+    let title = if !notification.has_problems() {
+        "Containers recovered! 😌"
+    } else {
+        "Problem in containers! 🤕"
+    };
+    let mut blocks = vec![Block::Header {
+        text: Text::PlainText { text: title.to_owned() },
+    }];
+    if let Some(hostname) = hostname {
+        blocks.push(Block::Section {
+            text: Some(Text::Mrkdwn {
+                text: format!("Server: `{hostname}`."),
+            }),
+            fields: vec![],
+        });
+    }
+    let msg = Message {
+        text: title.to_owned(),
+        blocks,
+        attachments,
+    };
+    info!("Message to be sent: {:?}", msg);
+    Ok(vec![serde_json::to_vec(&msg)?])
+}
+
+fn attachment(color: &str, text: &str, facts: Vec<(String, String)>) -> Attachment {
+    let mut text = Some(Text::Mrkdwn {
+        text: format!("*{text}*"),
+    });
+    let mut blocks = vec![];
+    for chunk in &facts.into_iter().chunks(MAX_FIELDS_PER_SECTION) {
+        blocks.push(Block::Section {
+            text: text.take(),
+            fields: chunk
+                .map(|(name, value)| Text::Mrkdwn {
+                    text: format!("*{name}*\n{value}"),
+                })
+                .collect(),
+        });
+    }
+    Attachment {
+        color: color.to_owned(),
+        blocks,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::containers::{RunningContainerStatus, StoppedContainerStatus};
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn check_message() {
+        let running_containers = vec![
+            RunningContainerStatus {
+                name: "test1".to_string(),
+                health: None,
+                ..Default::default()
+            },
+            RunningContainerStatus {
+                name: "test2".to_string(),
+                health: Some(HealthStatusEnum::UNHEALTHY),
+                ..Default::default()
+            },
+        ];
+        let stopped_containers = vec![
+            StoppedContainerStatus {
+                name: "test3".to_string(),
+                status: Some("exited".to_string()),
+                ..Default::default()
+            },
+            StoppedContainerStatus {
+                name: "test4".to_string(),
+                status: None,
+                exit_code: Some(137),
+                oom_killed: true,
+                ..Default::default()
+            },
+        ];
+        let formatted_message_bytes = format_message(&Notification {
+            running_containers,
+            stopped_containers,
+            hostname: Some("myhostname".to_owned()),
+            ..Default::default()
+        })
+        .unwrap()
+        .remove(0);
+        let formatted_message: serde_json::Value = serde_json::from_slice(&formatted_message_bytes).unwrap();
+        assert_eq!(
+            formatted_message,
+            json!({
+                "text": "Problem in containers! 🤕",
+                "blocks": [
+                    {"type": "header", "text": {"type": "plain_text", "text": "Problem in containers! 🤕"}},
+                    {"type": "section", "text": {"type": "mrkdwn", "text": "Server: `myhostname`."}}
+                ],
+                "attachments": [
+                    {"color": "#9e9e9e", "blocks": [
+                        {"type": "section", "text": {"type": "mrkdwn", "text": "*The following running containers have no health status:*"}, "fields": [{"type": "mrkdwn", "text": "*test1*\nno health status"}]}
+                    ]},
+                    {"color": "#e01e5a", "blocks": [
+                        {"type": "section", "text": {"type": "mrkdwn", "text": "*The following running containers are not healthy:*"}, "fields": [{"type": "mrkdwn", "text": "*test2*\nunhealthy"}]}
+                    ]},
+                    {"color": "#e01e5a", "blocks": [
+                        {"type": "section", "text": {"type": "mrkdwn", "text": "*The following containers are not running:*"}, "fields": [
+                            {"type": "mrkdwn", "text": "*test3*\nexited"},
+                            {"type": "mrkdwn", "text": "*test4*\nno status, exit code 137, out of memory"}
+                        ]}
+                    ]}
+                ]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn sections_are_split_every_ten_fields() {
+        let stopped_containers = (1..=11)
+            .map(|i| StoppedContainerStatus {
+                name: format!("test{i}"),
+                status: Some("exited".to_string()),
+                ..Default::default()
+            })
+            .collect_vec();
+        let formatted_message_bytes = format_message(&Notification {
+            stopped_containers,
+            ..Default::default()
+        })
+        .unwrap()
+        .remove(0);
+        let formatted_message: serde_json::Value = serde_json::from_slice(&formatted_message_bytes).unwrap();
+        let blocks = formatted_message["attachments"][0]["blocks"].as_array().unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0]["fields"].as_array().unwrap().len(), 10);
+        assert_eq!(blocks[1]["fields"].as_array().unwrap().len(), 1);
+        assert!(blocks[1].get("text").is_none());
+    }
+}
@@ -0,0 +1,559 @@
+use super::containers::{
+    ResolvedContainerStatus, ResolvedHost, RunningContainerStatus, Severity, StoppedContainerStatus, UnreachableHost,
+};
+use super::notifier::Notification;
+use super::swarm::{ServiceStatus, UNDER_REPLICATED};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const STATE_FILE_NAME: &str = "state.json";
+const STOPPED: &str = "stopped";
+const UNREACHABLE: &str = "unreachable";
+
+/// The containers are keyed by their display name, which has the host when several hosts are checked.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Default)]
+pub struct State {
+    pub containers: BTreeMap<String, ContainerState>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub unreachable_hosts: BTreeMap<String, ContainerState>,
+    /// The Swarm services missing replicas, apart from the containers as a service and a container can share a name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub services: BTreeMap<String, ContainerState>,
+    /// The changes that could not be sent to the targets, by the name of the target, to send them again.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub pending: BTreeMap<String, Notification>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Default)]
+pub struct ContainerState {
+    pub status: String,
+    pub since: u64,
+    pub last_notified: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// The severity and the target of the container's labels, for its recovery.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<Severity>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+impl ContainerState {
+    fn new(status: &str, host: &Option<String>, now: u64) -> ContainerState {
+        ContainerState {
+            status: status.to_owned(),
+            since: now,
+            last_notified: now,
+            host: host.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+impl State {
+    pub fn load(state_dir: &Path) -> Result<State, Box<dyn std::error::Error>> {
+        let path = state_file(state_dir);
+        if !path.exists() {
+            info!("No state file at {}, starting with an empty state.", path.display());
+            return Ok(State::default());
+        }
+        let bytes = fs::read(&path)?;
+        serde_json::from_slice(&bytes).map_err(|err| format!("Invalid state file {}: {err}", path.display()).into())
+    }
+
+    pub fn save(&self, state_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::create_dir_all(state_dir)?;
+        let path = state_file(state_dir);
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&temp_path, &path)?;
+        debug!("State saved to {}.", path.display());
+        Ok(())
+    }
+
+    /// Records the current problems and returns only the ones that should be notified: new problems, problems
+    /// whose status changed, and problems that were last notified longer than `renotify_interval` ago.
+    pub fn track_problems(
+        &mut self,
+        running_containers: Vec<RunningContainerStatus>,
+        stopped_containers: Vec<StoppedContainerStatus>,
+        unreachable_hosts: Vec<UnreachableHost>,
+        now: SystemTime,
+        renotify_interval: Option<Duration>,
+    ) -> (
+        Vec<RunningContainerStatus>,
+        Vec<StoppedContainerStatus>,
+        Vec<UnreachableHost>,
+    ) {
+        let now = to_timestamp(now);
+        let running_containers = running_containers
+            .into_iter()
+            .filter(|c| {
+                let problem = ContainerState {
+                    severity: c.severity,
+                    target: c.target.clone(),
+                    ..ContainerState::new(&running_status(c), &c.host, now)
+                };
+                should_notify(&mut self.containers, &c.display_name(), problem, renotify_interval)
+            })
+            .collect();
+        let stopped_containers = stopped_containers
+            .into_iter()
+            .filter(|c| {
+                let problem = ContainerState {
+                    severity: c.severity,
+                    target: c.target.clone(),
+                    ..ContainerState::new(STOPPED, &c.host, now)
+                };
+                should_notify(&mut self.containers, &c.display_name(), problem, renotify_interval)
+            })
+            .collect();
+        let unreachable_hosts = unreachable_hosts
+            .into_iter()
+            .filter(|h| {
+                should_notify(
+                    &mut self.unreachable_hosts,
+                    &h.host,
+                    ContainerState::new(UNREACHABLE, &None, now),
+                    renotify_interval,
+                )
+            })
+            .collect();
+        (running_containers, stopped_containers, unreachable_hosts)
+    }
+
+    /// Forgets the containers that are no longer in the list of problems, returning them as resolved. The containers
+    /// of the hosts that could not be checked are kept, as it is not known if they recovered.
+    pub fn remove_recovered(
+        &mut self,
+        running_containers: &[RunningContainerStatus],
+        stopped_containers: &[StoppedContainerStatus],
+        unreachable_hosts: &[UnreachableHost],
+        now: SystemTime,
+    ) -> Vec<ResolvedContainerStatus> {
+        let now = to_timestamp(now);
+        let (recovered, still_bad): (BTreeMap<_, _>, BTreeMap<_, _>) = std::mem::take(&mut self.containers)
+            .into_iter()
+            .partition(|(name, container_state)| {
+                !running_containers.iter().any(|c| &c.display_name() == name)
+                    && !stopped_containers.iter().any(|c| &c.display_name() == name)
+                    && !unreachable_hosts
+                        .iter()
+                        .any(|h| Some(&h.host) == container_state.host.as_ref())
+            });
+        self.containers = still_bad;
+        recovered
+            .into_iter()
+            .map(|(name, container_state)| resolved(name, container_state, now))
+            .collect()
+    }
+
+    /// Forgets the hosts that are no longer in the list of unreachable hosts, returning them as resolved.
+    pub fn remove_reachable_hosts(
+        &mut self,
+        unreachable_hosts: &[UnreachableHost],
+        now: SystemTime,
+    ) -> Vec<ResolvedHost> {
+        let now = to_timestamp(now);
+        let (reachable, still_unreachable): (BTreeMap<_, _>, BTreeMap<_, _>) =
+            std::mem::take(&mut self.unreachable_hosts)
+                .into_iter()
+                .partition(|(host, _)| !unreachable_hosts.iter().any(|h| &h.host == host));
+        self.unreachable_hosts = still_unreachable;
+        reachable
+            .into_iter()
+            .map(|(host, host_state)| ResolvedHost {
+                host,
+                down_for_seconds: now.saturating_sub(host_state.since),
+            })
+            .collect()
+    }
+
+    /// Forgets the container with the display name `name` when it is no longer in the list of problems, returning it
+    /// as resolved, for a check of only that container.
+    pub fn remove_recovered_container(
+        &mut self,
+        name: &str,
+        running_containers: &[RunningContainerStatus],
+        stopped_containers: &[StoppedContainerStatus],
+        now: SystemTime,
+    ) -> Vec<ResolvedContainerStatus> {
+        if running_containers.iter().any(|c| c.display_name() == name)
+            || stopped_containers.iter().any(|c| c.display_name() == name)
+        {
+            return vec![];
+        }
+        self.containers
+            .remove(name)
+            .map(|container_state| resolved(name.to_owned(), container_state, to_timestamp(now)))
+            .into_iter()
+            .collect()
+    }
+
+    /// Records the services missing replicas and returns the ones that should be notified, like `track_problems`.
+    pub fn track_services(
+        &mut self,
+        services: Vec<ServiceStatus>,
+        now: SystemTime,
+        renotify_interval: Option<Duration>,
+    ) -> Vec<ServiceStatus> {
+        let now = to_timestamp(now);
+        services
+            .into_iter()
+            .filter(|s| {
+                should_notify(
+                    &mut self.services,
+                    &s.display_name(),
+                    ContainerState::new(UNDER_REPLICATED, &s.host, now),
+                    renotify_interval,
+                )
+            })
+            .collect()
+    }
+
+    /// Forgets the services that have all their replicas again, returning them as resolved, with
+    /// `under-replicated` as their previous status. The services of the hosts that could not be checked are kept.
+    pub fn remove_recovered_services(
+        &mut self,
+        services: &[ServiceStatus],
+        unreachable_hosts: &[UnreachableHost],
+        now: SystemTime,
+    ) -> Vec<ResolvedContainerStatus> {
+        let now = to_timestamp(now);
+        let (recovered, still_bad): (BTreeMap<_, _>, BTreeMap<_, _>) = std::mem::take(&mut self.services)
+            .into_iter()
+            .partition(|(name, service_state)| {
+                !services.iter().any(|s| &s.display_name() == name)
+                    && !unreachable_hosts
+                        .iter()
+                        .any(|h| Some(&h.host) == service_state.host.as_ref())
+            });
+        self.services = still_bad;
+        recovered
+            .into_iter()
+            .map(|(name, service_state)| resolved(name, service_state, now))
+            .collect()
+    }
+}
+
+/// The resolved status of a container or service that was kept in the state under its display name.
+fn resolved(name: String, state: ContainerState, now: u64) -> ResolvedContainerStatus {
+    ResolvedContainerStatus {
+        name: match &state.host {
+            Some(host) => name
+                .strip_prefix(&format!("{host}/"))
+                .map(str::to_owned)
+                .unwrap_or(name),
+            None => name,
+        },
+        previous_status: state.status,
+        down_for_seconds: now.saturating_sub(state.since),
+        host: state.host,
+        severity: state.severity,
+        target: state.target,
+    }
+}
+
+/// Keeps the problem in the states, with its time as `since` when it is new or its status changed.
+fn should_notify(
+    states: &mut BTreeMap<String, ContainerState>,
+    name: &str,
+    problem: ContainerState,
+    renotify_interval: Option<Duration>,
+) -> bool {
+    let now = problem.last_notified;
+    match states.get_mut(name) {
+        Some(state) if state.status == problem.status => {
+            state.severity = problem.severity;
+            state.target = problem.target;
+            let renotify = renotify_interval
+                .map(|interval| now.saturating_sub(state.last_notified) >= interval.as_secs())
+                .unwrap_or(false);
+            if renotify {
+                state.last_notified = now;
+            } else {
+                debug!("{name} is still {}, not notifying again.", state.status);
+            }
+            renotify
+        }
+        _ => {
+            states.insert(name.to_owned(), problem);
+            true
+        }
+    }
+}
+
+fn running_status(container: &RunningContainerStatus) -> String {
+    match container.health {
+        Some(health) => health.to_string(),
+        None => "none".to_owned(),
+    }
+}
+
+fn state_file(state_dir: &Path) -> PathBuf {
+    state_dir.join(STATE_FILE_NAME)
+}
+
+fn to_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bollard::models::HealthStatusEnum;
+    use pretty_assertions::assert_eq;
+
+    fn unhealthy(name: &str) -> RunningContainerStatus {
+        RunningContainerStatus {
+            name: name.to_owned(),
+            health: Some(HealthStatusEnum::UNHEALTHY),
+            ..Default::default()
+        }
+    }
+
+    fn stopped(name: &str) -> StoppedContainerStatus {
+        StoppedContainerStatus {
+            name: name.to_owned(),
+            status: Some("exited".to_owned()),
+            ..Default::default()
+        }
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn only_changes_are_notified() {
+        let mut state = State::default();
+        assert_eq!(
+            state.track_problems(vec![unhealthy("test1")], vec![stopped("test2")], vec![], at(100), None),
+            (vec![unhealthy("test1")], vec![stopped("test2")], vec![])
+        );
+        assert_eq!(
+            state.track_problems(vec![unhealthy("test1")], vec![stopped("test2")], vec![], at(200), None),
+            (vec![], vec![], vec![])
+        );
+        assert_eq!(
+            state.track_problems(vec![], vec![stopped("test1")], vec![], at(300), None),
+            (vec![], vec![stopped("test1")], vec![])
+        );
+        assert_eq!(
+            state.containers["test1"],
+            ContainerState {
+                status: "stopped".to_owned(),
+                since: 300,
+                last_notified: 300,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn still_bad_containers_are_notified_again_after_interval() {
+        let mut state = State::default();
+        let renotify_interval = Some(Duration::from_secs(3600));
+        state.track_problems(vec![unhealthy("test1")], vec![], vec![], at(0), renotify_interval);
+        assert_eq!(
+            state.track_problems(vec![unhealthy("test1")], vec![], vec![], at(3599), renotify_interval),
+            (vec![], vec![], vec![])
+        );
+        assert_eq!(
+            state.track_problems(vec![unhealthy("test1")], vec![], vec![], at(3600), renotify_interval),
+            (vec![unhealthy("test1")], vec![], vec![])
+        );
+        assert_eq!(
+            state.containers["test1"],
+            ContainerState {
+                status: "unhealthy".to_owned(),
+                since: 0,
+                last_notified: 3600,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn recovered_containers_are_removed() {
+        let mut state = State::default();
+        state.track_problems(vec![unhealthy("test1")], vec![stopped("test2")], vec![], at(100), None);
+        let recovered = state.remove_recovered(&[], &[stopped("test2")], &[], at(400));
+        assert_eq!(
+            recovered,
+            vec![ResolvedContainerStatus {
+                name: "test1".to_owned(),
+                previous_status: "unhealthy".to_owned(),
+                down_for_seconds: 300,
+                ..Default::default()
+            }]
+        );
+        assert_eq!(state.containers.keys().collect::<Vec<_>>(), vec!["test2"]);
+    }
+
+    #[test]
+    fn only_the_checked_container_is_recovered() {
+        let mut state = State::default();
+        state.track_problems(vec![unhealthy("test1")], vec![stopped("test2")], vec![], at(100), None);
+        assert_eq!(
+            state.remove_recovered_container("test1", &[unhealthy("test1")], &[], at(200)),
+            vec![]
+        );
+        assert_eq!(
+            state.remove_recovered_container("test2", &[], &[], at(400)),
+            vec![ResolvedContainerStatus {
+                name: "test2".to_owned(),
+                previous_status: "stopped".to_owned(),
+                down_for_seconds: 300,
+                ..Default::default()
+            }]
+        );
+        assert_eq!(state.containers.keys().collect::<Vec<_>>(), vec!["test1"]);
+    }
+
+    #[test]
+    fn containers_of_unreachable_hosts_are_not_recovered() {
+        let on_host = |host: &str| StoppedContainerStatus {
+            host: Some(host.to_owned()),
+            ..stopped("test1")
+        };
+        let unreachable = |host: &str| UnreachableHost {
+            host: host.to_owned(),
+            error: "connection refused".to_owned(),
+        };
+        let mut state = State::default();
+        assert_eq!(
+            state.track_problems(vec![], vec![on_host("host1"), on_host("host2")], vec![], at(100), None),
+            (vec![], vec![on_host("host1"), on_host("host2")], vec![])
+        );
+        assert_eq!(
+            state.remove_recovered(&[], &[], &[unreachable("host1")], at(200)),
+            vec![ResolvedContainerStatus {
+                name: "test1".to_owned(),
+                previous_status: "stopped".to_owned(),
+                down_for_seconds: 100,
+                host: Some("host2".to_owned()),
+                ..Default::default()
+            }]
+        );
+        assert_eq!(
+            state.track_problems(vec![], vec![], vec![unreachable("host1")], at(200), None),
+            (vec![], vec![], vec![unreachable("host1")])
+        );
+        assert_eq!(
+            state.track_problems(vec![], vec![], vec![unreachable("host1")], at(300), None),
+            (vec![], vec![], vec![])
+        );
+        state.remove_recovered(&[], &[], &[], at(400));
+        state.remove_reachable_hosts(&[], at(400));
+        assert_eq!(state, State::default());
+    }
+
+    #[test]
+    fn hosts_that_can_be_checked_again_are_resolved() {
+        let unreachable = UnreachableHost {
+            host: "host1".to_owned(),
+            error: "connection refused".to_owned(),
+        };
+        let mut state = State::default();
+        assert_eq!(state.remove_reachable_hosts(&[], at(100)), vec![]);
+        state.track_problems(vec![], vec![], vec![unreachable.clone()], at(200), None);
+        assert_eq!(
+            state.remove_reachable_hosts(std::slice::from_ref(&unreachable), at(300)),
+            vec![]
+        );
+        assert_eq!(
+            state.track_problems(vec![], vec![], vec![unreachable.clone()], at(300), None),
+            (vec![], vec![], vec![])
+        );
+        assert_eq!(
+            state.remove_reachable_hosts(&[], at(400)),
+            vec![ResolvedHost {
+                host: "host1".to_owned(),
+                down_for_seconds: 200,
+            }]
+        );
+        assert_eq!(state, State::default());
+        assert_eq!(
+            state.track_problems(vec![], vec![], vec![unreachable.clone()], at(500), None),
+            (vec![], vec![], vec![unreachable])
+        );
+    }
+
+    #[test]
+    fn services_are_tracked_apart_from_containers() {
+        let service = |host: &str| ServiceStatus {
+            name: "web".to_owned(),
+            desired_replicas: 3,
+            running_replicas: 1,
+            host: Some(host.to_owned()),
+            ..Default::default()
+        };
+        let mut state = State::default();
+        state.track_problems(vec![], vec![stopped("web")], vec![], at(100), None);
+        assert_eq!(
+            state.track_services(vec![service("host1"), service("host2")], at(100), None),
+            vec![service("host1"), service("host2")]
+        );
+        assert_eq!(state.track_services(vec![service("host1")], at(200), None), vec![]);
+        assert_eq!(
+            state.remove_recovered_services(
+                &[],
+                &[UnreachableHost {
+                    host: "host2".to_owned(),
+                    error: "connection refused".to_owned(),
+                }],
+                at(400)
+            ),
+            vec![ResolvedContainerStatus {
+                name: "web".to_owned(),
+                previous_status: UNDER_REPLICATED.to_owned(),
+                down_for_seconds: 300,
+                host: Some("host1".to_owned()),
+                ..Default::default()
+            }]
+        );
+        assert_eq!(state.services.keys().collect::<Vec<_>>(), vec!["host2/web"]);
+        assert_eq!(state.containers.keys().collect::<Vec<_>>(), vec!["web"]);
+    }
+
+    #[test]
+    fn recovered_containers_keep_the_severity_and_target_of_their_labels() {
+        let labelled = |severity: Severity| StoppedContainerStatus {
+            severity: Some(severity),
+            target: Some("teams-backend".to_owned()),
+            ..stopped("test1")
+        };
+        let mut state = State::default();
+        state.track_problems(vec![], vec![labelled(Severity::Warning)], vec![], at(100), None);
+        assert_eq!(
+            state.track_problems(vec![], vec![labelled(Severity::Critical)], vec![], at(200), None),
+            (vec![], vec![], vec![])
+        );
+        assert_eq!(
+            state.remove_recovered(&[], &[], &[], at(400)),
+            vec![ResolvedContainerStatus {
+                name: "test1".to_owned(),
+                previous_status: "stopped".to_owned(),
+                down_for_seconds: 300,
+                severity: Some(Severity::Critical),
+                target: Some("teams-backend".to_owned()),
+                ..Default::default()
+            }]
+        );
+    }
+
+    #[test]
+    fn state_is_saved_and_loaded() {
+        let state_dir = tempfile::tempdir().unwrap();
+        assert_eq!(State::load(state_dir.path()).unwrap(), State::default());
+        let mut state = State::default();
+        state.track_problems(vec![unhealthy("test1")], vec![], vec![], at(100), None);
+        state.save(state_dir.path()).unwrap();
+        assert_eq!(State::load(state_dir.path()).unwrap(), state);
+    }
+}
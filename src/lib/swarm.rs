@@ -0,0 +1,364 @@
+use super::containers::{display_name, filters, label_filters, Containers};
+use super::selector::LabelSelector;
+use async_trait::async_trait;
+use bollard::errors::Error;
+use bollard::models::{Service, Task, TaskState, TaskStatus};
+use bollard::query_parameters::{ListServicesOptions, ListTasksOptions};
+use futures::prelude::*;
+use log::*;
+#[cfg(test)]
+use mockall::automock;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+/// The status kept in the state for the services, and the one they recover from.
+pub const UNDER_REPLICATED: &str = "under-replicated";
+/// How long the latest tasks of a service with all its replicas can be pending or rejected before it is reported, as a
+/// rolling update that can't start its new tasks keeps the old ones running.
+const STUCK_TASKS_GRACE_PERIOD: Duration = Duration::from_secs(5 * 60);
+
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait HasServices {
+    async fn list_services(&self, options: Option<ListServicesOptions>) -> Result<Vec<Service>, Error>;
+    async fn list_tasks(&self, options: Option<ListTasksOptions>) -> Result<Vec<Task>, Error>;
+}
+
+#[async_trait]
+impl HasServices for Containers {
+    async fn list_services(&self, options: Option<ListServicesOptions>) -> Result<Vec<Service>, Error> {
+        self.docker.list_services(options).await
+    }
+    async fn list_tasks(&self, options: Option<ListTasksOptions>) -> Result<Vec<Task>, Error> {
+        self.docker.list_tasks(options).await
+    }
+}
+
+/// A Swarm service with fewer running tasks than replicas, with the latest task that could not be started.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Default)]
+pub struct ServiceStatus {
+    pub name: String,
+    pub desired_replicas: u64,
+    pub running_replicas: u64,
+    /// `pending`, `rejected` or `failed`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task_state: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task_error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+}
+
+impl ServiceStatus {
+    pub fn display_name(&self) -> String {
+        display_name(self.host.as_deref(), &self.name)
+    }
+
+    /// The replicas and the task error, e.g. `1/3 replicas running, rejected: no suitable node`.
+    pub fn summary(&self) -> String {
+        let replicas = format!("{}/{} replicas running", self.running_replicas, self.desired_replicas);
+        match (&self.task_state, &self.task_error) {
+            (Some(state), Some(error)) => format!("{replicas}, {state}: {error}"),
+            (Some(state), None) => format!("{replicas}, {state}"),
+            _ => replicas,
+        }
+    }
+}
+
+/// The services matching the label selector that are missing replicas, or whose latest tasks have been pending or
+/// rejected for longer than the grace period, like a stuck rolling update. Jobs are not checked, as their tasks are
+/// expected to end.
+pub async fn check_services(
+    docker: &dyn HasServices,
+    label: &LabelSelector,
+) -> Result<Vec<ServiceStatus>, Box<dyn std::error::Error>> {
+    let listed = docker
+        .list_services(Some(ListServicesOptions {
+            filters: label_filters(HashMap::new(), label),
+            status: Some(true),
+        }))
+        .await?;
+    let checked = listed.into_iter().filter_map(|service| {
+        let spec = service.spec?;
+        if !label.matches(spec.labels.as_ref()) {
+            return None;
+        }
+        let mode = spec.mode.unwrap_or_default();
+        if mode.replicated_job.is_some() || mode.global_job.is_some() {
+            return None;
+        }
+        let status = service.service_status.unwrap_or_default();
+        Some(ServiceStatus {
+            name: spec.name.or(service.id)?,
+            desired_replicas: status.desired_tasks.unwrap_or_default(),
+            running_replicas: status.running_tasks.unwrap_or_default(),
+            ..Default::default()
+        })
+    });
+    let now = SystemTime::now();
+    let services = future::join_all(checked.map(|mut service| async move {
+        let under_replicated = service.running_replicas < service.desired_replicas;
+        let tasks = match list_tasks(docker, &service.name).await {
+            Ok(tasks) => tasks,
+            Err(err) => {
+                warn!("Could not get the tasks of the service {}: {err}", service.name);
+                return under_replicated.then_some(service);
+            }
+        };
+        let task_status = if under_replicated {
+            latest_failed_task(tasks)
+        } else {
+            Some(stuck_task(tasks, now)?)
+        };
+        if let Some(task_status) = task_status {
+            service.task_state = task_status.state.map(|state| state.to_string());
+            service.task_error = task_status.err.or(task_status.message);
+        }
+        Some(service)
+    }))
+    .await;
+    Ok(services.into_iter().flatten().collect())
+}
+
+async fn list_tasks(docker: &dyn HasServices, service_name: &str) -> Result<Vec<Task>, Error> {
+    docker
+        .list_tasks(Some(ListTasksOptions {
+            filters: filters(hashmap!["service" => vec![service_name]]),
+        }))
+        .await
+}
+
+/// The status of the most recently updated task of the service that is pending, or was rejected or failed.
+fn latest_failed_task(tasks: Vec<Task>) -> Option<TaskStatus> {
+    tasks
+        .into_iter()
+        .filter(|task| {
+            matches!(
+                task.status.as_ref().and_then(|status| status.state),
+                Some(TaskState::PENDING | TaskState::REJECTED | TaskState::FAILED)
+            )
+        })
+        .max_by_key(|task| task.version.as_ref().and_then(|version| version.index))
+        .and_then(|task| task.status)
+}
+
+/// The status of the most recently updated task of the service when it and the ones updated right before it are
+/// pending or rejected, and the first of them has been for longer than the grace period. The scheduler keeps
+/// replacing the rejected tasks, so the latest one alone is always recent.
+fn stuck_task(mut tasks: Vec<Task>, now: SystemTime) -> Option<TaskStatus> {
+    tasks.sort_by_key(|task| Reverse(task.version.as_ref().and_then(|version| version.index)));
+    let stuck = tasks
+        .into_iter()
+        .map_while(|task| {
+            task.status
+                .filter(|status| matches!(status.state, Some(TaskState::PENDING | TaskState::REJECTED)))
+        })
+        .collect::<Vec<_>>();
+    let since = stuck
+        .iter()
+        .filter_map(|status| humantime::parse_rfc3339_weak(status.timestamp.as_deref()?).ok())
+        .min()?;
+    if now.duration_since(since).unwrap_or_default() < STUCK_TASKS_GRACE_PERIOD {
+        return None;
+    }
+    stuck.into_iter().next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bollard::models::{
+        ObjectVersion, ServiceServiceStatus, ServiceSpec, ServiceSpecMode, ServiceSpecModeReplicatedJob,
+    };
+    use pretty_assertions::assert_eq;
+
+    fn service(name: &str, running: u64, desired: u64) -> Service {
+        Service {
+            spec: Some(ServiceSpec {
+                name: Some(name.to_owned()),
+                labels: Some(hashmap!["monitor".to_owned() => "".to_owned()]),
+                ..Default::default()
+            }),
+            service_status: Some(ServiceServiceStatus {
+                running_tasks: Some(running),
+                desired_tasks: Some(desired),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn task(index: u64, state: TaskState, message: &str, err: Option<&str>) -> Task {
+        Task {
+            version: Some(ObjectVersion { index: Some(index) }),
+            status: Some(TaskStatus {
+                state: Some(state),
+                err: err.map(str::to_owned),
+                message: Some(message.to_owned()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn under_replicated_services_are_reported_with_the_task_error() {
+        let mut has_services_mock = MockHasServices::new();
+        has_services_mock
+            .expect_list_services()
+            .withf(|options| {
+                let opt = options.as_ref().unwrap();
+                opt.status == Some(true) && opt.filters == filters(hashmap!["label" => vec!["monitor"]])
+            })
+            .times(1)
+            .returning(|_| {
+                let mut job = service("migrate", 0, 1);
+                job.spec.as_mut().unwrap().mode = Some(ServiceSpecMode {
+                    replicated_job: Some(ServiceSpecModeReplicatedJob::default()),
+                    ..Default::default()
+                });
+                Ok(vec![
+                    service("web", 1, 3),
+                    service("db", 1, 1),
+                    service("cache", 0, 1),
+                    job,
+                ])
+            });
+        has_services_mock
+            .expect_list_tasks()
+            .withf(|options| options.as_ref().unwrap().filters == filters(hashmap!["service" => vec!["web"]]))
+            .times(1)
+            .returning(|_| {
+                Ok(vec![
+                    task(10, TaskState::RUNNING, "started", None),
+                    task(
+                        12,
+                        TaskState::REJECTED,
+                        "pending task scheduling",
+                        Some("no suitable node (insufficient resources on 3 nodes)"),
+                    ),
+                    task(11, TaskState::FAILED, "started", Some("task: non-zero exit (1)")),
+                    task(9, TaskState::SHUTDOWN, "shutdown", None),
+                ])
+            });
+        has_services_mock
+            .expect_list_tasks()
+            .withf(|options| options.as_ref().unwrap().filters == filters(hashmap!["service" => vec!["db"]]))
+            .times(1)
+            .returning(|_| Ok(vec![task(5, TaskState::RUNNING, "started", None)]));
+        has_services_mock
+            .expect_list_tasks()
+            .withf(|options| options.as_ref().unwrap().filters == filters(hashmap!["service" => vec!["cache"]]))
+            .times(1)
+            .returning(|_| Ok(vec![task(3, TaskState::PENDING, "pending task scheduling", None)]));
+        assert_eq!(
+            check_services(&has_services_mock, &"monitor".parse().unwrap())
+                .await
+                .unwrap(),
+            vec![
+                ServiceStatus {
+                    name: "web".to_owned(),
+                    desired_replicas: 3,
+                    running_replicas: 1,
+                    task_state: Some("rejected".to_owned()),
+                    task_error: Some("no suitable node (insufficient resources on 3 nodes)".to_owned()),
+                    host: None,
+                },
+                ServiceStatus {
+                    name: "cache".to_owned(),
+                    desired_replicas: 1,
+                    running_replicas: 0,
+                    task_state: Some("pending".to_owned()),
+                    task_error: Some("pending task scheduling".to_owned()),
+                    host: None,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn stuck_rolling_updates_are_reported() {
+        let at = |task: Task, timestamp: SystemTime| Task {
+            status: task.status.map(|status| TaskStatus {
+                timestamp: Some(humantime::format_rfc3339_nanos(timestamp).to_string()),
+                ..status
+            }),
+            ..task
+        };
+        let now = SystemTime::now();
+        let mut has_services_mock = MockHasServices::new();
+        has_services_mock
+            .expect_list_services()
+            .times(1)
+            .returning(|_| Ok(vec![service("web", 3, 3), service("api", 2, 2)]));
+        has_services_mock
+            .expect_list_tasks()
+            .withf(|options| options.as_ref().unwrap().filters == filters(hashmap!["service" => vec!["web"]]))
+            .times(1)
+            .returning(move |_| {
+                Ok(vec![
+                    at(
+                        task(10, TaskState::RUNNING, "started", None),
+                        now - Duration::from_secs(3600),
+                    ),
+                    at(
+                        task(11, TaskState::REJECTED, "preparing", Some("No such image: web:2")),
+                        now - Duration::from_secs(600),
+                    ),
+                    at(
+                        task(13, TaskState::PENDING, "pending task scheduling", None),
+                        now - Duration::from_secs(10),
+                    ),
+                    at(
+                        task(12, TaskState::REJECTED, "preparing", Some("No such image: web:2")),
+                        now - Duration::from_secs(300),
+                    ),
+                ])
+            });
+        has_services_mock
+            .expect_list_tasks()
+            .withf(|options| options.as_ref().unwrap().filters == filters(hashmap!["service" => vec!["api"]]))
+            .times(1)
+            .returning(move |_| {
+                Ok(vec![
+                    at(
+                        task(10, TaskState::RUNNING, "started", None),
+                        now - Duration::from_secs(3600),
+                    ),
+                    at(
+                        task(11, TaskState::PENDING, "pending task scheduling", None),
+                        now - Duration::from_secs(60),
+                    ),
+                ])
+            });
+        assert_eq!(
+            check_services(&has_services_mock, &"monitor".parse().unwrap())
+                .await
+                .unwrap(),
+            vec![ServiceStatus {
+                name: "web".to_owned(),
+                desired_replicas: 3,
+                running_replicas: 3,
+                task_state: Some("pending".to_owned()),
+                task_error: Some("pending task scheduling".to_owned()),
+                host: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn summary() {
+        let mut service = ServiceStatus {
+            name: "web".to_owned(),
+            desired_replicas: 3,
+            running_replicas: 1,
+            ..Default::default()
+        };
+        assert_eq!(service.summary(), "1/3 replicas running");
+        service.task_state = Some("rejected".to_owned());
+        service.task_error = Some("no suitable node".to_owned());
+        assert_eq!(service.summary(), "1/3 replicas running, rejected: no suitable node");
+    }
+}
@@ -1,9 +1,14 @@
-use super::containers::{RunningContainerStatus, StoppedContainerStatus};
+use super::containers::{
+    ResolvedContainerStatus, ResolvedHost, RunningContainerStatus, StoppedContainerStatus, UnreachableHost,
+};
+use super::notifier::Notification;
+use super::swarm::ServiceStatus;
 use isahc::{Body, Error, HttpClient, Request, Response};
 use log::*;
 #[cfg(test)]
 use mockall::automock;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::io::Read;
 
 pub struct MyHttpClient {
@@ -11,7 +16,7 @@ pub struct MyHttpClient {
 }
 
 #[cfg_attr(test, automock)]
-trait SendsHttp {
+pub(crate) trait SendsHttp {
     fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Body>, Error>;
 }
 impl SendsHttp for MyHttpClient {
@@ -20,11 +25,8 @@ impl SendsHttp for MyHttpClient {
     }
 }
 
-pub type FormatMessageType = fn(
-    running_containers: &[RunningContainerStatus],
-    stopped_containers: &[StoppedContainerStatus],
-    hostname: Option<String>,
-) -> Result<Vec<u8>, serde_json::Error>;
+/// Formats the bodies to be posted, one request is sent for each of them, in order.
+pub type FormatMessageType = fn(notification: &Notification) -> Result<Vec<Vec<u8>>, serde_json::Error>;
 
 pub struct Webhook {
     http_client: Box<dyn SendsHttp + Sync>,
@@ -52,48 +54,90 @@ impl Webhook {
         }
     }
 
-    pub fn notify(
-        &self,
-        url: &str,
-        running_containers: Vec<RunningContainerStatus>,
-        stopped_containers: Vec<StoppedContainerStatus>,
-        hostname: Option<String>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        if (running_containers.len() + stopped_containers.len()) == 0 {
+    pub fn notify(&self, url: &str, notification: &Notification) -> Result<(), Box<dyn std::error::Error>> {
+        if notification.is_empty() {
             return Ok(());
         }
-        let body_bytes = if let Some(message_formatter) = &self.message_formatter {
-            message_formatter(&running_containers, &stopped_containers, hostname)?
+        let bodies = if let Some(message_formatter) = &self.message_formatter {
+            message_formatter(notification)?
         } else {
-            serde_json::to_vec(
-                &(WebHookNotifyBody {
-                    running_containers,
-                    stopped_containers,
-                    hostname,
-                }),
-            )?
+            vec![serde_json::to_vec(&WebHookNotifyBody::new(notification))?]
         };
+        for body_bytes in bodies {
+            self.post(url, body_bytes)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn post(&self, url: &str, body_bytes: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
         let req = Request::post(url)
             .header("content-type", "application/json")
             .body(body_bytes)?;
-        let mut res = self.http_client.send(req)?;
-        let mut body = String::new();
-        res.body_mut().read_to_string(&mut body)?;
-        if !res.status().is_success() {
-            return Err(format!("Error: status code: {status}. Body: {body}", status = res.status()).into());
-        } else {
-            info!("Response: status code: {status}. Body: {body}", status = res.status());
-        }
-        Ok(())
+        send(self.http_client.as_ref(), req)
+    }
+}
+
+/// Sends the request, failing when the response status is not a success.
+pub(crate) fn send(http_client: &dyn SendsHttp, req: Request<Vec<u8>>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut res = http_client.send(req)?;
+    let mut body = String::new();
+    res.body_mut().read_to_string(&mut body)?;
+    if !res.status().is_success() {
+        return Err(format!("Error: status code: {status}. Body: {body}", status = res.status()).into());
+    } else {
+        info!("Response: status code: {status}. Body: {body}", status = res.status());
     }
+    Ok(())
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Default)]
 pub struct WebHookNotifyBody {
     pub running_containers: Vec<RunningContainerStatus>,
     pub stopped_containers: Vec<StoppedContainerStatus>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub resolved_containers: Vec<ResolvedContainerStatus>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unreachable_hosts: Vec<UnreachableHost>,
+    /// The hosts that could not be checked before and can be again.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub resolved_hosts: Vec<ResolvedHost>,
+    /// The Swarm services that are missing replicas.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub services: Vec<ServiceStatus>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub hostname: Option<String>,
+    /// The names of the running and stopped containers of each Docker Compose project, so a broken stack stands out.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub projects: BTreeMap<String, Vec<String>>,
+}
+
+impl WebHookNotifyBody {
+    pub fn new(notification: &Notification) -> Self {
+        let mut projects: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let running = notification
+            .running_containers
+            .iter()
+            .map(|c| (c.project(), c.display_name()));
+        let stopped = notification
+            .stopped_containers
+            .iter()
+            .map(|c| (c.project(), c.display_name()));
+        for (project, name) in running.chain(stopped) {
+            if let Some(project) = project {
+                projects.entry(project.to_owned()).or_default().push(name);
+            }
+        }
+        WebHookNotifyBody {
+            running_containers: notification.running_containers.clone(),
+            stopped_containers: notification.stopped_containers.clone(),
+            resolved_containers: notification.resolved_containers.clone(),
+            unreachable_hosts: notification.unreachable_hosts.clone(),
+            resolved_hosts: notification.resolved_hosts.clone(),
+            services: notification.services.clone(),
+            hostname: notification.hostname.clone(),
+            projects,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -109,10 +153,12 @@ mod tests {
         let running_containers = vec![RunningContainerStatus {
             name: "test1".to_string(),
             health: None,
+            ..Default::default()
         }];
         let stopped_containers = vec![StoppedContainerStatus {
             name: "test2".to_string(),
             status: Some("exited".to_string()),
+            ..Default::default()
         }];
         let rc = running_containers.clone();
         let sc = stopped_containers.clone();
@@ -125,7 +171,10 @@ mod tests {
                         == WebHookNotifyBody {
                             running_containers: rc.clone(),
                             stopped_containers: sc.clone(),
+                            resolved_containers: vec![],
+                            unreachable_hosts: vec![],
                             hostname: None,
+                            ..Default::default()
                         }
             })
             .times(1)
@@ -135,7 +184,14 @@ mod tests {
             message_formatter: None,
         };
         webhook
-            .notify(URL, running_containers, stopped_containers, None)
+            .notify(
+                URL,
+                &Notification {
+                    running_containers,
+                    stopped_containers,
+                    ..Default::default()
+                },
+            )
             .unwrap();
     }
 
@@ -147,15 +203,18 @@ mod tests {
             RunningContainerStatus {
                 name: "test1".to_string(),
                 health: None,
+                ..Default::default()
             },
             RunningContainerStatus {
                 name: "test3".to_string(),
                 health: Some(HealthStatusEnum::UNHEALTHY),
+                ..Default::default()
             },
         ];
         let stopped_containers = vec![StoppedContainerStatus {
             name: "test2".to_string(),
             status: Some("exited".to_string()),
+            ..Default::default()
         }];
         let rc = running_containers.clone();
         let sc = stopped_containers.clone();
@@ -168,7 +227,10 @@ mod tests {
                         == WebHookNotifyBody {
                             running_containers: rc.clone(),
                             stopped_containers: sc.clone(),
+                            resolved_containers: vec![],
+                            unreachable_hosts: vec![],
                             hostname: Some("myhostname".to_owned()),
+                            ..Default::default()
                         }
             })
             .times(1)
@@ -180,9 +242,12 @@ mod tests {
         webhook
             .notify(
                 URL,
-                running_containers,
-                stopped_containers,
-                Some("myhostname".to_owned()),
+                &Notification {
+                    running_containers,
+                    stopped_containers,
+                    hostname: Some("myhostname".to_owned()),
+                    ..Default::default()
+                },
             )
             .unwrap();
     }
@@ -198,7 +263,55 @@ mod tests {
             message_formatter: None,
         };
         webhook
-            .notify(URL, running_containers, stopped_containers, None)
+            .notify(
+                URL,
+                &Notification {
+                    running_containers,
+                    stopped_containers,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn resolved_containers_are_notified() {
+        let mut client = MockSendsHttp::new();
+        const URL: &str = "http://localhost:8080/";
+        let resolved_containers = vec![ResolvedContainerStatus {
+            name: "test1".to_string(),
+            previous_status: "stopped".to_string(),
+            down_for_seconds: 120,
+            ..Default::default()
+        }];
+        let resc = resolved_containers.clone();
+        client
+            .expect_send()
+            .withf(move |req| {
+                serde_json::from_slice::<WebHookNotifyBody>(req.body()).unwrap()
+                    == WebHookNotifyBody {
+                        running_containers: vec![],
+                        stopped_containers: vec![],
+                        resolved_containers: resc.clone(),
+                        unreachable_hosts: vec![],
+                        hostname: None,
+                        ..Default::default()
+                    }
+            })
+            .times(1)
+            .return_once(|_| Ok(Response::builder().status(200).body(Body::from("")).unwrap()));
+        let webhook = Webhook {
+            http_client: Box::new(client),
+            message_formatter: None,
+        };
+        webhook
+            .notify(
+                URL,
+                &Notification {
+                    resolved_containers,
+                    ..Default::default()
+                },
+            )
             .unwrap();
     }
 }
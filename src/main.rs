@@ -2,9 +2,11 @@ use log::error;
 
 #[tokio::main]
 async fn main() {
-    if let Err(err) = notifyhealth::run().await {
-        error!("{}", err);
-        std::process::exit(1);
+    match notifyhealth::run().await {
+        Ok(exit_code) => std::process::exit(exit_code),
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(1);
+        }
     }
-    std::process::exit(0);
 }
@@ -0,0 +1,47 @@
+use mockito::Matcher;
+use notifyhealth::{
+    alertmanager::AlertmanagerNotifier,
+    containers::{ResolvedContainerStatus, StoppedContainerStatus},
+    notifier::{Notification, Notifier},
+};
+use serde_json::json;
+use std::time::Duration;
+#[test]
+fn check_alertmanager_notify() {
+    let mut server = mockito::Server::new();
+    let mock = server
+        .mock("POST", "/api/v2/alerts")
+        .match_body(Matcher::AllOf(vec![
+            Matcher::PartialJson(json!([
+                {"labels": {"alertname": "ContainerNotRunning", "container": "test1", "host": "myhostname", "status": "stopped"}},
+                {"labels": {"alertname": "ContainerNotHealthy", "container": "test2", "host": "myhostname", "status": "running", "health": "unhealthy"}}
+            ])),
+            Matcher::Regex(r#""endsAt":"\d{4}-\d{2}-\d{2}T"#.to_owned()),
+        ]))
+        .match_header("content-type", "application/json")
+        .with_status(200)
+        .create();
+    let notifier = AlertmanagerNotifier::new(
+        "alertmanager".to_owned(),
+        &format!("{}/", server.url()),
+        Duration::from_secs(15 * 60),
+    );
+    notifier
+        .notify(&Notification {
+            stopped_containers: vec![StoppedContainerStatus {
+                name: "test1".to_string(),
+                status: Some("exited".to_string()),
+                ..Default::default()
+            }],
+            resolved_containers: vec![ResolvedContainerStatus {
+                name: "test2".to_string(),
+                previous_status: "unhealthy".to_string(),
+                down_for_seconds: 60,
+                ..Default::default()
+            }],
+            hostname: Some("myhostname".to_owned()),
+            ..Default::default()
+        })
+        .unwrap();
+    mock.assert();
+}
@@ -0,0 +1,45 @@
+use mockito::Matcher;
+use notifyhealth::{containers::StoppedContainerStatus, discord, notifier::Notification, webhook::Webhook};
+use serde_json::json;
+#[test]
+fn check_discord_notify_splits_messages() {
+    let webhook = Webhook::new(Some(discord::format_message));
+    let stopped_containers = (1..=251)
+        .map(|i| StoppedContainerStatus {
+            name: format!("test{i}"),
+            status: Some("exited".to_string()),
+            ..Default::default()
+        })
+        .collect();
+    let mut server = mockito::Server::new();
+    let url = server.url();
+    let first_mock = server
+        .mock("POST", "/")
+        .match_body(Matcher::PartialJson(
+            json!({"content": "**Problem in containers! 🤕**\nServer: `myhostname`."}),
+        ))
+        .match_header("content-type", "application/json")
+        .with_status(204)
+        .create();
+    let second_mock = server
+        .mock("POST", "/")
+        .match_body(Matcher::PartialJson(json!({"embeds": [{
+            "title": "The following containers are not running: (continued)",
+            "fields": [{"name": "test251", "value": "exited", "inline": true}]
+        }]})))
+        .match_header("content-type", "application/json")
+        .with_status(204)
+        .create();
+    webhook
+        .notify(
+            &url,
+            &Notification {
+                stopped_containers,
+                hostname: Some("myhostname".to_owned()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    first_mock.assert();
+    second_mock.assert();
+}
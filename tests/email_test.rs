@@ -0,0 +1,98 @@
+use notifyhealth::{
+    containers::StoppedContainerStatus,
+    email::{EmailNotifier, EmailSettings, SmtpSecurity},
+    notifier::{Notification, Notifier},
+};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread;
+
+struct ReceivedEmail {
+    recipients: Vec<String>,
+    data: String,
+}
+
+/// A minimal SMTP server that accepts a single email and returns what it received.
+fn smtp_sink() -> (u16, thread::JoinHandle<ReceivedEmail>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        let mut received = ReceivedEmail {
+            recipients: vec![],
+            data: String::new(),
+        };
+        writer.write_all(b"220 localhost ESMTP sink\r\n").unwrap();
+        let mut in_data = false;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 {
+                break;
+            }
+            if in_data {
+                if line == ".\r\n" {
+                    in_data = false;
+                    writer.write_all(b"250 OK\r\n").unwrap();
+                } else {
+                    received.data.push_str(&line);
+                }
+                continue;
+            }
+            let command = line.trim_end().to_uppercase();
+            if command.starts_with("EHLO") || command.starts_with("HELO") {
+                writer.write_all(b"250 localhost\r\n").unwrap();
+            } else if command.starts_with("RCPT TO:") {
+                received.recipients.push(line.trim_end()[8..].to_owned());
+                writer.write_all(b"250 OK\r\n").unwrap();
+            } else if command == "DATA" {
+                in_data = true;
+                writer.write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n").unwrap();
+            } else if command == "QUIT" {
+                writer.write_all(b"221 Bye\r\n").unwrap();
+                break;
+            } else {
+                writer.write_all(b"250 OK\r\n").unwrap();
+            }
+        }
+        received
+    });
+    (port, handle)
+}
+
+#[test]
+fn check_email_notify() {
+    let (port, sink) = smtp_sink();
+    let notifier = EmailNotifier::new(
+        "email".to_owned(),
+        EmailSettings {
+            smtp_host: "127.0.0.1".to_owned(),
+            smtp_port: Some(port),
+            smtp_security: SmtpSecurity::None,
+            smtp_username: None,
+            smtp_password: None,
+            from: "notifyhealth@example.com".to_owned(),
+            to: vec!["a@example.com".to_owned(), "b@example.com".to_owned()],
+        },
+    );
+    notifier
+        .notify(&Notification {
+            stopped_containers: vec![StoppedContainerStatus {
+                name: "test1".to_string(),
+                status: Some("exited".to_string()),
+                ..Default::default()
+            }],
+            hostname: Some("myhostname".to_owned()),
+            ..Default::default()
+        })
+        .unwrap();
+    let received = sink.join().unwrap();
+    assert_eq!(received.recipients, vec!["<a@example.com>", "<b@example.com>"]);
+    assert!(received.data.contains("Subject: Problem in containers on myhostname"));
+    assert!(received.data.contains("Content-Type: multipart/alternative"));
+    assert!(received.data.contains("Content-Type: text/plain"));
+    assert!(received.data.contains("- test1 (exited)"));
+    assert!(received.data.contains("Content-Type: text/html"));
+    assert!(received.data.contains("<li>test1 (exited)</li>"));
+}
@@ -0,0 +1,59 @@
+use mockito::Matcher;
+use notifyhealth::{
+    containers::{ResolvedContainerStatus, StoppedContainerStatus},
+    notifier::{Notification, Notifier},
+    pagerduty::{PagerDutyNotifier, PagerDutySettings},
+};
+use serde_json::json;
+#[test]
+fn check_pagerduty_trigger_and_resolve() {
+    let mut server = mockito::Server::new();
+    let trigger = server
+        .mock("POST", "/v2/enqueue")
+        .match_body(Matcher::PartialJson(json!({
+            "routing_key": "key",
+            "event_action": "trigger",
+            "dedup_key": "notifyhealth/myhostname/test1",
+            "payload": {"severity": "critical", "source": "myhostname", "component": "test1"}
+        })))
+        .match_header("content-type", "application/json")
+        .with_status(202)
+        .with_body(r#"{"status":"success"}"#)
+        .create();
+    let resolve = server
+        .mock("POST", "/v2/enqueue")
+        .match_body(Matcher::Json(json!({
+            "routing_key": "key",
+            "event_action": "resolve",
+            "dedup_key": "notifyhealth/myhostname/test2"
+        })))
+        .with_status(202)
+        .with_body(r#"{"status":"success"}"#)
+        .create();
+    let notifier = PagerDutyNotifier::new(
+        "pagerduty".to_owned(),
+        PagerDutySettings {
+            routing_key: "key".to_owned(),
+            events_url: format!("{}/v2/enqueue", server.url()),
+        },
+    );
+    notifier
+        .notify(&Notification {
+            stopped_containers: vec![StoppedContainerStatus {
+                name: "test1".to_string(),
+                status: Some("exited".to_string()),
+                ..Default::default()
+            }],
+            resolved_containers: vec![ResolvedContainerStatus {
+                name: "test2".to_string(),
+                previous_status: "unhealthy".to_string(),
+                down_for_seconds: 60,
+                ..Default::default()
+            }],
+            hostname: Some("myhostname".to_owned()),
+            ..Default::default()
+        })
+        .unwrap();
+    trigger.assert();
+    resolve.assert();
+}
@@ -0,0 +1,110 @@
+use bollard::models::HealthStatusEnum;
+use bollard::{Docker, API_DEFAULT_VERSION};
+use mockito::{Matcher, Server, ServerGuard};
+use notifyhealth::containers::{
+    check_not_running_containers, check_running_containers, Containers, Engine, HasContainers, RunningContainerStatus,
+    StoppedContainerStatus,
+};
+
+/// Serves hand-written responses shaped like the ones of the Docker-compatible API of Podman 4.6, like its empty health
+/// status, not recorded from a running Podman.
+async fn podman_server() -> ServerGuard {
+    let mut server = Server::new_async().await;
+    server
+        .mock("GET", "/version")
+        .with_body(include_str!("fixtures/podman/version.json"))
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/containers/json")
+        .match_query(Matcher::UrlEncoded(
+            "filters".to_owned(),
+            r#"{"status":["running"]}"#.to_owned(),
+        ))
+        .with_body(include_str!("fixtures/podman/containers_running.json"))
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/containers/json")
+        .match_query(Matcher::Regex("stopped".to_owned()))
+        .with_body(include_str!("fixtures/podman/containers_stopped.json"))
+        .create_async()
+        .await;
+    for (name, body) in [
+        ("web", include_str!("fixtures/podman/inspect_web.json")),
+        ("db", include_str!("fixtures/podman/inspect_db.json")),
+        ("cache", include_str!("fixtures/podman/inspect_cache.json")),
+        ("worker", include_str!("fixtures/podman/inspect_worker.json")),
+    ] {
+        server
+            .mock("GET", format!("/containers/{name}/json").as_str())
+            .with_body(body)
+            .create_async()
+            .await;
+    }
+    server
+}
+
+async fn podman_containers(server: &ServerGuard) -> Containers {
+    let docker = Docker::connect_with_http(&server.host_with_port(), 5, API_DEFAULT_VERSION).unwrap();
+    Containers::detect(docker).await.unwrap()
+}
+
+#[tokio::test]
+async fn podman_is_detected() {
+    let server = podman_server().await;
+    assert_eq!(podman_containers(&server).await.engine(), Engine::Podman);
+}
+
+#[tokio::test]
+async fn podman_running_containers() {
+    let server = podman_server().await;
+    let containers = podman_containers(&server).await;
+    assert_eq!(
+        check_running_containers(&containers, &"!notifyhealth.ignore".parse().unwrap(), true)
+            .await
+            .unwrap(),
+        vec![
+            RunningContainerStatus {
+                name: "web".to_owned(),
+                health: Some(HealthStatusEnum::UNHEALTHY),
+                ..Default::default()
+            },
+            RunningContainerStatus {
+                name: "cache".to_owned(),
+                health: None,
+                ..Default::default()
+            },
+        ]
+    );
+    assert_eq!(
+        check_running_containers(&containers, &"!notifyhealth.ignore".parse().unwrap(), false)
+            .await
+            .unwrap(),
+        vec![RunningContainerStatus {
+            name: "web".to_owned(),
+            health: Some(HealthStatusEnum::UNHEALTHY),
+            ..Default::default()
+        }]
+    );
+}
+
+#[tokio::test]
+async fn podman_stopped_containers() {
+    let server = podman_server().await;
+    let containers = podman_containers(&server).await;
+    assert_eq!(
+        check_not_running_containers(&containers, &"autoheal".parse().unwrap())
+            .await
+            .unwrap(),
+        vec![StoppedContainerStatus {
+            name: "worker".to_owned(),
+            status: Some("exited".to_owned()),
+            exit_code: Some(1),
+            finished_at: Some("2023-10-11T08:50:00.987654321Z".to_owned()),
+            restart_count: Some(2),
+            image: Some("docker.io/library/python:3.12".to_owned()),
+            ..Default::default()
+        }]
+    );
+}
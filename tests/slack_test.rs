@@ -0,0 +1,39 @@
+use mockito::Matcher;
+use notifyhealth::{containers::StoppedContainerStatus, notifier::Notification, slack, webhook::Webhook};
+use serde_json::json;
+#[test]
+fn check_slack_notify() {
+    let webhook = Webhook::new(Some(slack::format_message));
+    let stopped_containers = vec![StoppedContainerStatus {
+        name: "test1".to_string(),
+        status: Some("exited".to_string()),
+        ..Default::default()
+    }];
+    let mut server = mockito::Server::new();
+    let url = server.url();
+    let mock = server
+        .mock("POST", "/")
+        .match_body(Matcher::PartialJson(json!({
+            "text": "Problem in containers! 🤕",
+            "blocks": [
+                {"type": "header", "text": {"type": "plain_text", "text": "Problem in containers! 🤕"}},
+                {"type": "section", "text": {"type": "mrkdwn", "text": "Server: `myhostname`."}}
+            ],
+            "attachments": [{"color": "#e01e5a"}]
+        })))
+        .match_header("content-type", "application/json")
+        .with_status(200)
+        .with_body("ok")
+        .create();
+    webhook
+        .notify(
+            &url,
+            &Notification {
+                stopped_containers,
+                hostname: Some("myhostname".to_owned()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    mock.assert();
+}
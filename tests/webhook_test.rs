@@ -1,6 +1,7 @@
 use mockito::Matcher;
 use notifyhealth::{
     containers::{RunningContainerStatus, StoppedContainerStatus},
+    notifier::Notification,
     webhook::Webhook,
 };
 use serde_json::json;
@@ -10,20 +11,30 @@ async fn check_webhook_notify() {
     let running_containers = vec![RunningContainerStatus {
         name: "test1".to_string(),
         health: None,
+        ..Default::default()
     }];
     let stopped_containers = vec![StoppedContainerStatus {
         name: "test2".to_string(),
         status: Some("exited".to_string()),
+        ..Default::default()
     }];
-    let mut server = mockito::Server::new();
+    let mut server = mockito::Server::new_async().await;
     let url = server.url();
     let mock = server.mock("POST", "/")
         .match_body(Matcher::Json(json!({"running_containers": [{"name": "test1"}], "stopped_containers": [{"name": "test2", "status": "exited"}]})))
         .match_header("content-type", "application/json")
         .with_status(201)
-        .create();
+        .create_async()
+        .await;
     webhook
-        .notify(&url, running_containers, stopped_containers, None)
+        .notify(
+            &url,
+            &Notification {
+                running_containers,
+                stopped_containers,
+                ..Default::default()
+            },
+        )
         .unwrap();
-    mock.assert();
+    mock.assert_async().await;
 }